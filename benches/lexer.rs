@@ -0,0 +1,83 @@
+/*!
+ * Lexer benchmarks
+ * ================
+ *
+ * These set `Bencher::bytes`, so `cargo bench` reports lexer throughput in MB/s alongside the
+ * time per iteration.
+ */
+#![feature(test)]
+
+extern crate cc;
+extern crate test;
+
+use test::Bencher;
+
+/**
+ * A chunk of ordinary-looking C. The benchmarks repeat it until they have about a megabyte of
+ * source, which is roughly the size of the generated files that made the old lexer slow.
+ */
+static SAMPLE: &'static str = r##"
+/*
+ * A block comment, like the ones at the top of every function.
+ */
+static const unsigned long table_size = 0x1000UL;
+
+int lookup_entry(int *table, int key, char *name) {
+    int index = (key * 2654435761u) % 4096;
+    double load = 0.75e0;
+
+    // walk the probe sequence
+    while (table[index] != 0 && table[index] != key) {
+        index = (index + 1) & 4095;
+        load += 1.0 / 3.0;
+    }
+
+    if (name[0] == '\0' || name[0] == '\n') {
+        write_int(1, index);
+        return -1;
+    }
+
+    printf("found %s at %d\n", name, index);
+    return index <= 0 ? index : table[index] >> 2;
+}
+"##;
+
+fn generated_source() -> String {
+    let copies = 1024 * 1024 / SAMPLE.len();
+    let mut source = String::with_capacity(copies * SAMPLE.len());
+    for _ in 0..copies {
+        source.push_str(SAMPLE);
+    }
+    source
+}
+
+#[bench]
+fn lex_generated_source(b: &mut Bencher) {
+    let source = generated_source();
+    b.bytes = source.len() as u64;
+    b.iter(|| cc::parser::lexer::lex(&source).unwrap());
+}
+
+#[bench]
+fn lex_identifiers(b: &mut Bencher) {
+    // Mostly distinct identifiers, which is the worst case for the symbol table.
+    let mut source = String::new();
+    let mut i = 0;
+    while source.len() < 1024 * 1024 {
+        source.push_str(&format!("identifier_number_{} = other_{} + {};\n", i, i % 97, i));
+        i += 1;
+    }
+    b.bytes = source.len() as u64;
+    b.iter(|| cc::parser::lexer::lex(&source).unwrap());
+}
+
+#[bench]
+fn lex_strings_and_comments(b: &mut Bencher) {
+    let mut source = String::new();
+    while source.len() < 1024 * 1024 {
+        source.push_str("/* a fairly long comment that the lexer has to skip over */\n");
+        source.push_str("log(\"a string literal with an escape\\n\", L\"and a wide one\");\n");
+    }
+    b.bytes = source.len() as u64;
+    b.iter(|| cc::parser::lexer::lex(&source).unwrap());
+}
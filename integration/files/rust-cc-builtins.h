@@ -8,6 +8,13 @@
 #ifndef RUST_CC_BUILTINS_H_
 #define RUST_CC_BUILTINS_H_
 
+#ifdef __RUST_CC__
+
+// rust-cc has the real thing built in.
+#include <rust-cc-builtins.h>
+
+#else
+
 #include <stdio.h>
 
 // write_int will be a compiler builtin for us, but in standard C, we'd have to
@@ -16,5 +23,6 @@
 #define write_int(file_descriptor, integer) \
 	dprintf(file_descriptor, "%d\n", integer)
 
-#endif // RUST_CC_BUILTINS_H_
+#endif // __RUST_CC__
 
+#endif // RUST_CC_BUILTINS_H_
@@ -22,15 +22,27 @@
 
 #[derive(Clone,Debug,PartialEq)]
 pub enum BinaryOp {
-    // These are listed in order of precedence.
+    // These are listed in order of precedence, loosest first.
     //
-    // The deepest level of the tree comes last.
+    // The deepest level of the tree comes last. All of them are left-associative except for the
+    // assignments, which are right-associative. The conditional operator `?:` (which isn't a
+    // BinaryOp, see `Expression::Conditional`) comes between the assignments and `||`.
+    Comma,
     Assign,
-    Add, Subtract,
-    Multiply, Divide,
-    Modulo,
-    And,
+    AddAssign, SubtractAssign,
+    MultiplyAssign, DivideAssign, ModuloAssign,
+    ShiftLeftAssign, ShiftRightAssign,
+    BitwiseAndAssign, BitwiseXorAssign, BitwiseOrAssign,
     Or,
+    And,
+    BitwiseOr,
+    BitwiseXor,
+    BitwiseAnd,
+    Equal, NotEqual,
+    Less, LessEqual, Greater, GreaterEqual,
+    ShiftLeft, ShiftRight,
+    Add, Subtract,
+    Multiply, Divide, Modulo,
 }
 
 #[derive(Clone,Debug,PartialEq)]
@@ -40,19 +52,104 @@ pub enum UnaryOp {
     Negate,
     /// DontNegate represents a unary `+`. WTF does a unary `+` do?
     DontNegate,
+    /// Not is the logical `!`.
+    Not,
+    /// BitwiseNot is the one's complement operator `~`.
+    BitwiseNot,
+    PreIncrement,
+    PreDecrement,
+    PostIncrement,
+    PostDecrement,
 }
 
 /**
  * Number describes the possible **literals** that can occur as a result of lexing.
  *
- * Presently, we don't try to support literals like "10L" or "10.0f".
- * TODO: Implement those.
+ * Each literal records the C type it was given. For integers this follows the rules in C11
+ * 6.4.4.1: the type is the first one in a list (picked by the suffix and by whether the literal is
+ * decimal) that can represent the value. Integer literals are never negative; `-1` is a unary
+ * minus applied to the literal `1`.
  */
-// NOTE: changes to this enum require changes to `lex_number()`
-#[derive(Clone,Debug,PartialEq)]
+// NOTE: changes to this enum require changes to `lex_subroutines::number()`
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub enum Number {
-    Int(i64),
-    Float(f64),
+    Int(u64, IntType),
+    Float(f64, FloatType),
+}
+
+/**
+ * The C integer types an integer literal can have. rust-cc uses an LP64 data model: `int` is 32
+ * bits, and `long` and `long long` are both 64 bits.
+ */
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum IntType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl IntType {
+    /**
+     * Return the largest value representable by this type.
+     */
+    pub fn max_value(&self) -> u64 {
+        match *self {
+            IntType::Int => i32::max_value() as u64,
+            IntType::UnsignedInt => u32::max_value() as u64,
+            IntType::Long | IntType::LongLong => i64::max_value() as u64,
+            IntType::UnsignedLong | IntType::UnsignedLongLong => u64::max_value(),
+        }
+    }
+}
+
+/**
+ * The C floating point types a floating literal can have. `long double` is treated as a `double`.
+ */
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum FloatType {
+    Float,
+    Double,
+    LongDouble,
+}
+
+/**
+ * The encoding prefix of a string literal, which decides what its elements are.
+ */
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum Encoding {
+    Char,   // "..."   bytes of type char
+    Utf8,   // u8"..." bytes of type char, always UTF-8
+    Char16, // u"..."  UTF-16 code units of type char16_t
+    Char32, // U"..."  UTF-32 code units of type char32_t
+    Wide,   // L"..."  code units of type wchar_t, which is 32 bits for rust-cc
+}
+
+/**
+ * StringLiteral is the array a C string literal stands for: a sequence of code units (bytes for
+ * plain and `u8` strings) after escape sequences have been processed. C strings can hold any
+ * byte, including NUL and bytes that aren't valid UTF-8, so this is not a Rust String.
+ *
+ * `units` does not include the terminating NUL.
+ */
+#[derive(Clone,Debug,PartialEq,Eq,Hash)]
+pub struct StringLiteral {
+    pub encoding:   Encoding,
+    pub units:      Vec<u32>,
+}
+
+impl StringLiteral {
+    /**
+     * Build a plain (unprefixed) string literal holding the UTF-8 bytes of `s`.
+     */
+    pub fn from_str(s: &str) -> StringLiteral {
+        StringLiteral{
+            encoding:   Encoding::Char,
+            units:      s.bytes().map(|b| b as u32).collect(),
+        }
+    }
 }
 
 #[derive(Clone,Debug,PartialEq)]
@@ -62,17 +159,34 @@ pub enum Expression {
     // The deepest level of the tree comes last.
     UnaryOp(UnaryOp, Box<Expression>),
     BinaryOp(Box<Expression>, BinaryOp, Box<Expression>),
+    /// Conditional is `condition ? then : _else`.
+    Conditional{condition: Box<Expression>, then: Box<Expression>, _else: Box<Expression>},
+    /// MemberAccess is `a.b`. There's no separate node for `p->b`, which is parsed as `(*p).b`
+    /// since that's what it means (C11 6.5.2.3p4).
     MemberAccess{struct_name: Box<Expression>, field_name: String},
     ArrayIndex{array: Box<Expression>, index: Box<Expression>},
-    FunctionCall{name: String, args: Vec<Expression>},
+    /// FunctionCall calls whatever `function` evaluates to. Usually that's a `Variable` naming
+    /// the function, but it can be a function pointer, as in `handlers[i](x)`.
+    FunctionCall{function: Box<Expression>, args: Vec<Expression>},
+    /// Cast converts an expression to a type, as in `(unsigned char) c`.
+    Cast(Type, Box<Expression>),
+    /// Sizeof is the size in bytes of the type of an expression, as in `sizeof x`. The expression
+    /// isn't evaluated.
+    Sizeof(Box<Expression>),
+    /// SizeofType is the size in bytes of a type, as in `sizeof(int)`.
+    SizeofType(Type),
+    /// Alignof is the alignment in bytes of a type, as in `_Alignof(long)`.
+    Alignof(Type),
     Parenthetical(Box<Expression>),
-    Declaration(Declaration),
+    Declaration(Box<Declaration>),
     Variable(String),
 
     // these are literals
     Number(Number),
-    String(String),
-    Character(char),
+    String(StringLiteral),
+    /// A character constant like `'a'`. In C these have type `int`, so we store the value the
+    /// constant evaluates to rather than a Rust char.
+    Character(i32),
 }
 
 /**
@@ -83,32 +197,199 @@ pub enum Expression {
  * int **a[12]
  * ```
  *
+ * A function that's declared without being defined, like `void exit(int status);`, is also a
+ * Declaration, with a `Type::Function` type.
  */
 #[derive(Clone,Debug,PartialEq)]
 pub struct Declaration {
-    pub _type:  Type,
-    pub name:   String,
+    pub _type:          Type,
+    pub name:           String,
+    pub initializer:    Option<Initializer>, // the value after the `=`, if there is one
+}
+
+/**
+ * Initializer is the value a variable is declared with, like the `1` in `int a = 1;`. Arrays,
+ * structs and unions are initialized with a list in braces, which may be nested:
+ *
+ * ```c
+ * int matrix[2][2] = {{1, 0}, {0, 1}};
+ * struct point p = {.y = 2, .x = 1};
+ * int sparse[10] = {[3] = 7, 8};
+ * char greeting[] = "hello";
+ * ```
+ *
+ * A string literal initializing a char array is just an `Expression`; what it means depends on
+ * the type being initialized.
+ */
+#[derive(Clone,Debug,PartialEq)]
+pub enum Initializer {
+    Expression(Expression),
+    List(Vec<DesignatedInitializer>),
+}
+
+/**
+ * DesignatedInitializer is one item of an initializer list. Without any designators, it
+ * initializes the member or element after the previous one.
+ */
+#[derive(Clone,Debug,PartialEq)]
+pub struct DesignatedInitializer {
+    pub designators:    Vec<Designator>, // like the `.a[2]` in `.a[2] = 1`
+    pub initializer:    Initializer,
+}
+
+#[derive(Clone,Debug,PartialEq)]
+pub enum Designator {
+    Index(Expression),  // `[3]`
+    Field(String),      // `.x`
 }
 
 /**
  * Type represents a construct such as `unsigned int` or `const FILE*`. Note that Type does not
  * include a variable name. See Declaration for that.
+ *
+ * Types are built up the way C declarators build them, from a basic type outwards. In
+ *
+ * ```c
+ * char *(*argv)[];
+ * ```
+ *
+ * `argv` is a pointer to an array of pointers to char, which is a `Pointer` to an `Array` of
+ * `Pointer`s to the `Basic` type `char`.
  */
 #[derive(Clone,Debug,PartialEq)]
-pub struct Type {
-    pub base_name:      String,         // type name like `FILE` or `int`
-    pub modifiers:      Vec<String>,    // modifiers like "unsigned", "long", "const", etc.
-    pub length:         Option<usize>,  // for array declarations. `None` for simple variables.
-    pub pointer_levels: usize,          // 0=value, 1=pointer, 2=pointer pointer, etc.
+pub enum Type {
+    /// Basic is a type named by keywords, like `unsigned int`. The last keyword that names a type,
+    /// like `FILE` or `int`, is the `base_name`, and the rest, like "unsigned", "long", "const",
+    /// etc., are `modifiers`.
+    Basic{base_name: String, modifiers: Vec<String>},
+
+    /// Pointer is a pointer to `to`. The `qualifiers` are the ones after the `*`, so `p` in
+    /// `const int *volatile p` is a volatile pointer to a const int.
+    Pointer{to: Box<Type>, qualifiers: Vec<String>},
+
+    /// Array is an array of `of`. The length is left out of declarations like `int a[]`.
+    Array{of: Box<Type>, length: Option<Box<Expression>>},
+
+    /// Function is a function returning `returns`. Both `f()` and `f(void)` have no parameters.
+    /// A `variadic` function takes more arguments after its parameters, as in `f(int n, ...)`.
+    Function{returns: Box<Type>, parameters: Vec<Type>, variadic: bool},
+
+    /// Struct is a struct or union type, with `modifiers` like "const" and "static" that were
+    /// specified along with it.
+    Struct{_struct: Struct, modifiers: Vec<String>},
+}
+
+impl Type {
+    /**
+     * Return the structs and unions this type defines, including the ones defined inside them,
+     * innermost first. `struct outer { struct inner { int x; } in; } *p` defines `struct inner`
+     * and then `struct outer`.
+     */
+    pub fn struct_definitions(&self) -> Vec<&Struct> {
+        match *self {
+            Type::Basic{..} | Type::Function{..} => vec![],
+            Type::Pointer{to: ref _type, ..} | Type::Array{of: ref _type, ..} => _type.struct_definitions(),
+            Type::Struct{ref _struct, ..} if _struct.fields.is_some() => _struct.declarations(),
+            Type::Struct{..} => vec![],
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum StructKind {
+    Struct,
+    Union,
+}
+
+impl StructKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StructKind::Struct => "struct",
+            StructKind::Union => "union",
+        }
+    }
+}
+
+/**
+ * Struct is a struct or union, as named by a specifier like `struct point`, or defined by one like
+ *
+ * ```c
+ * union {
+ *     int i;
+ *     struct point p;
+ * }
+ * ```
+ *
+ * A struct without `fields` refers to one defined elsewhere by its tag. On its own, as in
+ * `struct list;`, it declares a struct that will be defined later.
+ */
+#[derive(Clone,Debug,PartialEq)]
+pub struct Struct {
+    pub kind:   StructKind,
+    pub tag:    Option<String>,                 // `point` in `struct point`, unless it's anonymous
+    pub fields: Option<Vec<Declaration>>,       // the members, none of which have initializers
+}
+
+impl Struct {
+    /**
+     * Return the structs and unions that declaring this one declares: any defined inside it,
+     * innermost first, and then this one.
+     */
+    pub fn declarations(&self) -> Vec<&Struct> {
+        let mut declarations: Vec<&Struct> = self.fields.iter()
+            .flat_map(|fields| fields.iter())
+            .flat_map(|field| field._type.struct_definitions())
+            .collect();
+        declarations.push(self);
+        declarations
+    }
+
+    /**
+     * Return the name of the struct as C would write it, like `struct point`.
+     */
+    pub fn name(&self) -> String {
+        match self.tag {
+            Some(ref tag) => format!("{} {}", self.kind.as_str(), tag),
+            None => format!("{} <anonymous>", self.kind.as_str()),
+        }
+    }
 }
 
 #[derive(Clone,Debug,PartialEq)]
 pub enum Statement {
-    Declaration(Declaration),
+    /// Declaration holds everything declared by one declaration, as in `int a, *b = &a;`.
+    Declaration(Vec<Declaration>),
+    /// Struct declares a struct or union without declaring any variables, as in
+    /// `struct point { int x, y; };`.
+    Struct(Struct),
     Expression(Expression),
-    Return(Expression),
+    /// Compound is a `{}` block. It starts a new scope: anything declared inside it can shadow
+    /// the names outside, and is gone after the closing brace.
+    Compound(Vec<Statement>),
+    /// Empty is a lone `;`, as in `while (*p++);`. A typedef in a function body leaves one behind
+    /// too, since it only names a type.
+    Empty,
+    Return(Option<Expression>),
     Continue,
     Break,
+
+    If{condition: Expression, then: Box<Statement>, _else: Option<Box<Statement>>},
+    While{condition: Expression, body: Box<Statement>},
+    DoWhile{body: Box<Statement>, condition: Expression},
+    /// Any of the three clauses of a `for` may be left out. `init` is either a `Declaration` or an
+    /// `Expression`; a variable declared there is only visible inside the loop.
+    For{
+        init:       Option<Box<Statement>>,
+        condition:  Option<Expression>,
+        step:       Option<Expression>,
+        body:       Box<Statement>,
+    },
+
+    Switch{value: Expression, body: Box<Statement>},
+    Case(Expression, Box<Statement>),
+    Default(Box<Statement>),
+    Labeled(String, Box<Statement>),
+    Goto(String),
 }
 
 #[derive(Clone,Debug,PartialEq)]
@@ -124,11 +405,12 @@ pub struct Program {
     pub globals:    Vec<Declaration>,
     pub functions:  Vec<Function>,
 
-    /*
-     * TODO: Implement:
-     *
-     * pub structs:    Vec<Struct>,
-     * pub typedefs:   Vec<Typedef>,
-     */
+    /// The structs and unions declared at file scope without declaring any variables. Structs
+    /// defined in the type of a global, as in `struct point { int x, y; } origin;`, are only in
+    /// that global's type.
+    pub structs:    Vec<Struct>,
+
+    // There are no typedefs here: the parser replaces each typedef name with the type it stands
+    // for, so the rest of the compiler never sees them.
 }
 
@@ -7,11 +7,526 @@
  * 1. All identifiers are defined before use.
  * 2. The variable types are valid during assignment and computation.
  * 3. Syntax is valid (no attempt to do something like "int c = int b".
+ *
+ * So far only the first of these is checked, along with the members of structs and unions. Names
+ * follow C's block scope: a `{}` block (and the body of a loop or `if`) starts a new scope, where a
+ * declaration can shadow a variable of the same name from outside. The declaration is forgotten
+ * again at the end of the block. Struct and union tags are scoped the same way, but apart from
+ * other names, so `struct point point;` is fine.
+ */
+
+use ast::{BinaryOp, Declaration, Designator, Expression, Function, Initializer, Number, Program, Statement,
+          Struct, Type, UnaryOp};
+
+/**
+ * Scope holds what's declared in one scope: the variables and functions with their types, and
+ * the structs and unions with tags.
+ */
+#[derive(Default)]
+struct Scope {
+    names:      Vec<(String, Type)>,
+    structs:    Vec<Struct>,
+}
+
+/**
+ * Scopes is the stack of scopes we're inside while walking a function, innermost last. The first
+ * scope is file scope, which holds the global variables and the functions.
+ */
+struct Scopes {
+    scopes: Vec<Scope>,
+}
+
+impl Scopes {
+    /**
+     * Find the variable or function `name`, looking outward from the innermost scope, and return
+     * its type.
+     */
+    fn lookup(&self, name: &str) -> Option<&Type> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&(_, ref _type)) = scope.names.iter().rev().find(|&&(ref n, _)| n == name) {
+                return Some(_type);
+            }
+        }
+        None
+    }
+
+    /**
+     * Return the members of `_struct`, looking up its tag if it doesn't list them itself. Returns
+     * None if the struct hasn't been defined, or at least not yet.
+     */
+    fn fields<'s>(&'s self, _struct: &'s Struct) -> Option<&'s [Declaration]> {
+        if let Some(ref fields) = _struct.fields {
+            return Some(fields);
+        }
+        for scope in self.scopes.iter().rev() {
+            if let Some(found) = scope.structs.iter().find(|s| s.tag == _struct.tag) {
+                return found.fields.as_ref().map(|fields| &fields[..]);
+            }
+        }
+        None
+    }
+
+    /**
+     * Check that a variable `name` can be declared with type `_type`, which it can't if it's a
+     * struct that hasn't been defined. An `extern` variable can be, since it's defined elsewhere.
+     */
+    fn check_complete(&self, name: &str, _type: &Type) -> Result<(), String> {
+        match *_type {
+            Type::Struct{ref _struct, ref modifiers} if !modifiers.iter().any(|m| m == "extern") => {
+                match self.fields(_struct) {
+                    Some(_) => Ok(()),
+                    None => Err(format!("'{}' has incomplete type '{}'", name, _struct.name())),
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * Declare the structs and unions that `_type` defines in the innermost scope.
+     */
+    fn define_structs(&mut self, _type: &Type) -> Result<(), String> {
+        for _struct in _type.struct_definitions() {
+            try!(self.declare_struct(_struct));
+        }
+        Ok(())
+    }
+
+    /**
+     * Declare `_struct` in the innermost scope, if it has a tag. A tag can be declared any number
+     * of times in a scope, but only defined once.
+     */
+    fn declare_struct(&mut self, _struct: &Struct) -> Result<(), String> {
+        if let Some(ref fields) = _struct.fields {
+            for (i, field) in fields.iter().enumerate() {
+                if fields[..i].iter().any(|f| f.name == field.name) {
+                    return Err(format!("duplicate member '{}' in '{}'", field.name, _struct.name()));
+                }
+                try!(self.check_complete(&field.name, &field._type));
+            }
+        }
+
+        let tag = match _struct.tag {
+            Some(ref tag) => tag,
+            None => return Ok(()),
+        };
+        let scope = self.scopes.last_mut().unwrap();
+        match scope.structs.iter().position(|s| s.tag.as_ref() == Some(tag)) {
+            Some(i) => {
+                if scope.structs[i].kind != _struct.kind {
+                    return Err(format!("'{}' defined as the wrong kind of tag", tag));
+                }
+                match (&scope.structs[i].fields, &_struct.fields) {
+                    (&Some(_), &Some(_)) => return Err(format!("redefinition of '{}'", _struct.name())),
+                    (&None, &Some(_)) => scope.structs[i] = _struct.clone(),
+                    _ => (),
+                }
+            },
+            None => scope.structs.push(_struct.clone()),
+        }
+        Ok(())
+    }
+
+    /**
+     * Declare `name` in the innermost scope. A name can only be declared once per scope, though
+     * it may shadow one from an enclosing scope.
+     */
+    fn declare(&mut self, declaration: &Declaration) -> Result<(), String> {
+        try!(self.define_structs(&declaration._type));
+        try!(self.check_complete(&declaration.name, &declaration._type));
+        {
+            let scope = self.scopes.last_mut().unwrap();
+            if scope.names.iter().any(|&(ref n, _)| *n == declaration.name) {
+                return Err(format!("redefinition of '{}'", declaration.name));
+            }
+            scope.names.push((declaration.name.clone(), declaration._type.clone()));
+        }
+
+        // The name is already in scope in its own initializer, as in `int x = sizeof x;`.
+        match declaration.initializer {
+            Some(ref initializer) => self.initializer(initializer),
+            None => Ok(()),
+        }
+    }
+
+    fn initializer(&mut self, initializer: &Initializer) -> Result<(), String> {
+        match *initializer {
+            Initializer::Expression(ref expr) => self.expression(expr),
+            Initializer::List(ref items) => {
+                for item in items {
+                    for designator in &item.designators {
+                        if let Designator::Index(ref index) = *designator {
+                            try!(self.expression(index));
+                        }
+                    }
+                    try!(self.initializer(&item.initializer));
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /**
+     * Check `statements` in a new scope of their own.
+     */
+    fn block(&mut self, statements: &[Statement]) -> Result<(), String> {
+        self.scopes.push(Scope::default());
+        let result = statements.iter().map(|s| self.statement(s)).collect();
+        self.scopes.pop();
+        result
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        match *statement {
+            Statement::Declaration(ref declarations) => declarations.iter().map(|d| self.declare(d)).collect(),
+            Statement::Struct(ref _struct) => _struct.declarations().into_iter().map(|s| self.declare_struct(s)).collect(),
+            Statement::Expression(ref expr) => self.expression(expr),
+            Statement::Compound(ref statements) => self.block(statements),
+            Statement::Return(Some(ref expr)) => self.expression(expr),
+            Statement::Empty | Statement::Return(None) | Statement::Continue | Statement::Break |
+            Statement::Goto(_) => Ok(()),
+
+            Statement::If{ref condition, ref then, ref _else} => {
+                try!(self.expression(condition));
+                try!(self.substatement(then));
+                match *_else {
+                    Some(ref _else) => self.substatement(_else),
+                    None => Ok(()),
+                }
+            },
+            Statement::While{ref condition, ref body} | Statement::DoWhile{ref body, ref condition} => {
+                try!(self.expression(condition));
+                self.substatement(body)
+            },
+            Statement::For{ref init, ref condition, ref step, ref body} => {
+                // The whole loop is a scope, so a variable declared in `init` is gone after it.
+                self.scopes.push(Scope::default());
+                let result = self.for_statement(init, condition, step, body);
+                self.scopes.pop();
+                result
+            },
+            Statement::Switch{ref value, ref body} => {
+                try!(self.expression(value));
+                self.substatement(body)
+            },
+
+            Statement::Case(ref value, ref statement) => {
+                try!(self.expression(value));
+                self.statement(statement)
+            },
+            Statement::Default(ref statement) | Statement::Labeled(_, ref statement) => {
+                self.statement(statement)
+            },
+        }
+    }
+
+    /**
+     * The body of a loop, `if` or `switch` is a scope of its own even if it isn't a `{}` block
+     * (C11 6.8.4p3 and 6.8.5p5).
+     */
+    fn substatement(&mut self, statement: &Statement) -> Result<(), String> {
+        match *statement {
+            Statement::Compound(ref statements) => self.block(statements),
+            _ => self.block(::std::slice::from_ref(statement)),
+        }
+    }
+
+    fn for_statement(&mut self, init: &Option<Box<Statement>>, condition: &Option<Expression>,
+                     step: &Option<Expression>, body: &Statement) -> Result<(), String> {
+        if let Some(ref init) = *init {
+            try!(self.statement(init));
+        }
+        for expr in condition.iter().chain(step.iter()) {
+            try!(self.expression(expr));
+        }
+        self.substatement(body)
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<(), String> {
+        self.type_of(expr).map(|_| ())
+    }
+
+    /**
+     * Check `expr`, and return its type if we can work it out. The types are only needed to find
+     * the members of structs for now, so they're rough: arithmetic gives the type of its left-hand
+     * side unless the other side is a pointer, and we don't know what undeclared functions return.
+     */
+    fn type_of(&mut self, expr: &Expression) -> Result<Option<Type>, String> {
+        let _type = match *expr {
+            Expression::Variable(ref name) => match self.lookup(name) {
+                Some(_type) => _type.clone(),
+                None => return Err(format!("use of undeclared identifier '{}'", name)),
+            },
+            Expression::UnaryOp(ref op, ref operand) => {
+                let operand = try!(self.type_of(operand));
+                match *op {
+                    UnaryOp::Dereference => return Ok(operand.and_then(|t| pointee(&t))),
+                    UnaryOp::Reference => match operand {
+                        Some(to) => Type::Pointer{to: box to, qualifiers: vec![]},
+                        None => return Ok(None),
+                    },
+                    UnaryOp::Not => int(),
+                    _ => return Ok(operand),
+                }
+            },
+            Expression::BinaryOp(ref lhs, ref op, ref rhs) => {
+                let lhs = try!(self.type_of(lhs));
+                let rhs = try!(self.type_of(rhs));
+                let is_pointer = |t: &Option<Type>| t.as_ref().and_then(pointee).is_some();
+                match *op {
+                    BinaryOp::Comma => return Ok(rhs),
+                    BinaryOp::Add if is_pointer(&rhs) && !is_pointer(&lhs) => return Ok(rhs),
+                    BinaryOp::Subtract if is_pointer(&lhs) && is_pointer(&rhs) => int(),
+                    BinaryOp::Add | BinaryOp::Subtract if is_pointer(&lhs) => {
+                        // An array decays to a pointer to its first element.
+                        let element = lhs.as_ref().and_then(pointee).unwrap();
+                        Type::Pointer{to: box element, qualifiers: vec![]}
+                    },
+                    BinaryOp::Or | BinaryOp::And | BinaryOp::Equal | BinaryOp::NotEqual |
+                    BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => int(),
+                    _ => return Ok(lhs),
+                }
+            },
+            Expression::Conditional{ref condition, ref then, ref _else} => {
+                try!(self.expression(condition));
+                let then = try!(self.type_of(then));
+                try!(self.expression(_else));
+                return Ok(then);
+            },
+            Expression::MemberAccess{ref struct_name, ref field_name} => {
+                match try!(self.type_of(struct_name)) {
+                    Some(Type::Struct{ref _struct, ..}) => match self.fields(_struct) {
+                        Some(fields) => match fields.iter().find(|f| f.name == *field_name) {
+                            Some(field) => field._type.clone(),
+                            None => return Err(format!("no member named '{}' in '{}'", field_name, _struct.name())),
+                        },
+                        None => return Err(format!("incomplete definition of type '{}'", _struct.name())),
+                    },
+                    Some(_) => return Err(format!("request for member '{}' in something not a structure or union", field_name)),
+                    None => return Ok(None),
+                }
+            },
+            Expression::ArrayIndex{ref array, ref index} => {
+                let array = try!(self.type_of(array));
+                let index = try!(self.type_of(index));
+                // `a[i]` is `*(a + i)`, so `i[a]` works too.
+                return Ok(array.as_ref().and_then(pointee).or_else(|| index.as_ref().and_then(pointee)));
+            },
+            Expression::FunctionCall{ref function, ref args} => {
+                // Functions can be called by name without being declared, as in C89.
+                let function = match **function {
+                    Expression::Variable(ref name) => self.lookup(name).cloned(),
+                    ref function => try!(self.type_of(function)),
+                };
+                for arg in args {
+                    try!(self.expression(arg));
+                }
+                let function = match function {
+                    Some(Type::Pointer{to, ..}) => *to,
+                    Some(function) => function,
+                    None => return Ok(None),
+                };
+                match function {
+                    Type::Function{returns, ..} => *returns,
+                    _ => return Ok(None),
+                }
+            },
+            Expression::Cast(ref _type, ref operand) => {
+                try!(self.expression(operand));
+                _type.clone()
+            },
+            Expression::Sizeof(ref operand) => {
+                try!(self.expression(operand));
+                size_t()
+            },
+            Expression::SizeofType(ref _type) => {
+                try!(self.measure("sizeof", _type));
+                size_t()
+            },
+            Expression::Alignof(ref _type) => {
+                try!(self.measure("_Alignof", _type));
+                size_t()
+            },
+            Expression::Parenthetical(ref operand) => return self.type_of(operand),
+            Expression::Declaration(ref declaration) => {
+                try!(self.declare(declaration));
+                return Ok(None);
+            },
+            Expression::Number(Number::Int(..)) | Expression::Character(_) => int(),
+            Expression::Number(Number::Float(..)) => basic("double"),
+            Expression::String(_) => Type::Pointer{to: box basic("char"), qualifiers: vec![]},
+        };
+        Ok(Some(_type))
+    }
+
+    /**
+     * Check that `operator`, which is `sizeof` or `_Alignof`, can be applied to `_type`. It can't
+     * be applied to a struct that hasn't been defined, but it can define one, as in
+     * `sizeof(struct point { int x, y; })`.
+     */
+    fn measure(&mut self, operator: &str, _type: &Type) -> Result<(), String> {
+        try!(self.define_structs(_type));
+        match *_type {
+            Type::Struct{ref _struct, ..} if self.fields(_struct).is_none() => {
+                Err(format!("invalid application of '{}' to incomplete type '{}'", operator, _struct.name()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn function(&mut self, function: &Function) -> Result<(), String> {
+        // The parameters are in the same scope as the outermost block of the body, so the body
+        // can't redeclare them.
+        self.scopes.push(Scope::default());
+        let mut result: Result<(), String> = function.arguments.iter().map(|arg| self.declare(arg)).collect();
+        if result.is_ok() {
+            result = function.statements.iter().map(|s| self.statement(s)).collect();
+        }
+        self.scopes.pop();
+        result.map_err(|e| format!("in function '{}': {}", function.name, e))
+    }
+}
+
+/**
+ * Return the type `_type` points to. An array counts, since it decays to a pointer to its first
+ * element.
  */
+fn pointee(_type: &Type) -> Option<Type> {
+    match *_type {
+        Type::Pointer{ref to, ..} => Some((**to).clone()),
+        Type::Array{ref of, ..} => Some((**of).clone()),
+        _ => None,
+    }
+}
+
+fn basic(base_name: &str) -> Type {
+    Type::Basic{base_name: base_name.to_string(), modifiers: vec![]}
+}
+
+/**
+ * Return the type of `sizeof`, which is `size_t`: `unsigned long`, like in `<stddef.h>`.
+ */
+fn size_t() -> Type {
+    Type::Basic{base_name: "long".to_string(), modifiers: vec!["unsigned".to_string()]}
+}
+
+fn int() -> Type {
+    basic("int")
+}
+
+pub fn check_program(program: &Program) -> Result<(), String> {
+    // A global can be declared more than once (C11 6.9.2), so file scope isn't checked for
+    // redefinitions.
+    let mut file_scope = Scope::default();
+    file_scope.names = program.globals.iter().map(|g| (g.name.clone(), g._type.clone())).collect();
+    file_scope.names.extend(program.functions.iter().map(|f| (f.name.clone(), Type::Function{
+        returns:    box f.return_type.clone(),
+        parameters: f.arguments.iter().map(|arg| arg._type.clone()).collect(),
+        variadic:   false,
+    })));
 
-use ast;
+    // Structs can be used before they're defined at file scope, as long as they're defined by the
+    // end of the file.
+    let mut scopes = Scopes{scopes: vec![file_scope]};
+    for _struct in program.structs.iter().flat_map(|s| s.declarations()) {
+        try!(scopes.declare_struct(_struct));
+    }
+    for _type in program.globals.iter().map(|g| &g._type).chain(program.functions.iter().map(|f| &f.return_type)) {
+        try!(scopes.define_structs(_type));
+    }
 
-pub fn check_program(program: &ast::Program) -> Result<(), String> {
-    let _ = program;
+    for global in &program.globals {
+        try!(scopes.check_complete(&global.name, &global._type));
+        if let Some(ref initializer) = global.initializer {
+            try!(scopes.initializer(initializer).map_err(|e| format!("in the initializer of '{}': {}", global.name, e)));
+        }
+    }
+    for function in &program.functions {
+        try!(scopes.function(function));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::parse_str;
+
+    fn check(s: &str) -> Result<(), String> {
+        check_program(&parse_str(s).unwrap())
+    }
+
+    #[test]
+    fn identifiers_must_be_declared() {
+        assert_eq!(check("int g; int f(int a) { int b; return a + b + g; }"), Ok(()));
+        assert_eq!(check("int f() { return x; }"), Err("in function 'f': use of undeclared identifier 'x'".to_string()));
+        assert_eq!(check("int f() { x = 1; int x; }"), Err("in function 'f': use of undeclared identifier 'x'".to_string()));
+
+        // `sizeof` doesn't evaluate its operand, but it still has to make sense.
+        assert_eq!(check("int f() { int x = sizeof x; return sizeof(int) + _Alignof(long); }"), Ok(()));
+        assert_eq!(check("int f() { return sizeof y; }"), Err("in function 'f': use of undeclared identifier 'y'".to_string()));
+        assert_eq!(check("int f() { return sizeof(struct s); }"),
+                   Err("in function 'f': invalid application of 'sizeof' to incomplete type 'struct s'".to_string()));
+    }
+
+    #[test]
+    fn initializers() {
+        assert_eq!(check("int a = 1; int b = a; int f() { int c = {a, [b] = b, .x = {c}}; }"), Ok(()));
+        assert_eq!(check("int a = b; int b;"), Ok(()));
+        assert_eq!(check("int a = c;"), Err("in the initializer of 'a': use of undeclared identifier 'c'".to_string()));
+        assert_eq!(check("int f() { int a = {1, [x] = 2}; }"), Err("in function 'f': use of undeclared identifier 'x'".to_string()));
+    }
+
+    #[test]
+    fn block_scope() {
+        // Inner blocks can shadow outer names, and their names are gone after the block.
+        assert_eq!(check("int x; void f(int a) { int x; { int x; int a; { int a; } } }"), Ok(()));
+        assert_eq!(check("void f() { { int y; } y; }"), Err("in function 'f': use of undeclared identifier 'y'".to_string()));
+        assert_eq!(check("void f() { if (1) { int y; } else y; }"), Err("in function 'f': use of undeclared identifier 'y'".to_string()));
+
+        // The loop variable of a `for` is only visible inside the loop.
+        assert_eq!(check("void f() { for (int i; i; i) { int i; } int i; }"), Ok(()));
+        assert_eq!(check("void f() { for (int i; i; i) {} i; }"), Err("in function 'f': use of undeclared identifier 'i'".to_string()));
+
+        // But a name can't be declared twice in the same scope.
+        assert_eq!(check("void f() { int x; int x; }"), Err("in function 'f': redefinition of 'x'".to_string()));
+        assert_eq!(check("void f(int a) { int a; }"), Err("in function 'f': redefinition of 'a'".to_string()));
+        assert_eq!(check("void f() { int x = 1, y = x, x; }"), Err("in function 'f': redefinition of 'x'".to_string()));
+        assert_eq!(check("int x; int x; void f() { { int y; int y; } }"), Err("in function 'f': redefinition of 'y'".to_string()));
+    }
+
+    #[test]
+    fn struct_members() {
+        assert_eq!(check("struct point { int x, y; }; struct point origin; \
+                          int f(struct point *p) { struct point q = {1, 2}; return p->x + q.y + origin.x + (*p).y + p[1].x; }"), Ok(()));
+        assert_eq!(check("struct outer { struct inner { int a; } in; union { int i; char c; } u; } o; \
+                          int f() { struct inner i; return o.in.a + o.u.c + i.a; }"), Ok(()));
+        assert_eq!(check("struct node; struct node *head; struct node { int value; struct node *next; }; \
+                          struct node *first() { return head; } int f() { return head->next->value + first()->value; }"), Ok(()));
+        assert_eq!(check("struct point { int x; } point; int f() { return point.x; }"), Ok(()));
+
+        assert_eq!(check("struct point { int x; }; int f(struct point p) { return p.z; }"),
+                   Err("in function 'f': no member named 'z' in 'struct point'".to_string()));
+        assert_eq!(check("int f(int a) { return a.x; }"),
+                   Err("in function 'f': request for member 'x' in something not a structure or union".to_string()));
+        assert_eq!(check("struct s; int f(struct s *p) { return p->x; }"),
+                   Err("in function 'f': incomplete definition of type 'struct s'".to_string()));
+        assert_eq!(check("int f() { union { int i; } u; return u.i.x; }"),
+                   Err("in function 'f': request for member 'x' in something not a structure or union".to_string()));
+    }
+
+    #[test]
+    fn struct_declarations() {
+        assert_eq!(check("struct s; struct s x;"), Err("'x' has incomplete type 'struct s'".to_string()));
+        assert_eq!(check("struct s; extern struct s x;"), Ok(()));
+        assert_eq!(check("struct s { int x; struct t y; };"), Err("'y' has incomplete type 'struct t'".to_string()));
+        assert_eq!(check("struct point { int x; int x; };"), Err("duplicate member 'x' in 'struct point'".to_string()));
+        assert_eq!(check("struct p { int x; }; struct p { int y; };"), Err("redefinition of 'struct p'".to_string()));
+        assert_eq!(check("struct p { int x; }; union p;"), Err("'p' defined as the wrong kind of tag".to_string()));
+
+        // Tags are scoped like other names, so an inner struct can hide an outer one.
+        assert_eq!(check("struct s { int x; } a; int f() { struct s { int y; } b; return a.x + b.y; }"), Ok(()));
+        assert_eq!(check("int f() { { struct s { int x; } a; } struct s b; }"),
+                   Err("in function 'f': 'b' has incomplete type 'struct s'".to_string()));
+    }
+}
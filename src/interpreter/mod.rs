@@ -15,28 +15,1117 @@
  * 2. Easier debugging (because the Interpreter has more state information).
  * 3. More portable because we're not emitting any platform-specific assembly.
  *
+ * For now every value is an integer, held in an `i64`, or a struct or union of them. Pointers,
+ * arrays, strings and floating point numbers are reported as unsupported when the program tries to
+ * use them. The only library function is the `write_int` builtin from `<rust-cc-builtins.h>`.
+ * Since values don't remember their types, `sizeof` works on types but not on expressions.
  */
 
-use ast;
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+use ast::{BinaryOp, Declaration, DesignatedInitializer, Designator, Expression, Function, Initializer, Number,
+          Program, Statement, Struct, StructKind, Type, UnaryOp};
 use checker;
 
+/**
+ * Value is what a variable holds.
+ */
+#[derive(Clone,Debug)]
+enum Value {
+    Int(i64),
+    Struct(Vec<Member>),
+    /// Union holds every member of a union, and the index of the one that was stored to last.
+    Union(Vec<Member>, usize),
+}
+
+/**
+ * Member is one member of a struct or union value.
+ */
+#[derive(Clone,Debug)]
+struct Member {
+    name:   String,
+    _type:  Type,
+    value:  Value,
+}
+
+/**
+ * Scope holds the variables declared in one scope, and the structs and unions declared there by
+ * tag.
+ */
+#[derive(Default)]
+struct Scope {
+    variables:  HashMap<String, Value>,
+    structs:    HashMap<String, Struct>,
+}
+
+/**
+ * Flow is how a statement finished, which decides what runs next.
+ */
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/**
+ * Interpreter holds the state of a running program.
+ */
+struct Interpreter<'a, W: Write> {
+    functions:  HashMap<&'a str, &'a Function>,
+    globals:    Scope,
+
+    /// The block scopes of the function that's running, innermost last. Each call gets a new
+    /// stack, so a function can't see the locals of its caller.
+    scopes:     Vec<Scope>,
+
+    /// Where `write_int(1, ...)` goes.
+    stdout:     W,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    fn call(&mut self, name: &str, args: &[Expression]) -> Result<Value, String> {
+        let mut values = vec![];
+        for arg in args {
+            values.push(try!(self.value(arg)));
+        }
+
+        let function = match self.functions.get(name) {
+            Some(&function) => function,
+            None if name == "write_int" => {
+                let args: Result<Vec<i64>, String> = values.into_iter().map(int).collect();
+                return self.write_int(&try!(args)).map(Value::Int);
+            },
+            None => return Err(format!("call to undefined function '{}'", name)),
+        };
+        if values.len() != function.arguments.len() {
+            return Err(format!("function '{}' takes {} arguments, but was given {}",
+                               name, function.arguments.len(), values.len()));
+        }
+
+        let parameters = Scope{
+            variables:  function.arguments.iter().map(|arg| arg.name.clone()).zip(values).collect(),
+            structs:    HashMap::new(),
+        };
+        let caller_scopes = ::std::mem::replace(&mut self.scopes, vec![parameters]);
+        let result = self.statements(&function.statements);
+        self.scopes = caller_scopes;
+
+        match try!(result) {
+            Flow::Return(value) => Ok(value),
+            Flow::Next => Ok(Value::Int(0)),
+            Flow::Break | Flow::Continue => Err(format!("break or continue outside of a loop in '{}'", name)),
+        }
+    }
+
+    /**
+     * `void write_int(int file_descriptor, int integer)` writes `integer` and a newline to
+     * standard output or standard error.
+     */
+    fn write_int(&mut self, args: &[i64]) -> Result<i64, String> {
+        if args.len() != 2 {
+            return Err(format!("function 'write_int' takes 2 arguments, but was given {}", args.len()));
+        }
+        let integer = args[1] as i32;
+        let result = match args[0] {
+            1 => writeln!(self.stdout, "{}", integer),
+            2 => writeln!(io::stderr(), "{}", integer),
+            fd => return Err(format!("write_int can't write to file descriptor {}", fd)),
+        };
+        result.map(|_| 0).map_err(|e| e.to_string())
+    }
+
+    /**
+     * Return the innermost scope. That's file scope while the globals are being declared, before
+     * any function runs.
+     */
+    fn scope(&mut self) -> &mut Scope {
+        match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => &mut self.globals,
+        }
+    }
+
+    fn declare(&mut self, declaration: &Declaration) -> Result<(), String> {
+        for _struct in declaration._type.struct_definitions() {
+            self.declare_struct(_struct);
+        }
+
+        // The variable is already in scope in its own initializer.
+        self.scope().variables.insert(declaration.name.clone(), Value::Int(0));
+        let value = try!(self.initial_value(declaration));
+        self.scope().variables.insert(declaration.name.clone(), value);
+        Ok(())
+    }
+
+    /**
+     * Declare the struct or union `_struct` in the innermost scope. A declaration without members
+     * doesn't hide a definition that's already there.
+     */
+    fn declare_struct(&mut self, _struct: &Struct) {
+        let tag = match _struct.tag {
+            Some(ref tag) => tag,
+            None => return,
+        };
+        let scope = self.scope();
+        if _struct.fields.is_some() || !scope.structs.contains_key(tag) {
+            scope.structs.insert(tag.clone(), _struct.clone());
+        }
+    }
+
+    /**
+     * Return the members of `_struct`, looking up its tag if it doesn't list them itself.
+     */
+    fn fields<'s>(&'s self, _struct: &'s Struct) -> Result<&'s [Declaration], String> {
+        if let Some(ref fields) = _struct.fields {
+            return Ok(fields);
+        }
+        for scope in self.scopes.iter().rev().chain(::std::iter::once(&self.globals)) {
+            if let Some(found) = _struct.tag.as_ref().and_then(|tag| scope.structs.get(tag)) {
+                if let Some(ref fields) = found.fields {
+                    return Ok(fields);
+                }
+                break;
+            }
+        }
+        Err(format!("incomplete definition of type '{}'", _struct.name()))
+    }
+
+    /**
+     * Return the value a variable of type `_type` starts with if nothing initializes it, which is
+     * 0, or a struct or union of zeros. (C only promises that for globals, but it's as good a
+     * value as any.)
+     */
+    fn zero(&self, _type: &Type) -> Result<Value, String> {
+        match *_type {
+            Type::Array{..} => Err("the interpreter doesn't support arrays yet".to_string()),
+            Type::Struct{ref _struct, ..} => {
+                let mut members = vec![];
+                for field in try!(self.fields(_struct)) {
+                    members.push(Member{
+                        name:   field.name.clone(),
+                        _type:  field._type.clone(),
+                        value:  try!(self.zero(&field._type)),
+                    });
+                }
+                Ok(match _struct.kind {
+                    StructKind::Struct => Value::Struct(members),
+                    StructKind::Union => Value::Union(members, 0),
+                })
+            },
+            _ => Ok(Value::Int(0)),
+        }
+    }
+
+    /**
+     * Return the size and alignment of `_type` in bytes, as they are on x86-64. A struct lays its
+     * members out in order, each aligned, and a union overlaps them; either way the size is
+     * rounded up to a multiple of the alignment, so that arrays of them stay aligned.
+     */
+    fn layout(&mut self, _type: &Type) -> Result<(i64, i64), String> {
+        match *_type {
+            Type::Basic{ref base_name, ref modifiers} => {
+                let has = |modifier: &str| modifiers.iter().any(|m| m == modifier);
+                let size = match &base_name[..] {
+                    "_Bool" | "char" => 1,
+                    "short" => 2,
+                    "int" if has("short") => 2,
+                    "int" if has("long") => 8,
+                    "int" | "float" => 4,
+                    "long" => 8,
+                    "double" if has("long") => 16,
+                    "double" => 8,
+                    "void" => return Err("void has no size".to_string()),
+                    _ => return Err(format!("the interpreter doesn't support {} yet", base_name)),
+                };
+                Ok((size, size))
+            },
+            Type::Pointer{..} => Ok((8, 8)),
+            Type::Array{ref of, ref length} => {
+                let (size, alignment) = try!(self.layout(of));
+                match *length {
+                    Some(ref length) => Ok((size * try!(self.expression(length)), alignment)),
+                    None => Err("an array of unknown length has no size".to_string()),
+                }
+            },
+            Type::Function{..} => Err("a function has no size".to_string()),
+            Type::Struct{ref _struct, ..} => {
+                let fields = try!(self.fields(_struct)).to_vec();
+                let (mut size, mut alignment) = (0, 1);
+                for field in &fields {
+                    let (field_size, field_alignment) = try!(self.layout(&field._type));
+                    size = match _struct.kind {
+                        StructKind::Struct => round_up(size, field_alignment) + field_size,
+                        StructKind::Union => cmp::max(size, field_size),
+                    };
+                    alignment = cmp::max(alignment, field_alignment);
+                }
+                Ok((round_up(size, alignment), alignment))
+            },
+        }
+    }
+
+    /**
+     * Work out the value `declaration` starts with.
+     */
+    fn initial_value(&mut self, declaration: &Declaration) -> Result<Value, String> {
+        let mut value = try!(self.zero(&declaration._type));
+        if let Some(ref initializer) = declaration.initializer {
+            try!(self.initialize(&mut value, initializer));
+        }
+        Ok(value)
+    }
+
+    /**
+     * Store `initializer` in `target`. The members of a struct that an initializer list leaves
+     * out keep the values they had.
+     */
+    fn initialize(&mut self, target: &mut Value, initializer: &Initializer) -> Result<(), String> {
+        let items = match *initializer {
+            Initializer::Expression(ref expr) => {
+                let value = try!(self.value(expr));
+                return store(target, value);
+            },
+            Initializer::List(ref items) => items,
+        };
+
+        if let Value::Int(_) = *target {
+            // A scalar can be initialized with a list of one item, as in `int a = {1};`.
+            return match items.first() {
+                Some(item) if items.len() == 1 && item.designators.is_empty() => match item.initializer {
+                    Initializer::Expression(_) => self.initialize(target, &item.initializer),
+                    Initializer::List(_) => Err("too many braces around scalar initializer".to_string()),
+                },
+                _ => Err("excess elements in scalar initializer".to_string()),
+            };
+        }
+
+        // Items without a designator initialize the member after the last one initialized.
+        let mut next = 0;
+        for item in items {
+            let index = match item.designators.first() {
+                None => next,
+                Some(&Designator::Field(ref name)) => try!(member_index(target, name)),
+                Some(&Designator::Index(_)) => return Err("the interpreter doesn't support arrays yet".to_string()),
+            };
+            let member = match member_at(target, index) {
+                Some(member) => member,
+                None => return Err("excess elements in struct or union initializer".to_string()),
+            };
+
+            if item.designators.len() > 1 {
+                // `.a.b = 1` initializes `b` in `a`, and leaves the rest of `a` as it was.
+                let rest = Initializer::List(vec![DesignatedInitializer{
+                    designators:    item.designators[1..].to_vec(),
+                    initializer:    item.initializer.clone(),
+                }]);
+                try!(self.initialize(member, &rest));
+            } else {
+                try!(self.initialize(member, &item.initializer));
+            }
+            next = index + 1;
+        }
+        Ok(())
+    }
+
+    /**
+     * Find the variable `name`, looking outward from the innermost scope.
+     */
+    fn variable(&mut self, name: &str) -> Result<&mut Value, String> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(value) = scope.variables.get_mut(name) {
+                return Ok(value);
+            }
+        }
+        self.globals.variables.get_mut(name).ok_or_else(|| format!("use of undeclared identifier '{}'", name))
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<Flow, String> {
+        for statement in statements {
+            match try!(self.statement(statement)) {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /**
+     * Run `statements` in a new scope, which is thrown away when they finish.
+     */
+    fn block(&mut self, statements: &[Statement]) -> Result<Flow, String> {
+        self.scopes.push(Scope::default());
+        let result = self.statements(statements);
+        self.scopes.pop();
+        result
+    }
+
+    /**
+     * Run the body of a loop, `if` or `switch`, which is a scope of its own even if it isn't a
+     * `{}` block.
+     */
+    fn substatement(&mut self, statement: &Statement) -> Result<Flow, String> {
+        match *statement {
+            Statement::Compound(ref statements) => self.block(statements),
+            _ => self.block(::std::slice::from_ref(statement)),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<Flow, String> {
+        match *statement {
+            Statement::Declaration(ref declarations) => {
+                for declaration in declarations {
+                    try!(self.declare(declaration));
+                }
+                Ok(Flow::Next)
+            },
+            Statement::Struct(ref _struct) => {
+                for _struct in _struct.declarations() {
+                    self.declare_struct(_struct);
+                }
+                Ok(Flow::Next)
+            },
+            Statement::Expression(ref expr) => self.value(expr).map(|_| Flow::Next),
+            Statement::Compound(ref statements) => self.block(statements),
+            Statement::Empty => Ok(Flow::Next),
+            Statement::Return(ref expr) => match *expr {
+                Some(ref expr) => self.value(expr).map(Flow::Return),
+                None => Ok(Flow::Return(Value::Int(0))),
+            },
+            Statement::Continue => Ok(Flow::Continue),
+            Statement::Break => Ok(Flow::Break),
+
+            Statement::If{ref condition, ref then, ref _else} => {
+                if try!(self.expression(condition)) != 0 {
+                    self.substatement(then)
+                } else if let Some(ref _else) = *_else {
+                    self.substatement(_else)
+                } else {
+                    Ok(Flow::Next)
+                }
+            },
+            Statement::While{ref condition, ref body} => {
+                while try!(self.expression(condition)) != 0 {
+                    match try!(self.substatement(body)) {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => (),
+                    }
+                }
+                Ok(Flow::Next)
+            },
+            Statement::DoWhile{ref body, ref condition} => {
+                loop {
+                    match try!(self.substatement(body)) {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Next | Flow::Continue => (),
+                    }
+                    if try!(self.expression(condition)) == 0 {
+                        break;
+                    }
+                }
+                Ok(Flow::Next)
+            },
+            Statement::For{ref init, ref condition, ref step, ref body} => {
+                self.scopes.push(Scope::default());
+                let result = self.for_statement(init, condition, step, body);
+                self.scopes.pop();
+                result
+            },
+
+            Statement::Switch{ref value, ref body} => {
+                let value = try!(self.expression(value));
+                self.switch(value, body)
+            },
+            Statement::Case(_, ref statement) | Statement::Default(ref statement) |
+            Statement::Labeled(_, ref statement) => self.statement(statement),
+            Statement::Goto(ref label) => Err(format!("goto {} isn't supported by the interpreter yet", label)),
+        }
+    }
+
+    fn for_statement(&mut self, init: &Option<Box<Statement>>, condition: &Option<Expression>,
+                     step: &Option<Expression>, body: &Statement) -> Result<Flow, String> {
+        if let Some(ref init) = *init {
+            try!(self.statement(init));
+        }
+        loop {
+            if let Some(ref condition) = *condition {
+                if try!(self.expression(condition)) == 0 {
+                    break;
+                }
+            }
+            match try!(self.substatement(body)) {
+                Flow::Break => break,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+                Flow::Next | Flow::Continue => (),
+            }
+            if let Some(ref step) = *step {
+                try!(self.expression(step));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /**
+     * Jump to the `case` label matching `value` (or to `default`) and run from there. Only labels
+     * directly inside the body of the switch are found.
+     */
+    fn switch(&mut self, value: i64, body: &Statement) -> Result<Flow, String> {
+        let statements = match *body {
+            Statement::Compound(ref statements) => &statements[..],
+            _ => ::std::slice::from_ref(body),
+        };
+
+        let mut start = None;
+        for (i, statement) in statements.iter().enumerate() {
+            if try!(self.case_matches(statement, value)) {
+                start = Some(i);
+                break;
+            }
+        }
+        let start = match start.or_else(|| statements.iter().position(is_default)) {
+            Some(start) => start,
+            None => return Ok(Flow::Next),
+        };
+
+        match try!(self.block(&statements[start..])) {
+            Flow::Break => Ok(Flow::Next),
+            flow => Ok(flow),
+        }
+    }
+
+    fn case_matches(&mut self, statement: &Statement, value: i64) -> Result<bool, String> {
+        match *statement {
+            Statement::Case(ref label, ref statement) => {
+                Ok(try!(self.expression(label)) == value || try!(self.case_matches(statement, value)))
+            },
+            Statement::Default(ref statement) | Statement::Labeled(_, ref statement) => {
+                self.case_matches(statement, value)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /**
+     * Evaluate `expr`, which may be a struct or union as well as a number.
+     */
+    fn value(&mut self, expr: &Expression) -> Result<Value, String> {
+        match *expr {
+            Expression::Variable(ref name) => self.variable(name).map(|value| value.clone()),
+            Expression::Parenthetical(ref expr) => self.value(expr),
+            Expression::MemberAccess{ref struct_name, ref field_name} => {
+                let value = try!(self.value(struct_name));
+                member(value, field_name)
+            },
+            Expression::FunctionCall{ref function, ref args} => match **function {
+                Expression::Variable(ref name) => self.call(name, args),
+                _ => Err("the interpreter doesn't support calls through function pointers yet".to_string()),
+            },
+            Expression::BinaryOp(ref lhs, BinaryOp::Assign, ref rhs) => {
+                let value = try!(self.value(rhs));
+                let target = try!(self.lvalue(lhs));
+                try!(store(target, value));
+                Ok(target.clone())
+            },
+            Expression::BinaryOp(ref lhs, BinaryOp::Comma, ref rhs) => {
+                try!(self.value(lhs));
+                self.value(rhs)
+            },
+            Expression::Conditional{ref condition, ref then, ref _else} => {
+                if try!(self.expression(condition)) != 0 {
+                    self.value(then)
+                } else {
+                    self.value(_else)
+                }
+            },
+            _ => self.expression(expr).map(Value::Int),
+        }
+    }
+
+    /**
+     * Evaluate `expr`, which must be a number.
+     */
+    fn expression(&mut self, expr: &Expression) -> Result<i64, String> {
+        match *expr {
+            Expression::Number(Number::Int(value, _)) => Ok(value as i64),
+            Expression::Character(value) => Ok(value as i64),
+            Expression::Variable(_) | Expression::MemberAccess{..} | Expression::FunctionCall{..} => {
+                self.value(expr).and_then(int)
+            },
+            Expression::Parenthetical(ref expr) => self.expression(expr),
+            Expression::UnaryOp(ref op, ref operand) => self.unary_op(op, operand),
+            Expression::BinaryOp(ref lhs, ref op, ref rhs) => self.binary_op(lhs, op, rhs),
+            Expression::Conditional{ref condition, ref then, ref _else} => {
+                if try!(self.expression(condition)) != 0 {
+                    self.expression(then)
+                } else {
+                    self.expression(_else)
+                }
+            },
+            Expression::Cast(ref _type, ref expr) => {
+                let value = try!(self.expression(expr));
+                convert(value, _type)
+            },
+            Expression::Declaration(ref declaration) => self.declare(declaration).map(|_| 0),
+            Expression::SizeofType(ref _type) | Expression::Alignof(ref _type) => {
+                // The type can define a struct, as in `sizeof(struct point { int x, y; })`.
+                for _struct in _type.struct_definitions() {
+                    self.declare_struct(_struct);
+                }
+                let (size, alignment) = try!(self.layout(_type));
+                Ok(if let Expression::Alignof(_) = *expr { alignment } else { size })
+            },
+            _ => Err(format!("the interpreter doesn't support {:?} yet", expr)),
+        }
+    }
+
+    fn unary_op(&mut self, op: &UnaryOp, operand: &Expression) -> Result<i64, String> {
+        match *op {
+            UnaryOp::PreIncrement | UnaryOp::PreDecrement => {
+                let change = if *op == UnaryOp::PreIncrement { 1 } else { -1 };
+                let variable = try!(int_mut(try!(self.lvalue(operand))));
+                *variable = variable.wrapping_add(change);
+                Ok(*variable)
+            },
+            UnaryOp::PostIncrement | UnaryOp::PostDecrement => {
+                let change = if *op == UnaryOp::PostIncrement { 1 } else { -1 };
+                let variable = try!(int_mut(try!(self.lvalue(operand))));
+                let old = *variable;
+                *variable = variable.wrapping_add(change);
+                Ok(old)
+            },
+            UnaryOp::Negate => self.expression(operand).map(|value| value.wrapping_neg()),
+            UnaryOp::DontNegate => self.expression(operand),
+            UnaryOp::Not => self.expression(operand).map(|value| (value == 0) as i64),
+            UnaryOp::BitwiseNot => self.expression(operand).map(|value| !value),
+            UnaryOp::Reference | UnaryOp::Dereference => {
+                Err("the interpreter doesn't support pointers yet".to_string())
+            },
+        }
+    }
+
+    fn binary_op(&mut self, lhs: &Expression, op: &BinaryOp, rhs: &Expression) -> Result<i64, String> {
+        // These evaluate their operands in order, and `&&` and `||` only evaluate their right-hand
+        // side if they need to.
+        match *op {
+            BinaryOp::Comma => {
+                try!(self.expression(lhs));
+                return self.expression(rhs);
+            },
+            BinaryOp::And => return Ok((try!(self.expression(lhs)) != 0 && try!(self.expression(rhs)) != 0) as i64),
+            BinaryOp::Or => return Ok((try!(self.expression(lhs)) != 0 || try!(self.expression(rhs)) != 0) as i64),
+            _ => (),
+        }
+
+        let operator = match *op {
+            BinaryOp::Assign => None,
+            BinaryOp::AddAssign => Some(BinaryOp::Add),
+            BinaryOp::SubtractAssign => Some(BinaryOp::Subtract),
+            BinaryOp::MultiplyAssign => Some(BinaryOp::Multiply),
+            BinaryOp::DivideAssign => Some(BinaryOp::Divide),
+            BinaryOp::ModuloAssign => Some(BinaryOp::Modulo),
+            BinaryOp::ShiftLeftAssign => Some(BinaryOp::ShiftLeft),
+            BinaryOp::ShiftRightAssign => Some(BinaryOp::ShiftRight),
+            BinaryOp::BitwiseAndAssign => Some(BinaryOp::BitwiseAnd),
+            BinaryOp::BitwiseXorAssign => Some(BinaryOp::BitwiseXor),
+            BinaryOp::BitwiseOrAssign => Some(BinaryOp::BitwiseOr),
+
+            ref op => {
+                let lhs = try!(self.expression(lhs));
+                let rhs = try!(self.expression(rhs));
+                return arithmetic(lhs, op, rhs);
+            },
+        };
+
+        // An assignment: `a op= b` is `a = a op b`, except that `a` is only evaluated once.
+        let rhs = try!(self.expression(rhs));
+        let variable = try!(int_mut(try!(self.lvalue(lhs))));
+        *variable = match operator {
+            Some(ref op) => try!(arithmetic(*variable, op, rhs)),
+            None => rhs,
+        };
+        Ok(*variable)
+    }
+
+    /**
+     * Find the variable that `expr` refers to, so it can be assigned to.
+     */
+    fn lvalue(&mut self, expr: &Expression) -> Result<&mut Value, String> {
+        match *expr {
+            Expression::Variable(ref name) => self.variable(name),
+            Expression::Parenthetical(ref expr) => self.lvalue(expr),
+            Expression::MemberAccess{ref struct_name, ref field_name} => {
+                let value = try!(self.lvalue(struct_name));
+                let index = try!(member_index(value, field_name));
+                Ok(member_at(value, index).unwrap())
+            },
+            _ => Err(format!("can't assign to {:?}", expr)),
+        }
+    }
+}
+
+/**
+ * Return the number in `value`, which can't be a struct or union.
+ */
+fn int(value: Value) -> Result<i64, String> {
+    match value {
+        Value::Int(value) => Ok(value),
+        _ => Err("a struct or union can't be used as a number".to_string()),
+    }
+}
+
+fn int_mut(value: &mut Value) -> Result<&mut i64, String> {
+    match *value {
+        Value::Int(ref mut value) => Ok(value),
+        _ => Err("a struct or union can't be used as a number".to_string()),
+    }
+}
+
+/**
+ * Store `value` in `target`, which must hold the same kind of value: a number, or a struct or
+ * union.
+ */
+fn store(target: &mut Value, value: Value) -> Result<(), String> {
+    match (&*target, &value) {
+        (&Value::Int(_), &Value::Int(_)) | (&Value::Struct(_), &Value::Struct(_)) |
+        (&Value::Union(..), &Value::Union(..)) => (),
+        (&Value::Int(_), _) => return Err("a struct or union can't be used as a number".to_string()),
+        _ => return Err("a number can't be stored in a struct or union".to_string()),
+    }
+    *target = value;
+    Ok(())
+}
+
+/**
+ * Return the index of the member `name` in the struct or union `value`.
+ */
+fn member_index(value: &Value, name: &str) -> Result<usize, String> {
+    let members = match *value {
+        Value::Struct(ref members) | Value::Union(ref members, _) => members,
+        Value::Int(_) => return Err(format!("request for member '{}' in something not a structure or union", name)),
+    };
+    members.iter().position(|m| m.name == name).ok_or_else(|| format!("no member named '{}'", name))
+}
+
+/**
+ * Return the member at `index` of the struct or union `value`, to store to it.
+ *
+ * The numbers in a union share their storage, so storing to one of them starts with the number
+ * that was stored last, and `u.c++` after `u.i = 300` changes `u.i` to 301.
+ */
+fn member_at(value: &mut Value, index: usize) -> Option<&mut Value> {
+    match *value {
+        Value::Struct(ref mut members) => members.get_mut(index).map(|m| &mut m.value),
+        Value::Union(ref mut members, ref mut active) => {
+            if index >= members.len() {
+                return None;
+            }
+            if let (Value::Int(stored), &mut Value::Int(ref mut number)) = (members[*active].value.clone(), &mut members[index].value) {
+                *number = stored;
+            }
+            *active = index;
+            Some(&mut members[index].value)
+        },
+        Value::Int(_) => None,
+    }
+}
+
+/**
+ * Return the member `name` of the struct or union `value`. Reading a number from a union reads
+ * the number stored last, cut down to the member's type: after `u.i = 300`, a `char` member `c`
+ * is 44.
+ */
+fn member(value: Value, name: &str) -> Result<Value, String> {
+    let index = try!(member_index(&value, name));
+    match value {
+        Value::Struct(mut members) => Ok(members.swap_remove(index).value),
+        Value::Union(members, active) => match (&members[active].value, &members[index]) {
+            (&Value::Int(stored), &Member{_type: ref _type @ Type::Basic{..}, value: Value::Int(_), ..}) => {
+                convert(stored, _type).map(Value::Int)
+            },
+            (_, member) => Ok(member.value.clone()),
+        },
+        Value::Int(_) => unreachable!(),
+    }
+}
+
+fn is_default(statement: &Statement) -> bool {
+    match *statement {
+        Statement::Default(_) => true,
+        Statement::Case(_, ref statement) | Statement::Labeled(_, ref statement) => is_default(statement),
+        _ => false,
+    }
+}
+
+/**
+ * Apply a binary operator that doesn't assign or short-circuit.
+ */
+fn arithmetic(lhs: i64, op: &BinaryOp, rhs: i64) -> Result<i64, String> {
+    Ok(match *op {
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+        BinaryOp::Multiply => lhs.wrapping_mul(rhs),
+        BinaryOp::Divide | BinaryOp::Modulo if rhs == 0 => return Err("division by zero".to_string()),
+        BinaryOp::Divide => lhs.wrapping_div(rhs),
+        BinaryOp::Modulo => lhs.wrapping_rem(rhs),
+        BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+        BinaryOp::BitwiseAnd => lhs & rhs,
+        BinaryOp::BitwiseXor => lhs ^ rhs,
+        BinaryOp::BitwiseOr => lhs | rhs,
+        BinaryOp::Equal => (lhs == rhs) as i64,
+        BinaryOp::NotEqual => (lhs != rhs) as i64,
+        BinaryOp::Less => (lhs < rhs) as i64,
+        BinaryOp::LessEqual => (lhs <= rhs) as i64,
+        BinaryOp::Greater => (lhs > rhs) as i64,
+        BinaryOp::GreaterEqual => (lhs >= rhs) as i64,
+        ref op => unreachable!("{:?} isn't arithmetic", op),
+    })
+}
+
+/**
+ * Round `n` up to a multiple of `alignment`.
+ */
+fn round_up(n: i64, alignment: i64) -> i64 {
+    (n + alignment - 1) / alignment * alignment
+}
+
 /**
  * Return the main() function from the program, if it exists.
  */
-fn get_main(program: &ast::Program) -> Option<&ast::Function> {
+fn get_main(program: &Program) -> Option<&Function> {
     program.functions.iter().find(|&f| f.name == "main")
 }
 
 /**
- * Interpret the program, starting at main().
+ * Convert `value` to `_type`, as a cast does. Integers are cut down to the size they have on
+ * x86-64, so `(unsigned char) 300` is 44 and `(short) 40000` is -25536.
+ */
+fn convert(value: i64, _type: &Type) -> Result<i64, String> {
+    let (base_name, modifiers) = match *_type {
+        Type::Basic{ref base_name, ref modifiers} => (base_name, modifiers),
+        _ => return Err("the interpreter doesn't support pointers yet".to_string()),
+    };
+    let has = |modifier: &str| modifiers.iter().any(|m| m == modifier);
+
+    // `long int` and `short int` have the base name `int`, while `long long` has `long`.
+    let bits = match &base_name[..] {
+        "_Bool" => return Ok((value != 0) as i64),
+        "void" => return Ok(0),
+        "char" => 8,
+        "short" => 16,
+        "long" => 64,
+        "int" if has("short") => 16,
+        "int" if has("long") => 64,
+        "int" => 32,
+        _ => return Err(format!("the interpreter doesn't support {} yet", base_name)),
+    };
+    let shift = 64 - bits;
+    if has("unsigned") {
+        Ok(((value as u64) << shift >> shift) as i64)
+    } else {
+        Ok(value << shift >> shift)
+    }
+}
+
+/**
+ * Interpret the program, starting at main(), and return what main() returns.
  */
-pub fn run_program(program: &ast::Program) -> Result<(), String> {
+fn run<W: Write>(program: &Program, stdout: W) -> Result<i64, String> {
     try!(checker::check_program(program));
-    let main = match get_main(program) {
-        Some(f) => f,
-        None => return Err("no main function found in program".to_string()),
+    if get_main(program).is_none() {
+        return Err("no main function found in program".to_string());
+    }
+
+    // Function declarations are globals too, but they don't need any storage.
+    let globals: Vec<&Declaration> = program.globals.iter().filter(|g| match g._type {
+        Type::Function{..} => false,
+        _ => true,
+    }).collect();
+
+    let mut interpreter = Interpreter{
+        functions:  program.functions.iter().map(|f| (&f.name[..], f)).collect(),
+        globals:    Scope::default(),
+        scopes:     vec![],
+        stdout:     stdout,
     };
-    let _ = main;
-    Ok(())
+    for _struct in program.structs.iter().flat_map(|s| s.declarations()) {
+        interpreter.declare_struct(_struct);
+    }
+    for global in &globals {
+        interpreter.globals.variables.insert(global.name.clone(), Value::Int(0));
+    }
+    for global in globals {
+        try!(interpreter.declare(global));
+    }
+    interpreter.call("main", &[]).and_then(int)
+}
+
+/**
+ * Interpret the program, starting at main().
+ */
+pub fn run_program(program: &Program) -> Result<(), String> {
+    run(program, io::stdout()).map(|_| ())
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::parse_str;
+
+    /**
+     * Run the program in `s`, and return what it wrote to standard output.
+     */
+    fn output(s: &str) -> Result<String, String> {
+        let mut stdout = vec![];
+        try!(run(&parse_str(s).unwrap(), &mut stdout));
+        Ok(String::from_utf8(stdout).unwrap())
+    }
+
+    #[test]
+    fn block_scope() {
+        let program = r##"
+            int x;
+            int main() {
+                x = 1;
+                {
+                    int x;
+                    x = 2;
+                    {
+                        int x;
+                        x = 3;
+                        write_int(1, x);
+                    }
+                    write_int(1, x);
+                }
+                write_int(1, x);
+                for (int x; x < 2; x += 1) write_int(1, x + 10);
+                write_int(1, x);
+            }
+            "##;
+        assert_eq!(output(program), Ok("3\n2\n1\n10\n11\n1\n".to_string()));
+    }
+
+    #[test]
+    fn initializers() {
+        let program = r##"
+            int width = 3;
+            int area = {width * 4};
+            int main() {
+                int fd = 1;
+                int doubled = area * 2;
+                write_int(fd, width);
+                write_int(fd, area);
+                write_int(fd, doubled);
+            }
+            "##;
+        assert_eq!(output(program), Ok("3\n12\n24\n".to_string()));
+        assert_eq!(output("int main() { int a = {1, 2}; }"), Err("excess elements in scalar initializer".to_string()));
+        assert_eq!(output("int main() { int a = {{1}}; }"), Err("too many braces around scalar initializer".to_string()));
+    }
+
+    #[test]
+    fn function_scope() {
+        let program = r##"
+            int count;
+            void shadow() { int count; count = 5; }
+            int triple(int count) { return count * 3; }
+            int main() {
+                count = 2;
+                shadow();
+                write_int(1, count);
+                write_int(1, triple(7));
+                write_int(1, count);
+            }
+            "##;
+        assert_eq!(output(program), Ok("2\n21\n2\n".to_string()));
+    }
+
+    #[test]
+    fn control_flow() {
+        let program = r##"
+            int main() {
+                int i;
+                i = 0;
+                while (1) {
+                    i += 1;
+                    if (i == 2) continue;
+                    if (i > 3) break;
+                    write_int(1, i);
+                }
+                do write_int(1, i--); while (i > 2);
+                switch (i) {
+                case 1: write_int(1, 100);
+                case 2: write_int(1, 200);
+                case 3: write_int(1, 300); break;
+                default: write_int(1, 400);
+                }
+                switch (i + 10) { case 1: write_int(1, 1); default: write_int(1, 500); }
+                return i;
+            }
+            "##;
+        assert_eq!(output(program), Ok("1\n3\n4\n3\n200\n300\n500\n".to_string()));
+    }
+
+    /**
+     * The results here are what gcc gives for the same program.
+     */
+    #[test]
+    fn operator_precedence() {
+        let program = r##"
+            int main() {
+                int a; int b; int c;
+                a = 5; b = 3; c = 0;
+                write_int(1, a + b * 2 - 10 / 3 % 2);
+                write_int(1, a << 2 >> 1 | b & 6 ^ 1);
+                write_int(1, a > b == b < a != 0);
+                write_int(1, c || a && !b || -a + ~b);
+                write_int(1, c ? 10 : a > 4 ? 20 : 30);
+                write_int(1, (a = 1, b = 2, a + b));
+                write_int(1, a += b *= 3);
+                write_int(1, a - -b - - -c);
+                write_int(1, (c = a ? b : c) + 1);
+                write_int(1, (1 ? 2, 3 : 4));
+                write_int(1, a);
+            }
+            "##;
+        assert_eq!(output(program), Ok("10\n11\n1\n1\n20\n3\n7\n13\n7\n3\n7\n".to_string()));
+    }
+
+    #[test]
+    fn operators() {
+        let program = r##"
+            int zero() { write_int(1, 0); return 0; }
+            int main() {
+                int x;
+                x = 7;
+                write_int(1, x * 3 - 10 / 4 % 3);
+                write_int(1, x << 2 | 1);
+                write_int(1, ~x ^ 5 & 6);
+                write_int(1, !x + !!x);
+                write_int(1, x > 6 == 1 != 0);
+                x += 1; x <<= 2; x %= 5;
+                write_int(1, x);
+                write_int(1, ++x);
+                write_int(1, 0 && zero() || 2 > 1 || zero());
+            }
+            "##;
+        assert_eq!(output(program), Ok("19\n29\n-4\n1\n1\n2\n3\n1\n".to_string()));
+    }
+
+    #[test]
+    fn calls() {
+        let program = r##"
+            int factorial(int n) { if (n <= 1) return 1; return n * factorial(n - 1); }
+            int main() { write_int(1, factorial(10)); return factorial(3); }
+            "##;
+        let mut stdout = vec![];
+        assert_eq!(run(&parse_str(program).unwrap(), &mut stdout), Ok(6));
+        assert_eq!(stdout, b"3628800\n");
+
+        // write_int takes an int, so bigger values are truncated, as they would be in C.
+        assert_eq!(output("int main() { write_int(1, 4000000000); }"), Ok("-294967296\n".to_string()));
+    }
+
+    #[test]
+    fn multiple_declarators() {
+        let program = r##"
+            int a = 2, b, c = a * 3;
+            int main() {
+                int d = c + 1, e = d * 2;
+                for (int i = 0, j = 3; i < j; i++, j--)
+                    b += 10;
+                write_int(1, a + b + c + d + e);
+            }
+            "##;
+        assert_eq!(output(program), Ok("49\n".to_string()));
+    }
+
+    #[test]
+    fn structs_and_unions() {
+        let program = r##"
+            struct point { int x, y; };
+            struct rect { struct point min, max; } box = {{1, 2}, .max = {.y = 8, .x = 5}};
+            union number { int i; char c; };
+            int area(struct rect r) { return (r.max.x - r.min.x) * (r.max.y - r.min.y); }
+            struct point add(struct point a, struct point b) { a.x += b.x; a.y += b.y; return a; }
+            int main() {
+                struct point p = {3}, q;
+                q = p;
+                q.y = 4;
+                p.x++;
+                write_int(1, p.x);
+                write_int(1, p.y);
+                write_int(1, q.x + q.y * 10);
+                write_int(1, area(box));
+                write_int(1, add(p, q).y);
+                box.min = add(box.min, p);
+                write_int(1, area(box));
+                union number n = {.i = 300};
+                write_int(1, n.c);
+                n.c++;
+                write_int(1, n.i);
+                struct { int a; union { int b; } u; } anon = {.u.b = 7};
+                write_int(1, anon.u.b + anon.a);
+            }
+            "##;
+        assert_eq!(output(program), Ok("4\n0\n43\n24\n4\n0\n44\n301\n7\n".to_string()));
+
+        assert_eq!(output("int main() { struct s { int x; } a; return a + 1; }"),
+                   Err("a struct or union can't be used as a number".to_string()));
+        assert_eq!(output("int main() { struct s { int x; } a = {1, 2}; }"),
+                   Err("excess elements in struct or union initializer".to_string()));
+    }
+
+    #[test]
+    fn casts() {
+        let program = r##"
+            int twice(int);
+            int main(void) {
+                write_int(1, (unsigned char) 300);
+                write_int(1, (short) 40000);
+                write_int(1, (unsigned) -1 / 2);
+                write_int(1, (long long) (int) 4294967297);
+                write_int(1, (_Bool) twice(-3));
+            }
+            int twice(int n) { return n * 2; }
+            "##;
+        assert_eq!(output(program), Ok("44\n-25536\n2147483647\n1\n1\n".to_string()));
+    }
+
+    #[test]
+    fn sizeof_and_alignof() {
+        let program = r##"
+            int main(void) {
+                write_int(1, sizeof(int) + sizeof(long long) + sizeof(short int));
+                write_int(1, sizeof(char *[3]));
+                write_int(1, sizeof(struct s { char c; int i; short h; }));
+                write_int(1, sizeof(union u { char c[5]; int i; }) * 10 + _Alignof(union u));
+                write_int(1, _Alignof(long double));
+                struct s one;
+                write_int(1, sizeof(struct s[2]));
+            }
+            "##;
+        assert_eq!(output(program), Ok("14\n24\n12\n84\n16\n24\n".to_string()));
+        assert_eq!(output("int main() { return sizeof(void); }"), Err("void has no size".to_string()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(output("int f() {}"), Err("no main function found in program".to_string()));
+        assert_eq!(output("int main() { return 1 / 0; }"), Err("division by zero".to_string()));
+        assert_eq!(output("int main() { missing(); }"), Err("call to undefined function 'missing'".to_string()));
+        assert_eq!(output("int f(int a) { return a; } int main() { f(1, 2); }"),
+                   Err("function 'f' takes 1 arguments, but was given 2".to_string()));
+        assert_eq!(output("int main() { write_int(3, 0); }"),
+                   Err("write_int can't write to file descriptor 3".to_string()));
+        assert_eq!(output("int main() { break; }"),
+                   Err("break or continue outside of a loop in 'main'".to_string()));
+        assert_eq!(output("int main() { int a[3]; }"), Err("the interpreter doesn't support arrays yet".to_string()));
+        assert_eq!(output("int main() { return (int *) 0; }"), Err("the interpreter doesn't support pointers yet".to_string()));
+    }
+}
@@ -19,6 +19,7 @@ pub mod ast;
 mod checker;
 pub mod interpreter;
 pub mod parser;
+pub mod preprocessor;
 pub mod source;
 pub mod util;
 
@@ -2,21 +2,80 @@
 extern crate cc;
 
 use std::env;
-use std::path::Path;
+use std::io::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 
 
 #[allow(dead_code)] // This shouldn't be necessary, but otherwise `cargo test` complains.
 fn main() {
-    // The first parameter is the filename of the C program we're going to parse.
-    let input_filename = env::args().skip(1).next().unwrap();
+    // The parameters are the filename of the C program we're going to parse, plus any flags.
+    let mut preprocessor = cc::preprocessor::Preprocessor::new();
+    let mut input_filename = None;
+    let mut preprocess_only = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-E" => preprocess_only = true,
+            "-trigraphs" => preprocessor.lex_options.trigraphs = true,
+            "-digraphs" => preprocessor.lex_options.digraphs = true,
+            "-I" => preprocessor.include_paths.extend(args.next().map(PathBuf::from)),
+            _ if arg.starts_with("-I") => preprocessor.include_paths.push(PathBuf::from(&arg[2..])),
+            "-D" => if let Some(definition) = args.next() { define(&mut preprocessor, &definition) },
+            _ if arg.starts_with("-D") => define(&mut preprocessor, &arg[2..]),
+            "-U" => if let Some(name) = args.next() { preprocessor.undefine_macro(&name) },
+            _ if arg.starts_with("-U") => preprocessor.undefine_macro(&arg[2..]),
+            _ => input_filename = Some(arg),
+        }
+    }
+    let usage = "usage: cc [-E] [-trigraphs] [-digraphs] [-I DIR]... [-D NAME[=VALUE]]... [-U NAME]... FILE";
+    let input_filename = input_filename.expect(usage);
 
     // Load the file.
     let file = cc::source::File::from_disk(Path::new(&input_filename)).unwrap();
 
+    // With `-E`, all we do is write out the preprocessed source.
+    if preprocess_only {
+        let (text, errors) = preprocessor.preprocess_to_text(file);
+        for diagnostic in &errors {
+            writeln!(io::stderr(), "{}", preprocessor.sources.describe(diagnostic)).unwrap();
+        }
+        print!("{}", text);
+        process::exit(if errors.iter().any(|d| d.is_error()) { 1 } else { 0 });
+    }
+
+    // Preprocess it into tokens. This keeps going after an error, so report everything it found.
+    // Errors come out like `hello.c:3:14: unexpected character '$'`, and warnings (which don't
+    // stop us) like `hello.c:1:9: warning: unknown pragma ignored`.
+    let (lexemes, errors) = preprocessor.preprocess(file);
+    for diagnostic in &errors {
+        writeln!(io::stderr(), "{}", preprocessor.sources.describe(diagnostic)).unwrap();
+    }
+
     // Parse it into an AST (see `ast.rs`)
-    let program = cc::parser::parse_str(&file.buf).unwrap();
+    let program = match cc::parser::parse(lexemes) {
+        Ok(program) => program,
+        Err(diagnostic) => {
+            writeln!(io::stderr(), "{}", preprocessor.sources.describe(&diagnostic)).unwrap();
+            process::exit(1);
+        },
+    };
+    if errors.iter().any(|d| d.is_error()) {
+        process::exit(1);
+    }
     println!("parsed an AST {:?}", program);
 
     // Run the program loaded in the AST.
     cc::interpreter::run_program(&program).unwrap();
 }
+
+/**
+ * `-D NAME` defines NAME as 1, and `-D NAME=VALUE` defines it as VALUE.
+ */
+fn define(preprocessor: &mut cc::preprocessor::Preprocessor, definition: &str) {
+    match definition.find('=') {
+        Some(i) => preprocessor.define_macro(&definition[..i], &definition[i + 1..]),
+        None => preprocessor.define_macro(definition, "1"),
+    }
+}
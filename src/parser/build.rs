@@ -2,9 +2,12 @@
  * This module contains the functions that build ASTs from Tokens.
  */
 
+use parser::lexer;
 use parser::lexer::Token;
+use parser::lexer::Keyword;
 use parser::lexer::Operator;
 use ast::*;
+use source::{Diagnostic, Span};
 
 use super::context::Context;
 
@@ -13,89 +16,244 @@ use super::context::Context;
  * called. Terminals, maybe?
  *
  * Either way, this function returns one of the fundamental units of the AST (sometimes). The
- * exception to this rule is function calls, which obviously can contain full expressions as
- * arguments.
+ * exception to this rule is parenthesized expressions, which obviously can contain full
+ * expressions.
  */
-fn atom(context: &mut Context) -> Result<Expression, String> {
+fn atom(context: &mut Context) -> Result<Expression, Diagnostic> {
     match context.next() {
-        Some(Token::String(s)) => Ok(Expression::String(s)),
+        Some(Token::String(s)) => {
+            // Adjacent string literals are concatenated, so `"a" "b"` is the same as `"ab"`.
+            let mut literal = s.get();
+            while let Some(Token::String(next)) = context.peek() {
+                context.next();
+                literal = match concatenate_strings(literal, next.get()) {
+                    Ok(literal) => literal,
+                    Err(e) => return Err(context.error(e)),
+                };
+            }
+            Ok(Expression::String(literal))
+        },
         Some(Token::Character(ch)) => Ok(Expression::Character(ch)),
+        Some(Token::LParen) => {
+            let expr = try!(expression(context));
+            try!(expect(context, Token::RParen, "')' after parenthesized expression"));
+            Ok(Expression::Parenthetical(box expr))
+        },
         Some(Token::Number(n)) => Ok(Expression::Number(n)),
-        Some(Token::Identifier(id)) => {
-            match context.peek() {
-                Some(Token::LParen) => {
-                    /*
-                     * Function call on the identifier.
-                     */
-                    context.next(); // consume the opening LParen
-
-                    let function_name = id;
-                    let mut arguments: Vec<Expression> = vec![];
-
-                    match context.peek() {
-                        Some(Token::RParen) => {
-                            // no arguments to function call
-                            context.next(); // consume the Token::RParen
-                            Ok(Expression::FunctionCall{name: function_name, args: vec![]})
-                        }
+        Some(Token::Identifier(id)) => Ok(Expression::Variable(id.as_str().to_string())),
+        Some(token) => Err(context.error(format!("expected atom token, got {:?}", token))),
+        None => Err(context.error("expected atom token")),
+    }
+}
 
-                        _ => {
-                            let first_arg = try!(expression(context));
-                            arguments.push(first_arg);
+/**
+ * Parse an atom followed by any number of postfix operators: `a[i]`, `f(x, y)`, `s.field`,
+ * `p->field`, `i++` and `i--`. These bind tighter than anything else and group to the left, so
+ * `get_table()[i](x)` calls the function found in the table that `get_table()` returns.
+ */
+fn postfix(context: &mut Context) -> Result<Expression, Diagnostic> {
+    let mut expr = try!(atom(context));
 
-                            loop {
-                                match context.next() {
-                                    Some(Token::RParen) => break,
-                                    Some(Token::Comma) => arguments.push(try!(expression(context))),
+    loop {
+        expr = match context.peek() {
+            Some(Token::LSquareBracket) => {
+                context.next();
+                let index = try!(expression(context));
+                try!(expect(context, Token::RSquareBracket, "']' after array index"));
+                Expression::ArrayIndex{array: box expr, index: box index}
+            },
+            Some(Token::LParen) => {
+                context.next();
+                Expression::FunctionCall{function: box expr, args: try!(arguments(context))}
+            },
+            Some(Token::Period) => {
+                context.next();
+                Expression::MemberAccess{struct_name: box expr, field_name: try!(field_name(context))}
+            },
+            Some(Token::Arrow) => {
+                context.next();
+                Expression::MemberAccess{
+                    struct_name:    box Expression::UnaryOp(UnaryOp::Dereference, box expr),
+                    field_name:     try!(field_name(context)),
+                }
+            },
+            Some(Token::Operator(Operator::Increment)) => {
+                context.next();
+                Expression::UnaryOp(UnaryOp::PostIncrement, box expr)
+            },
+            Some(Token::Operator(Operator::Decrement)) => {
+                context.next();
+                Expression::UnaryOp(UnaryOp::PostDecrement, box expr)
+            },
+            _ => return Ok(expr),
+        };
+    }
+}
 
-                                    Some(tok) => return Err(format!(
-                                            "unexpected token {:?} while parsing arguments to function {:?}",
-                                            tok, function_name)),
+/**
+ * Parse the arguments of a function call, after the opening paren, up to and including the
+ * closing paren.
+ */
+fn arguments(context: &mut Context) -> Result<Vec<Expression>, Diagnostic> {
+    let mut arguments: Vec<Expression> = vec![];
+    if context.peek() == Some(Token::RParen) {
+        // no arguments to function call
+        context.next(); // consume the Token::RParen
+        return Ok(arguments);
+    }
 
-                                    None => return Err(format!(
-                                            "unexpected EOF while parsing arguments to function {:?}",
-                                            function_name)),
-                                }
-                            }
+    arguments.push(try!(assignment_expression(context)));
+    loop {
+        match context.next() {
+            Some(Token::RParen) => return Ok(arguments),
+            Some(Token::Comma) => arguments.push(try!(assignment_expression(context))),
 
-                            Ok(Expression::FunctionCall{name: function_name, args: arguments})
-                        }
-                    }
-                },
+            Some(tok) => return Err(context.error(format!(
+                    "unexpected token {:?} while parsing arguments to function call", tok))),
+            None => return Err(context.error("unexpected EOF while parsing arguments to function call")),
+        }
+    }
+}
 
-                _ => Ok(Expression::Variable(id))
-            }
+/**
+ * Parse the name of a struct or union member after a `.` or `->`.
+ */
+fn field_name(context: &mut Context) -> Result<String, Diagnostic> {
+    match context.next() {
+        Some(Token::Identifier(name)) => Ok(name.as_str().to_string()),
+        Some(tok) => Err(context.error(format!("expected member name, got {:?}", tok))),
+        None => Err(context.error("expected member name")),
+    }
+}
+
+/**
+ * Concatenate two adjacent string literals (C11 6.4.5p5).
+ *
+ * If either literal has an encoding prefix, the result has that prefix too, and the characters of
+ * an unprefixed literal are re-encoded to match. Two different prefixes can't be mixed.
+ */
+fn concatenate_strings(lhs: StringLiteral, rhs: StringLiteral) -> Result<StringLiteral, String> {
+    let encoding = match (lhs.encoding, rhs.encoding) {
+        (a, b) if a == b => a,
+        (Encoding::Char, b) => b,
+        (a, Encoding::Char) => a,
+        (a, b) => return Err(format!("can't concatenate string literals with different \
+                                      encodings ({:?} and {:?})", a, b)),
+    };
+
+    let mut units = reencode(lhs, encoding);
+    units.extend(reencode(rhs, encoding));
+    Ok(StringLiteral{
+        encoding:   encoding,
+        units:      units,
+    })
+}
+
+/**
+ * Convert the code units of `literal` into `encoding`. Only plain literals ever need converting;
+ * their bytes are decoded as UTF-8 where possible, and any other byte (from an escape like
+ * `"\xff"`) is kept as a code unit of the same value.
+ */
+fn reencode(literal: StringLiteral, encoding: Encoding) -> Vec<u32> {
+    let narrow = |e| e == Encoding::Char || e == Encoding::Utf8;
+    if literal.encoding == encoding || (narrow(literal.encoding) && narrow(encoding)) {
+        return literal.units;
+    }
+
+    let bytes: Vec<u8> = literal.units.iter().map(|&u| u as u8).collect();
+    let mut units = vec![];
+    let mut rest = &bytes[..];
+    while !rest.is_empty() {
+        let valid = match ::std::str::from_utf8(rest) {
+            Ok(s) => s,
+            Err(e) => ::std::str::from_utf8(&rest[..e.valid_up_to()]).unwrap(),
+        };
+        for ch in valid.chars() {
+            lexer::encode(&mut units, encoding, ch);
+        }
+        rest = &rest[valid.len()..];
+        if !rest.is_empty() {
+            units.push(rest[0] as u32);
+            rest = &rest[1..];
         }
-        Some(token) => Err(format!("expected atom token, got {:?}", token)),
-        None => Err("expected atom token".to_string()),
     }
+    units
 }
 
 /**
- * Parse one of the unary operators: `+`, `-`, `*`, or `&`.
+ * Parse one of the prefix unary operators: `+`, `-`, `*`, `&`, `!`, `~`, `++`, `--`, `sizeof` or
+ * `_Alignof`. These can be stacked, as in `!!x` or `-*p`.
  */
-fn unary_op(context: &mut Context) -> Result<Expression, String> {
+fn unary_op(context: &mut Context) -> Result<Expression, Diagnostic> {
     match context.peek() {
+        // A type in parentheses is a cast, which binds as tightly as the unary operators.
+        Some(Token::LParen) if starts_parenthesized_type_name(context) => {
+            let _type = try!(parenthesized_type_name(context, "cast"));
+            let operand = try!(unary_op(context));
+            Ok(Expression::Cast(_type, box operand))
+        },
+
+        // `sizeof` takes either a type in parentheses or an expression, which may be in
+        // parentheses too, as in `sizeof (x)`.
+        Some(Token::Keyword(Keyword::Sizeof)) => {
+            context.next();
+            if context.peek() == Some(Token::LParen) && starts_parenthesized_type_name(context) {
+                return Ok(Expression::SizeofType(try!(parenthesized_type_name(context, "sizeof"))));
+            }
+            let operand = try!(unary_op(context));
+            Ok(Expression::Sizeof(box operand))
+        },
+
+        Some(Token::Keyword(Keyword::Alignof)) => {
+            context.next();
+            Ok(Expression::Alignof(try!(parenthesized_type_name(context, "_Alignof"))))
+        },
+
+
         Some(Token::Operator(lexer_op @ _)) => {
             let parser_op = match lexer_op {
                 Operator::Add => UnaryOp::DontNegate,
                 Operator::Subtract => UnaryOp::Negate,
-                Operator::Reference => UnaryOp::Reference,
+                Operator::Ampersand => UnaryOp::Reference,
                 Operator::Asterisk => UnaryOp::Dereference,
+                Operator::Not => UnaryOp::Not,
+                Operator::BitwiseNot => UnaryOp::BitwiseNot,
+                Operator::Increment => UnaryOp::PreIncrement,
+                Operator::Decrement => UnaryOp::PreDecrement,
 
                 // not a unary op.
-                _ => return atom(context),
+                _ => return postfix(context),
             };
 
             context.next(); // consume token
-            let rhs = try!(atom(context));
+            let rhs = try!(unary_op(context));
             Ok(Expression::UnaryOp(parser_op, box rhs))
         },
 
-        _ => atom(context),
+        _ => postfix(context),
     }
 }
 
+/**
+ * Return true if the left paren `peek()` would return starts a type name in parentheses, as in a
+ * cast, rather than a parenthesized expression.
+ */
+fn starts_parenthesized_type_name(context: &mut Context) -> bool {
+    let second = peek_second(context);
+    starts_type_name(context, second)
+}
+
+/**
+ * Parse a type name in parentheses, like the `(unsigned char)` in a cast. `what` is what it's for,
+ * to say in errors.
+ */
+fn parenthesized_type_name(context: &mut Context, what: &str) -> Result<Type, Diagnostic> {
+    try!(expect(context, Token::LParen, &format!("'(' before type name in {}", what)));
+    let _type = try!(type_name(context));
+    try!(expect(context, Token::RParen, &format!("')' after type name in {}", what)));
+    Ok(_type)
+}
+
 /**
  * Convert from a lexer operator to a parser BinaryOp.
  *
@@ -112,305 +270,1114 @@ fn lexer_op_to_parser_op(lexer_op: Operator) -> Option<BinaryOp> {
         Operator::Modulo => BinaryOp::Modulo,
         Operator::And => BinaryOp::And,
         Operator::Or => BinaryOp::Or,
+        Operator::Ampersand => BinaryOp::BitwiseAnd,
+        Operator::BitwiseOr => BinaryOp::BitwiseOr,
+        Operator::BitwiseXor => BinaryOp::BitwiseXor,
+        Operator::ShiftLeft => BinaryOp::ShiftLeft,
+        Operator::ShiftRight => BinaryOp::ShiftRight,
+        Operator::Equal => BinaryOp::Equal,
+        Operator::NotEqual => BinaryOp::NotEqual,
+        Operator::Less => BinaryOp::Less,
+        Operator::LessEqual => BinaryOp::LessEqual,
+        Operator::Greater => BinaryOp::Greater,
+        Operator::GreaterEqual => BinaryOp::GreaterEqual,
         Operator::Assign => BinaryOp::Assign,
-        _ => return None,
+        Operator::AddAssign => BinaryOp::AddAssign,
+        Operator::SubtractAssign => BinaryOp::SubtractAssign,
+        Operator::MultiplyAssign => BinaryOp::MultiplyAssign,
+        Operator::DivideAssign => BinaryOp::DivideAssign,
+        Operator::ModuloAssign => BinaryOp::ModuloAssign,
+        Operator::ShiftLeftAssign => BinaryOp::ShiftLeftAssign,
+        Operator::ShiftRightAssign => BinaryOp::ShiftRightAssign,
+        Operator::AndAssign => BinaryOp::BitwiseAndAssign,
+        Operator::XorAssign => BinaryOp::BitwiseXorAssign,
+        Operator::OrAssign => BinaryOp::BitwiseOrAssign,
+        Operator::Increment | Operator::Decrement | Operator::Not | Operator::BitwiseNot => return None,
     })
 }
 
 /**
- * Construct an operator node with the only operators allowed coming from `allowed_operators`.
- * `build_subtree` refers to the next-looser precedence level.
+ * Associativity says which way a chain of operators from the same level groups: `a - b - c` is
+ * `(a - b) - c`, but `a = b = c` is `a = (b = c)`.
  */
-fn binary_operator_helper(
-    context: &mut Context,
-    allowed_operators: &'static [BinaryOp],
-    build_subtree: fn(&mut Context) -> Result<Expression, String>
-) -> Result<Expression, String> {
+#[derive(Clone,Copy,PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+}
 
-    let lhs_node = try!(build_subtree(context));
-    let operator;
+/**
+ * Infix is an operator that goes between two operands.
+ */
+#[derive(Clone,PartialEq)]
+enum Infix {
+    Binary(BinaryOp),
+    Conditional, // the `?` of `a ? b : c`
+}
 
-    // Look for an operator token, if one exists.
-    match context.peek() {
-        None => return Ok(lhs_node),
-        Some(Token::Operator(tok)) => {
-            let parser_op = lexer_op_to_parser_op(tok);
-            match parser_op {
-                Some(ref o) if allowed_operators.contains(&o) => {
-                    operator = o.clone();
-                    context.next();
-                },
-                _ => return Ok(lhs_node),
-            }
-        },
-        _ => return Ok(lhs_node),
-    }
+/**
+ * The levels of infix operators, loosest first (C11 6.5.5 to 6.5.17). Together with the unary
+ * and postfix operators, which bind tighter than any of these, that's all 15 levels of C.
+ */
+static LEVELS: [(Associativity, &'static [Infix]); 13] = [
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::Comma)]),
+    (Associativity::Right,  &[
+        Infix::Binary(BinaryOp::Assign),
+        Infix::Binary(BinaryOp::AddAssign), Infix::Binary(BinaryOp::SubtractAssign),
+        Infix::Binary(BinaryOp::MultiplyAssign), Infix::Binary(BinaryOp::DivideAssign),
+        Infix::Binary(BinaryOp::ModuloAssign),
+        Infix::Binary(BinaryOp::ShiftLeftAssign), Infix::Binary(BinaryOp::ShiftRightAssign),
+        Infix::Binary(BinaryOp::BitwiseAndAssign), Infix::Binary(BinaryOp::BitwiseXorAssign),
+        Infix::Binary(BinaryOp::BitwiseOrAssign),
+    ]),
+    (Associativity::Right,  &[Infix::Conditional]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::Or)]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::And)]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::BitwiseOr)]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::BitwiseXor)]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::BitwiseAnd)]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::Equal), Infix::Binary(BinaryOp::NotEqual)]),
+    (Associativity::Left,   &[
+        Infix::Binary(BinaryOp::Less), Infix::Binary(BinaryOp::LessEqual),
+        Infix::Binary(BinaryOp::Greater), Infix::Binary(BinaryOp::GreaterEqual),
+    ]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::ShiftLeft), Infix::Binary(BinaryOp::ShiftRight)]),
+    (Associativity::Left,   &[Infix::Binary(BinaryOp::Add), Infix::Binary(BinaryOp::Subtract)]),
+    (Associativity::Left,   &[
+        Infix::Binary(BinaryOp::Multiply), Infix::Binary(BinaryOp::Divide), Infix::Binary(BinaryOp::Modulo),
+    ]),
+];
+
+// Indexes into LEVELS for the places the grammar asks for "an expression without commas" and so on.
+const COMMA_LEVEL: usize = 0;
+const ASSIGNMENT_LEVEL: usize = 1;
+const CONDITIONAL_LEVEL: usize = 2;
 
-    let rhs_node = try!(binary_operator_helper(context, allowed_operators, build_subtree));
-    Ok(Expression::BinaryOp(
-        box lhs_node,
-        operator,
-        box rhs_node,
-    ))
+/**
+ * If the next token is an infix operator, return it and its index in LEVELS, without consuming
+ * it.
+ */
+fn peek_infix(context: &Context) -> Option<(Infix, usize)> {
+    let infix = match context.peek() {
+        Some(Token::Operator(op)) => match lexer_op_to_parser_op(op) {
+            Some(op) => Infix::Binary(op),
+            None => return None,
+        },
+        Some(Token::Comma) => Infix::Binary(BinaryOp::Comma),
+        Some(Token::Question) => Infix::Conditional,
+        _ => return None,
+    };
+    let level = LEVELS.iter().position(|&(_, infixes)| infixes.contains(&infix)).unwrap();
+    Some((infix, level))
 }
 
-fn multiplication(context: &mut Context) -> Result<Expression, String> {
-    static OPERATORS: [BinaryOp; 2] = [BinaryOp::Multiply, BinaryOp::Divide];
-    binary_operator_helper(context, &OPERATORS, unary_op)
+/**
+ * Parse an expression made of operators from `min_level` of LEVELS or tighter. This is
+ * precedence climbing: an operand is followed by any number of operators, and the operand to the
+ * right of each one is parsed with only the operators that bind tighter than it (or, for a
+ * right-associative operator, as tight). Whatever's left over groups to the left.
+ */
+fn binary_expression(context: &mut Context, min_level: usize) -> Result<Expression, Diagnostic> {
+    let mut lhs = try!(unary_op(context));
+
+    loop {
+        let (infix, level) = match peek_infix(context) {
+            Some((infix, level)) if level >= min_level => (infix, level),
+            _ => return Ok(lhs),
+        };
+        context.next(); // consume the operator
+
+        let rhs_level = match LEVELS[level].0 {
+            Associativity::Left => level + 1,
+            Associativity::Right => level,
+        };
+        lhs = match infix {
+            Infix::Binary(op) => {
+                let rhs = try!(binary_expression(context, rhs_level));
+                Expression::BinaryOp(box lhs, op, box rhs)
+            },
+            Infix::Conditional => {
+                // Anything can go between the `?` and the `:`, even a comma.
+                let then = try!(expression(context));
+                try!(expect(context, Token::Colon, "':' in conditional expression"));
+                let _else = try!(binary_expression(context, rhs_level));
+                Expression::Conditional{condition: box lhs, then: box then, _else: box _else}
+            },
+        };
+    }
 }
 
-fn addition(context: &mut Context) -> Result<Expression, String> {
-    static OPERATORS: [BinaryOp; 2] = [BinaryOp::Add, BinaryOp::Subtract];
-    binary_operator_helper(context, &OPERATORS, multiplication)
+/**
+ * Parse a single expression. Many things in C are expressions, including declarations and
+ * assignments. This includes the comma operator, so it's only the right thing where a comma
+ * can't mean anything else.
+ */
+pub fn expression(context: &mut Context) -> Result<Expression, Diagnostic> {
+    binary_expression(context, COMMA_LEVEL)
 }
 
-fn boolean_ops(context: &mut Context) -> Result<Expression, String> {
-    static OPERATORS: [BinaryOp; 2] = [BinaryOp::And, BinaryOp::Or];
-    binary_operator_helper(context, &OPERATORS, addition)
+/**
+ * Parse an expression that stops at a comma, like a function argument.
+ */
+fn assignment_expression(context: &mut Context) -> Result<Expression, Diagnostic> {
+    binary_expression(context, ASSIGNMENT_LEVEL)
 }
 
 /**
- * Parse a single expression. Many things in C are expressions, including declarations and
- * assignments.
+ * Parse an expression without assignments or commas, like a `case` label.
  */
-pub fn expression(context: &mut Context) -> Result<Expression, String> {
-    boolean_ops(context)
+fn conditional_expression(context: &mut Context) -> Result<Expression, Diagnostic> {
+    binary_expression(context, CONDITIONAL_LEVEL)
 }
 
 /**
- * Parse a statement.
+ * Consume the next token, which must be `expected`. `what` describes it for the error message.
  */
-fn statement(context: &mut Context) -> Result<Statement, String> {
-    let expr = try!(expression(context));
+fn expect(context: &mut Context, expected: Token, what: &str) -> Result<(), Diagnostic> {
     match context.next() {
-        Some(Token::Semicolon) => Ok(Statement::Expression(expr)),
-        Some(token) => Err(format!("unexpected token {:?} after expression", token)),
-        None => Err(format!("expected semicolon after statement")),
+        Some(ref tok) if *tok == expected => Ok(()),
+        Some(tok) => Err(context.error(format!("expected {}, got {:?}", what, tok))),
+        None => Err(context.error(format!("expected {}, got EOF", what))),
     }
 }
 
 /**
- * Parse a block of statements. This may be either a single statement or a series of statements
- * enclosed in curly braces `{}`.
- *
- * The astute among you may notice that this is very similar to argument parsing :)
+ * Parse a parenthesized expression, like the condition of an `if` or a `while`. `what` is the
+ * statement it belongs to, for error messages.
+ */
+fn parenthesized(context: &mut Context, what: &str) -> Result<Expression, Diagnostic> {
+    try!(expect(context, Token::LParen, &format!("'(' after '{}'", what)));
+    let expr = try!(expression(context));
+    try!(expect(context, Token::RParen, &format!("')' after {} condition", what)));
+    Ok(expr)
+}
+
+/**
+ * Parse a `for` statement, starting after the `for` keyword. The first clause may declare a
+ * variable, as in `for (int i = 0; i < 10; i++)`.
+ */
+fn for_statement(context: &mut Context) -> Result<Statement, Diagnostic> {
+    try!(expect(context, Token::LParen, "'(' after 'for'"));
+
+    // A declaration in the first clause is only in scope until the end of the loop.
+    context.push_scope();
+    let init = match context.peek() {
+        Some(Token::Semicolon) => None,
+        token if starts_type_name(context, token) => {
+            Some(box Statement::Declaration(try!(declaration(context))))
+        },
+        _ => Some(box Statement::Expression(try!(expression(context)))),
+    };
+    try!(expect(context, Token::Semicolon, "semicolon after for loop initializer"));
+
+    let condition = match context.peek() {
+        Some(Token::Semicolon) => None,
+        _ => Some(try!(expression(context))),
+    };
+    try!(expect(context, Token::Semicolon, "semicolon after for loop condition"));
+
+    let step = match context.peek() {
+        Some(Token::RParen) => None,
+        _ => Some(try!(expression(context))),
+    };
+    try!(expect(context, Token::RParen, "')' after for loop clauses"));
+
+    let body = try!(statement(context));
+    context.pop_scope();
+    Ok(Statement::For{
+        init:       init,
+        condition:  condition,
+        step:       step,
+        body:       box body,
+    })
+}
+
+/**
+ * Parse a statement. Statements that start with a keyword (`return x;`, `int a;`, `while (x)`)
+ * are dispatched on that keyword, and an identifier followed by a colon is a label. Anything else
+ * is an expression followed by a semicolon.
  */
-fn statement_block(context: &mut Context) -> Result<Vec<Statement>, String> {
+fn statement(context: &mut Context) -> Result<Statement, Diagnostic> {
     match context.peek() {
-        Some(Token::LBrace) => {
+        Some(Token::Semicolon) => {
             context.next();
-            let mut statements = vec![];
-            loop {
-                match context.peek() {
-                    Some(Token::RBrace) => {
-                        context.next(); // consume the closing paren
-                        return Ok(statements);
-                    },
+            Ok(Statement::Empty)
+        },
 
-                    Some(_) => {
-                        let statement = try!(statement(context));
-                        statements.push(statement);
-                    },
+        Some(Token::LBrace) => Ok(Statement::Compound(try!(compound_statement(context)))),
 
-                    None => return Err("unterminated statement block".to_string()),
-                }
+        Some(Token::Keyword(Keyword::Return)) => {
+            context.next();
+            if context.peek() == Some(Token::Semicolon) {
+                context.next();
+                return Ok(Statement::Return(None));
             }
+            let expr = try!(expression(context));
+            try!(expect(context, Token::Semicolon, "semicolon after return statement"));
+            Ok(Statement::Return(Some(expr)))
+        },
+
+        Some(Token::Keyword(Keyword::Break)) => {
+            context.next();
+            try!(expect(context, Token::Semicolon, "semicolon after break"));
+            Ok(Statement::Break)
+        },
+
+        Some(Token::Keyword(Keyword::Continue)) => {
+            context.next();
+            try!(expect(context, Token::Semicolon, "semicolon after continue"));
+            Ok(Statement::Continue)
+        },
+
+        Some(Token::Keyword(Keyword::Goto)) => {
+            context.next();
+            let label = match context.next() {
+                Some(Token::Identifier(label)) => label.as_str().to_string(),
+                Some(tok) => return Err(context.error(format!("expected label name after goto, got {:?}", tok))),
+                None => return Err(context.error("expected label name after goto")),
+            };
+            try!(expect(context, Token::Semicolon, "semicolon after goto"));
+            Ok(Statement::Goto(label))
+        },
+
+        Some(Token::Keyword(Keyword::If)) => {
+            context.next();
+            let condition = try!(parenthesized(context, "if"));
+            let then = box try!(statement(context));
+
+            // An `else` belongs to the closest `if`, which is this one: any inner `if` in `then`
+            // would already have taken it.
+            let _else = if context.peek() == Some(Token::Keyword(Keyword::Else)) {
+                context.next();
+                Some(box try!(statement(context)))
+            } else {
+                None
+            };
+            Ok(Statement::If{condition: condition, then: then, _else: _else})
+        },
+
+        Some(Token::Keyword(Keyword::While)) => {
+            context.next();
+            let condition = try!(parenthesized(context, "while"));
+            let body = box try!(statement(context));
+            Ok(Statement::While{condition: condition, body: body})
+        },
+
+        Some(Token::Keyword(Keyword::Do)) => {
+            context.next();
+            let body = box try!(statement(context));
+            try!(expect(context, Token::Keyword(Keyword::While), "'while' in do/while loop"));
+            let condition = try!(parenthesized(context, "while"));
+            try!(expect(context, Token::Semicolon, "semicolon after do/while loop"));
+            Ok(Statement::DoWhile{body: body, condition: condition})
+        },
+
+        Some(Token::Keyword(Keyword::For)) => {
+            context.next();
+            for_statement(context)
+        },
+
+        Some(Token::Keyword(Keyword::Switch)) => {
+            context.next();
+            let value = try!(parenthesized(context, "switch"));
+            let body = box try!(statement(context));
+            Ok(Statement::Switch{value: value, body: body})
+        },
+
+        Some(Token::Keyword(Keyword::Case)) => {
+            context.next();
+            let value = try!(conditional_expression(context));
+            try!(expect(context, Token::Colon, "':' after case value"));
+            Ok(Statement::Case(value, box try!(statement(context))))
+        },
+
+        Some(Token::Keyword(Keyword::Default)) => {
+            context.next();
+            try!(expect(context, Token::Colon, "':' after 'default'"));
+            Ok(Statement::Default(box try!(statement(context))))
         },
 
-        Some(_) => {
-            let statement = try!(statement(context));
-            Ok(vec![statement])
+        Some(Token::Keyword(keyword)) if is_declaration_specifier(keyword) => declaration_statement(context),
+
+        Some(Token::Identifier(label)) => {
+            // Look past the identifier to tell `label:` from an expression starting with a name,
+            // or a declaration starting with a typedef name.
+            context.next();
+            if context.peek() == Some(Token::Colon) {
+                context.next();
+                return Ok(Statement::Labeled(label.as_str().to_string(), box try!(statement(context))));
+            }
+            context.step_back();
+            if context.typedef(label.as_str()).is_some() {
+                return declaration_statement(context);
+            }
+            expression_statement(context)
         },
 
-        None => return Err("expected statement in statement block".to_string()),
+        _ => expression_statement(context),
+    }
+}
+
+/**
+ * Parse a declaration followed by a semicolon. A typedef declares no variables, so it only gives
+ * the struct or union that it defines, if any.
+ */
+fn declaration_statement(context: &mut Context) -> Result<Statement, Diagnostic> {
+    let span = context.peek_span();
+    let base = try!(declaration_specifiers(context));
+    if let Some(_struct) = struct_declaration(context, &base) {
+        return Ok(Statement::Struct(_struct));
+    }
+
+    let first = try!(try!(declarator(context)).declaration(context, &base, span));
+    let declarations = try!(init_declarators(context, &base, first));
+    try!(expect(context, Token::Semicolon, "semicolon after declaration"));
+    if is_typedef(&base) {
+        return Ok(match base {
+            Type::Struct{ref _struct, ..} if _struct.fields.is_some() => Statement::Struct(_struct.clone()),
+            _ => Statement::Empty,
+        });
+    }
+    Ok(Statement::Declaration(declarations))
+}
+
+/**
+ * Parse an expression followed by a semicolon.
+ */
+fn expression_statement(context: &mut Context) -> Result<Statement, Diagnostic> {
+    let expr = try!(expression(context));
+    match context.next() {
+        Some(Token::Semicolon) => Ok(Statement::Expression(expr)),
+        Some(token) => Err(context.error(format!("unexpected token {:?} after expression", token))),
+        None => Err(context.error("expected semicolon after statement")),
+    }
+}
+
+/**
+ * Parse the statements in a `{}` block, including the braces.
+ */
+fn compound_statement(context: &mut Context) -> Result<Vec<Statement>, Diagnostic> {
+    try!(expect(context, Token::LBrace, "'{' at start of block"));
+    context.push_scope();
+    let mut statements = vec![];
+    loop {
+        match context.peek() {
+            Some(Token::RBrace) => {
+                context.next(); // consume the closing brace
+                context.pop_scope();
+                return Ok(statements);
+            },
+
+            Some(_) => {
+                let statement = try!(statement(context));
+                statements.push(statement);
+            },
+
+            None => return Err(context.error("unterminated statement block")),
+        }
     }
 }
 
 /**
- * Parse function definitions such as
+ * Parse the body of a function definition such as
  *
  * ```c
  *      void say_hello() {}
  *      const int number_of_processes() { return 5; }
  * ```
  *
- * into the appropriate ast::Function structures.
- *
- * NOTE: This function assumes that the type declaration has already been parsed, up to and
- * including the left paren of the argument list.
+ * into the appropriate ast::Function structure. `declaration` is everything before the body, and
+ * `parameters` come from its parameter list. Unlike in a prototype, they all need names.
  */
-fn function_definition(context: &mut Context, signature: Declaration) -> Result<Function, String> {
-    /*
-     * Argument parsing
-     */
+fn function_definition(context: &mut Context, declaration: Declaration, parameters: Vec<Parameter>)
+                       -> Result<Function, Diagnostic> {
     let mut arguments: Vec<Declaration> = vec![];
+    for parameter in parameters {
+        match parameter.name {
+            Some(name) => arguments.push(Declaration{
+                _type:          parameter._type,
+                name:           name,
+                initializer:    None,
+            }),
+            None => return Err(Diagnostic::new(parameter.span, "parameter name omitted")),
+        }
+    }
 
-    match context.peek() {
-        Some(Token::RParen) => {
-            // A RParen immediately means the arguments list is empty; the function signature looks
-            // like this (no arguments):
-            //
-            //      int my_function()
-            //
+    let return_type = match declaration._type {
+        Type::Function{returns, ..} => *returns,
+        _ => unreachable!(),
+    };
 
-            context.next(); // Consume the Token::RParen
-        },
+    // The arguments are in scope in the body, where they hide any typedef names they share.
+    context.push_scope();
+    for argument in &arguments {
+        context.declare(&argument.name, None);
+    }
+    let statements = try!(compound_statement(context));
+    context.pop_scope();
+
+    Ok(Function{
+        name:           declaration.name,
+        arguments:      arguments,
+        return_type:    return_type,
+        statements:     statements,
+    })
+}
+
+/**
+ * Return true if `keyword` can appear in the list of specifiers and qualifiers at the start of a
+ * declaration, like the `static const unsigned int` in `static const unsigned int a;`.
+ */
+fn is_declaration_specifier(keyword: Keyword) -> bool {
+    match keyword {
+        // type specifiers
+        Keyword::Void | Keyword::Char | Keyword::Short | Keyword::Int | Keyword::Long |
+        Keyword::Float | Keyword::Double | Keyword::Signed | Keyword::Unsigned | Keyword::Bool |
+        Keyword::Complex | Keyword::Struct | Keyword::Union |
 
-        _ => {
-            // Parse arguments (in this branch, we know there is at least one argument ready to be
-            // parsed).
-            let first_arg = try!(declaration(context));
-            arguments.push(first_arg);
+        // type qualifiers
+        Keyword::Const | Keyword::Volatile | Keyword::Restrict | Keyword::Atomic |
 
-            loop {
-                match context.next() {
-                    Some(Token::RParen) => break,
-                    Some(Token::Comma)  => arguments.push(try!(declaration(context))),
+        // storage classes and function specifiers
+        Keyword::Static | Keyword::Extern | Keyword::Auto | Keyword::Register |
+        Keyword::ThreadLocal | Keyword::Typedef | Keyword::Inline | Keyword::Noreturn => true,
 
-                    Some(tok) => return Err(format!("unexpected token {:?} while parsing function argument list", tok)),
-                    None      => return Err("unexpected EOF when parsing function argument list".to_string()),
+        _ => false,
+    }
+}
+
+/**
+ * Parse the keywords at the start of a declaration into a Type. The last keyword that names a
+ * basic type becomes the `base_name` and every other keyword becomes a modifier, so
+ * `const unsigned long long int` has the base name `int` and the modifiers
+ * `["const", "unsigned", "long", "long"]`. A lone `signed` or `unsigned` means `int`.
+ *
+ * A struct or union specifier like `struct point` or `union { int i; float f; }` names the type
+ * instead, and can't be combined with any of the basic type keywords. So can a typedef name, which
+ * gives the type it stands for with the other keywords added, as `specify` adds them.
+ */
+fn declaration_specifiers(context: &mut Context) -> Result<Type, Diagnostic> {
+    let mut specifiers: Vec<Keyword> = vec![];
+    let mut _struct = None;
+    let mut typedef = None;
+
+    let is_base = |k: &Keyword| match *k {
+        Keyword::Void | Keyword::Char | Keyword::Short | Keyword::Int | Keyword::Long |
+        Keyword::Float | Keyword::Double | Keyword::Bool => true,
+        _ => false,
+    };
+    let is_basic = |k: &Keyword| is_base(k) || *k == Keyword::Signed || *k == Keyword::Unsigned;
+
+    loop {
+        match context.peek() {
+            Some(Token::Keyword(keyword @ Keyword::Struct)) | Some(Token::Keyword(keyword @ Keyword::Union)) => {
+                if _struct.is_some() || typedef.is_some() {
+                    return Err(context.error("two or more data types in declaration specifiers"));
                 }
-            }
-        },
+                context.next();
+                let kind = if keyword == Keyword::Struct { StructKind::Struct } else { StructKind::Union };
+                _struct = Some(try!(struct_specifier(context, kind)));
+            },
+            Some(Token::Keyword(keyword)) if is_declaration_specifier(keyword) => {
+                context.next();
+                specifiers.push(keyword);
+            },
+
+            // An identifier is only a typedef name if the type hasn't been named yet, so that
+            // `int T;` can declare a variable that hides a typedef `T`.
+            Some(Token::Identifier(name)) if _struct.is_none() && typedef.is_none() &&
+                                             !specifiers.iter().any(&is_basic) => {
+                match context.typedef(name.as_str()) {
+                    Some(_type) => typedef = Some(_type.clone()),
+                    None => break,
+                }
+                context.next();
+            },
+            _ => break,
+        }
     }
 
-    /*
-     * Body parsing
-     */
-    let statements = try!(statement_block(context));
+    if let Some(_type) = typedef {
+        if specifiers.iter().any(&is_basic) {
+            return Err(context.error("two or more data types in declaration specifiers"));
+        }
+        return Ok(specify(_type, &specifiers));
+    }
+    if let Some(_struct) = _struct {
+        if specifiers.iter().any(&is_basic) {
+            return Err(context.error("two or more data types in declaration specifiers"));
+        }
+        return Ok(Type::Struct{
+            _struct:    _struct,
+            modifiers:  specifiers.iter().map(|k| k.as_str().to_string()).collect(),
+        });
+    }
 
-    Ok(Function{
-        name:           signature.name,
-        arguments:      arguments,
-        return_type:    signature._type,
-        statements:     statements,
+    if specifiers.is_empty() {
+        return match context.next() {
+            Some(tok) => Err(context.error(format!("expected type at beginning of declaration, got {:?}", tok))),
+            None => Err(context.error("expected type at beginning of declaration")),
+        };
+    }
+
+    let base_name = match specifiers.iter().rposition(&is_base) {
+        Some(index) => specifiers.remove(index).as_str(),
+        None => {
+            if specifiers.contains(&Keyword::Signed) || specifiers.contains(&Keyword::Unsigned) {
+                "int"
+            } else {
+                return Err(context.error("expected a type name in declaration"));
+            }
+        },
+    };
+
+    Ok(Type::Basic{
+        base_name:  base_name.to_string(),
+        modifiers:  specifiers.iter().map(|k| k.as_str().to_string()).collect(),
     })
 }
 
 /**
- * Parse declarations such as
+ * Add the keywords `specifiers` to the type `_type` that a typedef name stands for. Where they go
+ * is the same as if the typedef had been written out in the declaration: a storage class like
+ * `static` ends up with the specifiers at the bottom of the type, while a qualifier applies to the
+ * type itself, so in
  *
  * ```c
- *      const int b
- *      int *a
- *      int c[10]
- *      unsigned char d, e
- *      int f
+ *      typedef int *int_pointer;
+ *      static const int_pointer p;
  * ```
  *
- * into ast::Declaration structs. The rule for this parse looks something like
+ * `p` has the same type as in `static int *const p`.
+ */
+fn specify(_type: Type, specifiers: &[Keyword]) -> Type {
+    let prepend = |modifiers: Vec<String>, keywords: &[Keyword]| {
+        keywords.iter().map(|k| k.as_str().to_string()).chain(modifiers).collect()
+    };
+    match _type {
+        Type::Basic{base_name, modifiers} => Type::Basic{
+            base_name:  base_name,
+            modifiers:  prepend(modifiers, specifiers),
+        },
+        Type::Struct{_struct, modifiers} => Type::Struct{
+            _struct:    _struct,
+            modifiers:  prepend(modifiers, specifiers),
+        },
+        Type::Pointer{to, mut qualifiers} => {
+            let (added, others): (Vec<Keyword>, Vec<Keyword>) = specifiers.iter().cloned().partition(|&k| is_type_qualifier(k));
+            qualifiers.extend(added.iter().map(|k| k.as_str().to_string()));
+            Type::Pointer{to: box specify(*to, &others), qualifiers: qualifiers}
+        },
+        Type::Array{of, length} => Type::Array{of: box specify(*of, specifiers), length: length},
+        Type::Function{returns, parameters, variadic} => Type::Function{
+            returns:    box specify(*returns, specifiers),
+            parameters: parameters,
+            variadic:   variadic,
+        },
+    }
+}
+
+/**
+ * Parse a struct or union specifier, after the `struct` or `union` keyword. It has a tag, a list
+ * of members in braces, or both:
  *
  * ```c
- *      ident+ (asterisk+ ident)? ((comma asterisk* ident)*)
+ *      struct point
+ *      struct point { int x, y; }
+ *      union { int i; struct point p; }
  * ```
  *
- * A handcrafted parser may not be the most understandable way to build this construct :)
+ * Members are declared like variables, without initializers.
+ */
+fn struct_specifier(context: &mut Context, kind: StructKind) -> Result<Struct, Diagnostic> {
+    let tag = match context.peek() {
+        Some(Token::Identifier(tag)) => {
+            context.next();
+            Some(tag.as_str().to_string())
+        },
+        _ => None,
+    };
+    if context.peek() != Some(Token::LBrace) {
+        if tag.is_none() {
+            return match context.next() {
+                Some(tok) => Err(context.error(format!("expected '{{' or tag name after '{}', got {:?}", kind.as_str(), tok))),
+                None => Err(context.error(format!("expected '{{' or tag name after '{}'", kind.as_str()))),
+            };
+        }
+        return Ok(Struct{
+            kind:   kind,
+            tag:    tag,
+            fields: None,
+        });
+    }
+    context.next(); // consume the LBrace
+
+    let mut fields = vec![];
+    loop {
+        match context.peek() {
+            Some(Token::RBrace) => {
+                context.next();
+                break;
+            },
+            Some(_) => {
+                let span = context.peek_span();
+                let base = try!(declaration_specifiers(context));
+                loop {
+                    fields.push(try!(try!(declarator(context)).declaration(context, &base, span)));
+                    if context.peek() != Some(Token::Comma) {
+                        break;
+                    }
+                    context.next();
+                }
+                try!(expect(context, Token::Semicolon, &format!("';' after {} member", kind.as_str())));
+            },
+            None => return Err(context.error(format!("unterminated {} definition", kind.as_str()))),
+        }
+    }
+
+    Ok(Struct{
+        kind:   kind,
+        tag:    tag,
+        fields: Some(fields),
+    })
+}
+
+/**
+ * If the specifiers just parsed, `base`, make up the whole declaration, as in
+ * `struct point { int x, y; };` or `struct list;`, then consume the semicolon and return the
+ * struct or union they declare.
+ */
+fn struct_declaration(context: &mut Context, base: &Type) -> Option<Struct> {
+    match *base {
+        Type::Struct{ref _struct, ..} if context.peek() == Some(Token::Semicolon) => {
+            context.next();
+            Some(_struct.clone())
+        },
+        _ => None,
+    }
+}
+
+/**
+ * Return true if `keyword` is a type qualifier, which can come after the `*` of a pointer as well
+ * as at the start of a declaration.
+ */
+fn is_type_qualifier(keyword: Keyword) -> bool {
+    match keyword {
+        Keyword::Const | Keyword::Volatile | Keyword::Restrict | Keyword::Atomic => true,
+        _ => false,
+    }
+}
+
+/**
+ * Parameter is one parameter in the parameter list of a function declarator. Its name can be
+ * left out, except in a function definition.
+ */
+struct Parameter {
+    name:   Option<String>,
+    _type:  Type,
+    span:   Span, // where the parameter starts, to point at if it needs a name
+}
+
+/**
+ * Derivation is one step in building the type a declarator declares out of the type named by
+ * the specifiers, like the `*` in `int *p` or the `[3]` in `int a[3]`.
+ */
+enum Derivation {
+    Pointer(Vec<String>),           // the qualifiers after the `*`
+    Array(Option<Expression>),      // the length
+    Function(Vec<Parameter>, bool), // the parameters, and whether they end with `...`
+}
+
+/**
+ * Declarator is the part of a declaration about one name, like the `*argv[]` in
+ * `char *argv[]`. Abstract declarators, which are used in casts and prototypes, have no name.
  *
+ * The derivations apply to the specified type in order, so for `*argv[]` they're a `Pointer`
+ * and then an `Array`: an array of pointers.
+ */
+struct Declarator {
+    name:           Option<String>,
+    derivations:    Vec<Derivation>,
+}
+
+impl Declarator {
+    /**
+     * Apply the derivations to `base`, giving the declared type. `span` is where the declaration
+     * starts, for errors about types that C doesn't allow, like functions returning arrays.
+     */
+    fn derive(&self, base: Type, span: Span) -> Result<Type, Diagnostic> {
+        let mut _type = base;
+        for derivation in &self.derivations {
+            _type = match *derivation {
+                Derivation::Pointer(ref qualifiers) => Type::Pointer{
+                    to:         box _type,
+                    qualifiers: qualifiers.clone(),
+                },
+                Derivation::Array(ref length) => {
+                    if let Type::Function{..} = _type {
+                        return Err(Diagnostic::new(span, "declared as an array of functions"));
+                    }
+                    Type::Array{of: box _type, length: length.clone().map(|length| box length)}
+                },
+                Derivation::Function(ref parameters, variadic) => {
+                    match _type {
+                        Type::Array{..} => return Err(Diagnostic::new(span, "function can't return an array")),
+                        Type::Function{..} => return Err(Diagnostic::new(span, "function can't return a function")),
+                        _ => (),
+                    }
+                    Type::Function{
+                        returns:    box _type,
+                        parameters: parameters.iter().map(|p| p._type.clone()).collect(),
+                        variadic:   variadic,
+                    }
+                },
+            };
+        }
+        Ok(_type)
+    }
+
+    /**
+     * Return the declaration this declarator makes with the specified type `base`. Unlike an
+     * abstract declarator, it must name what it declares.
+     */
+    fn declaration(&self, context: &mut Context, base: &Type, span: Span) -> Result<Declaration, Diagnostic> {
+        let name = match self.name {
+            Some(ref name) => name.clone(),
+            None => match context.next() {
+                Some(tok) => return Err(context.error(format!("expected variable name in declaration, got {:?}", tok))),
+                None => return Err(context.error("expected variable name in declaration")),
+            },
+        };
+        Ok(Declaration{
+            name:           name,
+            _type:          try!(self.derive(base.clone(), span)),
+            initializer:    None,
+        })
+    }
+}
+
+/**
+ * Return the token after the one `peek()` would return.
  */
-fn declaration(context: &mut Context) -> Result<Declaration, String> {
+fn peek_second(context: &mut Context) -> Option<Token> {
+    context.next();
+    let token = context.peek();
+    context.step_back();
+    token
+}
 
-    //
-    // parse identifiers until we see
-    //
-    // 1. An asterisk. This means **type** definition is essentially over and a variable name is
-    //    next. Note that it is technically possible for multiple asterisks to appear.
-    // 2. A token we don't recognize. This means the variable declaration is over. Return what we
-    //    have so far.
-    //
+/**
+ * Return true if `token` can start a type name, as in a cast, or the specifiers of a declaration.
+ */
+fn starts_type_name(context: &Context, token: Option<Token>) -> bool {
+    match token {
+        Some(Token::Keyword(keyword)) => is_declaration_specifier(keyword),
+        Some(Token::Identifier(name)) => context.typedef(name.as_str()).is_some(),
+        _ => false,
+    }
+}
 
-    let mut identifiers: Vec<String> = vec![];
+/**
+ * Parse a declarator such as the `*(*fp)(int, char)` in `int *(*fp)(int, char)`. The grammar
+ * (C11 6.7.6) looks something like
+ *
+ * ```c
+ *      declarator := ('*' qualifier*)* direct
+ *      direct     := (ident | '(' declarator ')')? ('[' expression? ']' | '(' parameters ')')*
+ * ```
+ *
+ * The name can always be left out here; it's up to the caller to say whether it should be.
+ */
+fn declarator(context: &mut Context) -> Result<Declarator, Diagnostic> {
+    let mut pointers = vec![];
+    while context.peek() == Some(Token::Operator(Operator::Asterisk)) {
+        context.next();
+        let mut qualifiers = vec![];
+        loop {
+            match context.peek() {
+                Some(Token::Keyword(keyword)) if is_type_qualifier(keyword) => {
+                    context.next();
+                    qualifiers.push(keyword.as_str().to_string());
+                },
+                _ => break,
+            }
+        }
+        pointers.push(Derivation::Pointer(qualifiers));
+    }
 
-    let first_token = context.next();
-    match first_token {
-        Some(Token::Identifier(ident)) => identifiers.push(ident),
-        _ => return Err("expected identifier at beginning of declaration".to_string()),
+    // A paren is a parenthesized declarator unless it starts a parameter list, as in the abstract
+    // declarator `(*)(int)` or `()`.
+    let paren_declarator = context.peek() == Some(Token::LParen) && {
+        let second = peek_second(context);
+        second != Some(Token::RParen) && !starts_type_name(context, second)
     };
+    let inner = match context.peek() {
+        Some(Token::Identifier(name)) => {
+            context.next();
+            Declarator{name: Some(name.as_str().to_string()), derivations: vec![]}
+        },
+        Some(Token::LParen) if paren_declarator => {
+            context.next();
+            let inner = try!(declarator(context));
+            try!(expect(context, Token::RParen, "')' after declarator"));
+            inner
+        },
+        _ => Declarator{name: None, derivations: vec![]},
+    };
+
+    let mut suffixes = vec![];
+    loop {
+        match context.peek() {
+            Some(Token::LSquareBracket) => {
+                context.next();
+                let length = match context.peek() {
+                    Some(Token::RSquareBracket) => None,
+                    _ => Some(try!(assignment_expression(context))),
+                };
+                try!(expect(context, Token::RSquareBracket, "']' after array length"));
+                suffixes.push(Derivation::Array(length));
+            },
+            Some(Token::LParen) => {
+                context.next();
+                let (parameters, variadic) = try!(parameter_list(context));
+                suffixes.push(Derivation::Function(parameters, variadic));
+            },
+            _ => break,
+        }
+    }
+
+    // `*a[3]` is an array of pointers, and `(*a)[3]` is a pointer to an array. The pointers apply
+    // first, then the suffixes from right to left, and then whatever was in parentheses.
+    let mut derivations = pointers;
+    derivations.extend(suffixes.into_iter().rev());
+    derivations.extend(inner.derivations);
+    Ok(Declarator{
+        name:           inner.name,
+        derivations:    derivations,
+    })
+}
+
+/**
+ * Parse the parameters of a function declarator, after the opening paren, up to and including
+ * the closing paren. Return them, and whether they end with `...`.
+ */
+fn parameter_list(context: &mut Context) -> Result<(Vec<Parameter>, bool), Diagnostic> {
+    let mut parameters = vec![];
+
+    // `int main(void)` is a function with no arguments, just like `int main()`.
+    if context.peek() == Some(Token::Keyword(Keyword::Void)) && peek_second(context) == Some(Token::RParen) {
+        context.next();
+    }
+    if context.peek() == Some(Token::RParen) {
+        context.next();
+        return Ok((parameters, false));
+    }
 
     loop {
+        let span = context.peek_span();
+        let base = try!(declaration_specifiers(context));
+        let declarator = try!(declarator(context));
+        parameters.push(Parameter{
+            _type:  try!(declarator.derive(base, span)),
+            name:   declarator.name,
+            span:   span,
+        });
+
         match context.next() {
-            // Keep pushing identifiers until we hit something else. This is the only match arm
-            // that will actually continue the loop.
-            Some(Token::Identifier(ident)) => identifiers.push(ident),
-
-            // This is a case where an asterisk interrupts the stream of tokens. This tells us some
-            // important information about the identifiers we just grabbed. Example:
-            //
-            //      const int *b;
-            //
-            // We can easily infer that the previous token (int) was the base type, the tokens
-            // before that were modifiers, and the token immediately after the asterisk is the
-            // variable name. The last part there is not always true; sometimes multiple asterisks
-            // are chained together as in this declaration:
-            //
-            //      const int ***c;
-            //
-            Some(Token::Operator(Operator::Asterisk)) => {
-                let base_name = identifiers.pop().unwrap();
-                let modifiers = identifiers;
-                let mut pointer_levels = 1;
-                let variable_name: String;
+            Some(Token::RParen) => return Ok((parameters, false)),
+            Some(Token::Comma) if context.peek() == Some(Token::Ellipsis) => {
+                context.next();
+                try!(expect(context, Token::RParen, "')' after '...'"));
+                return Ok((parameters, true));
+            },
+            Some(Token::Comma) => (),
 
-                loop {
-                    // In this loop, we're looking for either another asterisk or an identifier
-                    // (the variable name). This is only a loop because the number of asterisks can
-                    // vary freely.
-                    match context.next() {
-                        Some(Token::Operator(Operator::Asterisk)) => pointer_levels += 1,
-                        Some(Token::Identifier(string)) => {
-                            variable_name = string;
-                            break;
-                        }
-                        _ => return Err("expected either variable name or asterisk after asterisk in declaration".to_string()),
-                    }
-                }
+            Some(tok) => return Err(context.error(format!("unexpected token {:?} while parsing function argument list", tok))),
+            None      => return Err(context.error("unexpected EOF when parsing function argument list")),
+        }
+    }
+}
 
-                // TODO: support for multiple comma-separated declarations and array declarations
-                // (such as [10]).
-                return Ok(Declaration{
-                    _type: Type{
-                        modifiers:      modifiers,
-                        base_name:      base_name,
-                        pointer_levels: pointer_levels,
-                        length:         None,
-                    },
-                    name: variable_name,
-                });
-            }
+/**
+ * Parse a type name, like the `unsigned char *` in the cast `(unsigned char *) p`. This is a
+ * declaration without a name.
+ */
+fn type_name(context: &mut Context) -> Result<Type, Diagnostic> {
+    let span = context.peek_span();
+    let base = try!(declaration_specifiers(context));
+    let declarator = try!(declarator(context));
+    if declarator.name.is_some() {
+        return Err(Diagnostic::new(span, "type name can't declare a variable"));
+    }
+    declarator.derive(base, span)
+}
 
-            // This is a very simple case: a series of identifiers followed by a semicolon or comma
-            // or something like that. We don't really care what the next token is because we might
-            // be inside a function's argument list or part of a global variable declaration.
-            // Example:
-            //
-            //      const unsigned long a;
-            //
-            _ => {
-                context.step_back(); // don't want to absorb the next token if it exists
-
-                if identifiers.len() < 2 {
-                    return Err("expected at least two identifiers before semicolon".to_string());
-                }
+/**
+ * Parse declarations such as
+ *
+ * ```c
+ *      const int b
+ *      int *a, **b = 0
+ *      unsigned char buffer[4] = {0}, *end
+ *      int (*handlers[4])(int signal)
+ * ```
+ *
+ * into ast::Declaration structs. A declaration is some specifiers followed by one or more
+ * declarators separated by commas, where each specifier is one of the keywords accepted by
+ * `is_declaration_specifier`.
+ */
+fn declaration(context: &mut Context) -> Result<Vec<Declaration>, Diagnostic> {
+    let span = context.peek_span();
+    let base = try!(declaration_specifiers(context));
+    let first = try!(try!(declarator(context)).declaration(context, &base, span));
+    init_declarators(context, &base, first)
+}
 
-                // the following comments assume a declaration such as
-                //
-                //      const unsigned int my_integer;
-                //
-                let name = identifiers.pop().unwrap();      // my_integer
-                let base_name = identifiers.pop().unwrap(); // int
-                let modifiers = identifiers;                // [const, unsigned]
-
-                return Ok(Declaration{
-                    _type: Type{
-                        base_name:      base_name,
-                        modifiers:      modifiers,
-                        length:         None,
-                        pointer_levels: 0,
-                    },
-                    name: name,
-                });
+/**
+ * Parse the rest of a declaration after its first declarator, which declared `first`: an
+ * initializer for it, then any more declarators after commas, each of which can have an
+ * initializer too. They all share the specified type `base`, as in `int a = 1, *b, c[3];`.
+ *
+ * Each name is in scope from the end of its declarator, so it's declared before its initializer
+ * is parsed: in `T T = sizeof(T);`, the second `T` hides the typedef name.
+ */
+fn init_declarators(context: &mut Context, base: &Type, first: Declaration) -> Result<Vec<Declaration>, Diagnostic> {
+    let mut declarations = vec![];
+    let mut declaration = first;
+    loop {
+        let typedef = if is_typedef(base) { Some(typedef_type(&declaration._type)) } else { None };
+        context.declare(&declaration.name, typedef);
+        if context.peek() == Some(Token::Operator(Operator::Assign)) {
+            context.next();
+            declaration.initializer = Some(try!(initializer(context)));
+        }
+        declarations.push(declaration);
+
+        if context.peek() != Some(Token::Comma) {
+            return Ok(declarations);
+        }
+        context.next();
+        let span = context.peek_span();
+        declaration = try!(try!(declarator(context)).declaration(context, base, span));
+    }
+}
+
+/**
+ * Parse the initializer after the `=` of a declaration. This is either an expression, or a list
+ * of initializers in braces, each of which may be designated as in `{.x = 1, [2] = 3}`.
+ */
+fn initializer(context: &mut Context) -> Result<Initializer, Diagnostic> {
+    if context.peek() != Some(Token::LBrace) {
+        return Ok(Initializer::Expression(try!(assignment_expression(context))));
+    }
+    context.next(); // consume the LBrace
+
+    let mut items = vec![];
+    loop {
+        // The list can't be empty, but it can end with a comma, as in `{1, 2, 3,}`.
+        if !items.is_empty() && context.peek() == Some(Token::RBrace) {
+            context.next();
+            return Ok(Initializer::List(items));
+        }
+
+        let designators = try!(designators(context));
+        items.push(DesignatedInitializer{
+            designators:    designators,
+            initializer:    try!(initializer(context)),
+        });
+
+        match context.next() {
+            Some(Token::Comma) => (),
+            Some(Token::RBrace) => return Ok(Initializer::List(items)),
+            Some(tok) => return Err(context.error(format!("expected ',' or '}}' in initializer list, got {:?}", tok))),
+            None => return Err(context.error("unterminated initializer list")),
+        }
+    }
+}
+
+/**
+ * Parse the designators at the start of an item in an initializer list, like the `.a[2] =` in
+ * `{.a[2] = 1}`, if there are any.
+ */
+fn designators(context: &mut Context) -> Result<Vec<Designator>, Diagnostic> {
+    let mut designators = vec![];
+    loop {
+        match context.peek() {
+            Some(Token::LSquareBracket) => {
+                context.next();
+                let index = try!(conditional_expression(context));
+                try!(expect(context, Token::RSquareBracket, "']' after array designator"));
+                designators.push(Designator::Index(index));
             },
+            Some(Token::Period) => {
+                context.next();
+                designators.push(Designator::Field(try!(field_name(context))));
+            },
+            _ => break,
         }
     }
+
+    if !designators.is_empty() {
+        try!(expect(context, Token::Operator(Operator::Assign), "'=' after designator"));
+    }
+    Ok(designators)
+}
+
+fn is_function(_type: &Type) -> bool {
+    match *_type {
+        Type::Function{..} => true,
+        _ => false,
+    }
+}
+
+/**
+ * Return true if the specified type `base` is for a typedef, like the `typedef unsigned long` in
+ * `typedef unsigned long size_t;`.
+ */
+fn is_typedef(base: &Type) -> bool {
+    match *base {
+        Type::Basic{ref modifiers, ..} | Type::Struct{ref modifiers, ..} => modifiers.iter().any(|m| m == "typedef"),
+        Type::Pointer{to: ref _type, ..} | Type::Array{of: ref _type, ..} |
+        Type::Function{returns: ref _type, ..} => is_typedef(_type),
+    }
+}
+
+/**
+ * Return the type that a typedef name stands for, given the type `_type` that the typedef declared
+ * it with. That's the same type without the `typedef`, and without any tagged structs or unions it
+ * defined: the typedef itself defines them, so the typedef name only refers to them by their tags.
+ */
+fn typedef_type(_type: &Type) -> Type {
+    match *_type {
+        Type::Basic{ref base_name, ref modifiers} => Type::Basic{
+            base_name:  base_name.clone(),
+            modifiers:  modifiers.iter().filter(|m| *m != "typedef").cloned().collect(),
+        },
+        Type::Struct{ref _struct, ref modifiers} => Type::Struct{
+            _struct:    Struct{
+                kind:   _struct.kind,
+                tag:    _struct.tag.clone(),
+                fields: match _struct.fields {
+                    Some(_) if _struct.tag.is_some() => None,
+                    ref fields => fields.as_ref().map(|fields| fields.iter().map(|field| Declaration{
+                        _type:  typedef_type(&field._type),
+                        ..field.clone()
+                    }).collect()),
+                },
+            },
+            modifiers:  modifiers.iter().filter(|m| *m != "typedef").cloned().collect(),
+        },
+        Type::Pointer{ref to, ref qualifiers} => Type::Pointer{
+            to:         box typedef_type(to),
+            qualifiers: qualifiers.clone(),
+        },
+        Type::Array{ref of, ref length} => Type::Array{of: box typedef_type(of), length: length.clone()},
+        Type::Function{ref returns, ref parameters, variadic} => Type::Function{
+            returns:    box typedef_type(returns),
+            parameters: parameters.clone(),
+            variadic:   variadic,
+        },
+    }
 }
 
 /**
@@ -428,45 +1395,72 @@ fn declaration(context: &mut Context) -> Result<Declaration, String> {
  * ```
  */
 // build::program is our only exported identifier from this module.
-pub fn program(context: &mut Context) -> Result<Program, String> {
+pub fn program(context: &mut Context) -> Result<Program, Diagnostic> {
     let mut program = Program{
         globals: vec![],
         functions: vec![],
+        structs: vec![],
     };
 
     loop {
         match context.peek() {
-            Some(Token::Identifier(_)) => {
-                let declaration = try!(declaration(context));
+            token @ Some(_) if starts_type_name(context, token) => {
+                let span = context.peek_span();
+                let base = try!(declaration_specifiers(context));
 
-                match context.next() {
-                    // Global variable declaration without initialization.
-                    //
-                    //      int num_rows;
-                    //
-                    Some(Token::Semicolon) => {
-                        program.globals.push(declaration);
-                        continue;
+                // A struct or union declared on its own.
+                //
+                //      struct point { int x, y; };
+                //
+                if let Some(_struct) = struct_declaration(context, &base) {
+                    program.structs.push(_struct);
+                    continue;
+                }
+
+                let mut declarator = try!(declarator(context));
+                let declaration = try!(declarator.declaration(context, &base, span));
+
+                match context.peek() {
+                    // Function definition. The parameters are the ones from the last part of the
+                    // declarator, which made it a function.
+                    Some(Token::LBrace) if is_function(&declaration._type) => {
+                        let parameters = match declarator.derivations.pop() {
+                            Some(Derivation::Function(parameters, _)) => parameters,
+                            _ => unreachable!(),
+                        };
+                        program.functions.push(try!(function_definition(context, declaration, parameters)));
                     },
 
-                    // TODO: handle Operator::Assign in this block for global variable
-                    // declarations like
+                    // Global variable declarations, with or without initialization, and function
+                    // declarations.
                     //
+                    //      int num_rows, *rows;
                     //      const int NUM_ROWS = 100;
+                    //      int count_rows(int *table);
                     //
-                    Some(Token::Operator(Operator::Assign)) => {},
+                    _ => {
+                        let declarations = try!(init_declarators(context, &base, declaration));
+                        if context.next() != Some(Token::Semicolon) {
+                            return Err(context.error("expected semicolon after global variable declaration"));
+                        }
 
-                    // Function definition
-                    Some(Token::LParen) => {
-                        let function = try!(function_definition(context, declaration));
-                        program.functions.push(function);
+                        // A typedef only names a type, though it can define a struct as well.
+                        //
+                        //      typedef struct node { struct node *next; } node;
+                        //
+                        if !is_typedef(&base) {
+                            program.globals.extend(declarations);
+                        } else if let Type::Struct{ref _struct, ..} = base {
+                            if _struct.fields.is_some() {
+                                program.structs.push(_struct.clone());
+                            }
+                        }
                     },
-
-                    _ => return Err("expected semicolon after global variable declaration".to_string()),
                 }
             },
 
-            Some(tok)   => return Err(format!("unexpected token {:?}", tok)),
+            Some(tok)   => return Err(Diagnostic::new(context.peek_span(),
+                                                      format!("unexpected token {:?}", tok))),
             None        => return Ok(program),
         }
     }
@@ -1,6 +1,123 @@
 
+use ast::Type;
 use util::StepbackIterator;
-use parser::lexer::Token;
+use parser::lexer::{Lexeme, Token};
+use source::{Diagnostic, Span};
 
-pub type Context = StepbackIterator<Token>;
+/**
+ * Context is the parser's view of the token stream. It hands out bare Tokens so the builder
+ * functions can keep matching on them directly, but it remembers the Span of each one so errors
+ * can say where they happened.
+ *
+ * It also keeps track of the typedef names in scope, since C can't be parsed without knowing
+ * them: `T * x;` declares a pointer if `T` is a typedef name, and multiplies otherwise.
+ */
+pub struct Context {
+    lexemes:    StepbackIterator<Lexeme>,
+    last_span:  Span, // Span of the final token, used for errors at EOF
+    names:      Vec<Vec<(String, Option<Type>)>>, // the names declared in each scope, innermost last
+}
 
+impl Context {
+    /**
+     * Build a Context over `lexemes`. Error tokens have already been reported by the lexer, so
+     * they're dropped here and the parser never sees them.
+     */
+    pub fn new(lexemes: Vec<Lexeme>) -> Self {
+        let lexemes: Vec<Lexeme> = lexemes.into_iter().filter(|l| l.token != Token::Error).collect();
+        let last_span = lexemes.last().map(|l| l.span).unwrap_or_default();
+        Context{
+            lexemes:    StepbackIterator::new(lexemes),
+            last_span:  last_span,
+            names:      vec![builtin_typedefs()],
+        }
+    }
+
+    pub fn next(&mut self) -> Option<Token> {
+        self.lexemes.next().map(|l| l.token)
+    }
+
+    pub fn peek(&self) -> Option<Token> {
+        self.lexemes.peek().map(|l| l.token)
+    }
+
+    pub fn step_back(&mut self) {
+        self.lexemes.step_back()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.lexemes.is_exhausted()
+    }
+
+    /**
+     * Return the Span of the token most recently returned by `next()`. If we've run off the end
+     * of the stream, this is the Span of the last token, which is the best place to point an
+     * "unexpected EOF" error at.
+     */
+    pub fn span(&self) -> Span {
+        match self.lexemes.current() {
+            Some(lexeme) => lexeme.span,
+            None => self.last_span,
+        }
+    }
+
+    /**
+     * Return the Span of the token that `peek()` would return.
+     */
+    pub fn peek_span(&self) -> Span {
+        match self.lexemes.peek() {
+            Some(lexeme) => lexeme.span,
+            None => self.last_span,
+        }
+    }
+
+    /**
+     * Build an error pointing at the token most recently returned by `next()`.
+     */
+    pub fn error<S: Into<String>>(&self, message: S) -> Diagnostic {
+        Diagnostic::new(self.span(), message)
+    }
+
+    /**
+     * Start a new block scope. Names declared inside it are forgotten again by `pop_scope()`.
+     */
+    pub fn push_scope(&mut self) {
+        self.names.push(vec![]);
+    }
+
+    pub fn pop_scope(&mut self) {
+        self.names.pop();
+    }
+
+    /**
+     * Declare `name` in the innermost scope: as a typedef name for `typedef` if there is one, and
+     * otherwise as an ordinary identifier, which hides any typedef name from an outer scope.
+     */
+    pub fn declare(&mut self, name: &str, typedef: Option<Type>) {
+        self.names.last_mut().unwrap().push((name.to_string(), typedef));
+    }
+
+    /**
+     * Return the type that `name` stands for, if it's a typedef name.
+     */
+    pub fn typedef(&self, name: &str) -> Option<&Type> {
+        for scope in self.names.iter().rev() {
+            if let Some(&(_, ref typedef)) = scope.iter().rev().find(|&&(ref n, _)| n == name) {
+                return typedef.as_ref();
+            }
+        }
+        None
+    }
+}
+
+/**
+ * Return the typedef names that rust-cc defines before any source is parsed. `<stdarg.h>` needs
+ * `__builtin_va_list`, since there's no way to write a `va_list` in C.
+ */
+fn builtin_typedefs() -> Vec<(String, Option<Type>)> {
+    let va_list = Type::Basic{
+        base_name:  "__builtin_va_list".to_string(),
+        modifiers:  vec![],
+    };
+    vec![("__builtin_va_list".to_string(), Some(va_list))]
+}
@@ -1,129 +1,556 @@
 
 use ast;
-use parser::lexer::Token;
 use parser::lexer::Context;
 
 /**
- * Extract as much of a number as possible from `chars`, starting at index `pos`. The `pos`
- * parameter will be mutated to point to the first token of the next character after the number.
+ * Extract as much of a number as possible from `context` and return the literal it spells.
  *
- * For example, if you have a string like this: `" 12.3abc"` and you start at `pos=2`, this
- * function will return `2.3` as a token and `pos` will be mutated to point to `a`.
+ * We first grab a whole "preprocessing number" (C11 6.4.8): a digit (or a `.` followed by a
+ * digit) and then everything that could possibly continue a number, including letters and the
+ * signs of exponents. Only then do we figure out what the characters mean. This is how C
+ * compilers are supposed to lex numbers, and it means that garbage like `123abc` or `0x1e+5` is
+ * one bad literal instead of a number followed by something else.
+ *
+ * For example, if you have a string like this: `" 12.3abc"` and you start at the `1`, this
+ * function will complain about the invalid suffix `abc`.
  */
-pub fn number(context: &mut Context) -> Result<Token, String> {
-    let first_ch = context.next().unwrap();
-    let mut seen_decimal = first_ch == '.';
-    let mut literal = String::new();
-    literal.push(first_ch);
+pub fn number(context: &mut Context) -> Result<ast::Number, String> {
+    let start = context.position();
 
     loop {
-        let ch = match context.next() {
-            Some(ch) => ch,
-            None => break,
+        match context.peek() {
+            Some(b'0'...b'9') | Some(b'a'...b'z') | Some(b'A'...b'Z') | Some(b'_') | Some(b'.') => (),
+
+            // A sign is only part of a number when it's in an exponent, like `1e+5` or `0x1p-3`.
+            Some(b'+') | Some(b'-') => match context.peek_back() {
+                Some(b'e') | Some(b'E') | Some(b'p') | Some(b'P') => (),
+                _ => break,
+            },
+
+            // The next character is part of another token.
+            _ => break,
+        }
+        context.next();
+    };
+
+    let literal = context.slice(start, context.position());
+    let is_hex = literal.starts_with("0x") || literal.starts_with("0X");
+    let is_binary = literal.starts_with("0b") || literal.starts_with("0B");
+    let is_float = if is_hex {
+        literal.contains(|c| c == '.' || c == 'p' || c == 'P')
+    } else {
+        !is_binary && literal.contains(|c| c == '.' || c == 'e' || c == 'E')
+    };
+
+    if is_float {
+        float_literal(literal, is_hex)
+    } else {
+        integer_literal(literal)
+    }
+}
+
+/**
+ * Parse an integer literal such as `123`, `0777`, `0xFFu` or `0b101LL`, and work out its type.
+ */
+fn integer_literal(literal: &str) -> Result<ast::Number, String> {
+    use ast::IntType::*;
+
+    let (radix, digits_start) = if literal.starts_with("0x") || literal.starts_with("0X") {
+        (16, 2)
+    } else if literal.starts_with("0b") || literal.starts_with("0B") {
+        (2, 2)
+    } else if literal.starts_with("0") {
+        (8, 0)
+    } else {
+        (10, 0)
+    };
+
+    // Split the literal into its digits and its suffix. Octal literals are allowed to contain the
+    // digits 8 and 9 here so we can give a better error for them below.
+    let body = &literal[digits_start..];
+    let digits_len = body.find(|c: char| !c.is_digit(if radix == 16 { 16 } else { 10 }))
+                         .unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(digits_len);
+
+    if digits.is_empty() {
+        return Err(format!("no digits in integer literal '{}'", literal));
+    }
+
+    let mut value: u64 = 0;
+    for ch in digits.chars() {
+        let digit = match ch.to_digit(radix) {
+            Some(digit) => digit as u64,
+            None => return Err(format!("invalid digit '{}' in {} literal '{}'", ch,
+                                       if radix == 8 { "octal" } else { "binary" }, literal)),
+        };
+        value = match value.checked_mul(radix as u64).and_then(|v| v.checked_add(digit)) {
+            Some(v) => v,
+            None => return Err(format!("integer literal '{}' is too large for any integer type",
+                                       literal)),
         };
+    }
 
-        match ch {
-            '0'...'9' => (),
-            '.' => {
-                // If `seen_decimal` is already true, that means we've already seen a '.' character
-                // in this literal.
-                if seen_decimal {
-                    return Err(format!("two decimals in numeric literal '{}.'", literal));
-                };
-                seen_decimal = true;
-            }
+    let (unsigned, longs) = match integer_suffix(suffix) {
+        Some(s) => s,
+        None => return Err(format!("invalid suffix '{}' on integer literal '{}'", suffix, literal)),
+    };
 
-            // TODO: handle trailing type specifiers like 10f and 50L (which mean float and long
-            // respectively).
-            _ => {
-                // The current character is part of another token. Back up so we don't consume it.
-                context.step_back();
-                break;
+    // The candidate types, in order, from the table in C11 6.4.4.1. Decimal literals without a
+    // `u` suffix never become unsigned.
+    let decimal = radix == 10;
+    let candidates: &[ast::IntType] = match (decimal, unsigned, longs) {
+        (_, true, 0) => &[UnsignedInt, UnsignedLong, UnsignedLongLong],
+        (_, true, 1) => &[UnsignedLong, UnsignedLongLong],
+        (_, true, _) => &[UnsignedLongLong],
+        (true, false, 0) => &[Int, Long, LongLong],
+        (true, false, 1) => &[Long, LongLong],
+        (true, false, _) => &[LongLong],
+        (false, false, 0) => &[Int, UnsignedInt, Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (false, false, 1) => &[Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (false, false, _) => &[LongLong, UnsignedLongLong],
+    };
+
+    match candidates.iter().find(|t| value <= t.max_value()) {
+        Some(&int_type) => Ok(ast::Number::Int(value, int_type)),
+        None => Err(format!("integer literal '{}' is too large for its type", literal)),
+    }
+}
+
+/**
+ * Decode an integer suffix into whether it contains a `u` and how many `l`s it has. The `u` may
+ * come before or after the `l`s, and `ll` must be written `ll` or `LL` (not `lL`). Return None if
+ * the suffix is invalid.
+ */
+fn integer_suffix(suffix: &str) -> Option<(bool, usize)> {
+    let mut rest = suffix;
+    let mut unsigned = false;
+
+    if rest.starts_with(|c| c == 'u' || c == 'U') {
+        unsigned = true;
+        rest = &rest[1..];
+    }
+
+    let longs = if rest.starts_with("ll") || rest.starts_with("LL") {
+        2
+    } else if rest.starts_with(|c| c == 'l' || c == 'L') {
+        1
+    } else {
+        0
+    };
+    rest = &rest[longs..];
+
+    if !unsigned && rest.starts_with(|c| c == 'u' || c == 'U') {
+        unsigned = true;
+        rest = &rest[1..];
+    }
+
+    if rest.is_empty() { Some((unsigned, longs)) } else { None }
+}
+
+/**
+ * Parse a floating literal such as `1.5`, `.5e-3f`, `1e9L` or the hex float `0x1.8p3`.
+ */
+fn float_literal(literal: &str, is_hex: bool) -> Result<ast::Number, String> {
+    // Find where the suffix starts. For decimal floats that's the first letter that isn't part of
+    // the exponent, for hex floats it's the first thing after the (mandatory) binary exponent.
+    let body_len = if is_hex {
+        match literal.find(|c| c == 'p' || c == 'P') {
+            Some(p) => {
+                let after = &literal[p + 1..];
+                let sign_len = if after.starts_with(|c| c == '+' || c == '-') { 1 } else { 0 };
+                let digits = after[sign_len..].find(|c: char| !c.is_digit(10))
+                                              .unwrap_or(after.len() - sign_len);
+                if digits == 0 {
+                    return Err(format!("exponent has no digits in '{}'", literal));
+                }
+                p + 1 + sign_len + digits
             },
+            None => return Err(format!("hexadecimal floating literal '{}' requires an exponent",
+                                       literal)),
         }
-        literal.push(ch);
+    } else {
+        let mut end = literal.find(|c: char| !(c.is_digit(10) || c == '.'))
+                             .unwrap_or(literal.len());
+        if literal[end..].starts_with(|c| c == 'e' || c == 'E') {
+            let after = &literal[end + 1..];
+            let sign_len = if after.starts_with(|c| c == '+' || c == '-') { 1 } else { 0 };
+            let digits = after[sign_len..].find(|c: char| !c.is_digit(10))
+                                          .unwrap_or(after.len() - sign_len);
+            if digits == 0 {
+                return Err(format!("exponent has no digits in '{}'", literal));
+            }
+            end += 1 + sign_len + digits;
+        }
+        end
     };
 
-    // We now have the literal contained in `literal`. Parse it into an instance of the Number
-    // enum. Our variable `seen_decimal` tells us whether the number should be parsed as an int64
-    // or a float64.
-    if seen_decimal {
-        if literal == "." {
-            return Ok(Token::Period);
-        }
+    let (body, suffix) = literal.split_at(body_len);
+    if body.matches('.').count() > 1 {
+        return Err(format!("too many decimal points in number '{}'", literal));
+    }
+
+    let float_type = match suffix {
+        "" => ast::FloatType::Double,
+        "f" | "F" => ast::FloatType::Float,
+        "l" | "L" => ast::FloatType::LongDouble,
+        _ => return Err(format!("invalid suffix '{}' on floating literal '{}'", suffix, literal)),
+    };
 
-        let f = match literal.parse::<f64>() {
+    let value = if is_hex {
+        let mantissa = &body[2..body.find(|c| c == 'p' || c == 'P').unwrap()];
+        if !mantissa.chars().all(|c| c.is_digit(16) || c == '.') || mantissa == "." {
+            return Err(format!("bad hexadecimal floating literal '{}'", literal));
+        }
+        hex_float_value(&body[2..])
+    } else {
+        // Rust won't parse "1." or "1.e5", so give it a zero after the decimal point.
+        match body.replace(".e", ".0e").replace(".E", ".0E").parse::<f64>() {
             Ok(f) => f,
             Err(_) => return Err(format!("bad floating point literal '{}'", literal)),
-        };
-        Ok(Token::Number(ast::Number::Float(f)))
+        }
+    };
+
+    let value = match float_type {
+        ast::FloatType::Float => value as f32 as f64,
+        _ => value,
+    };
+    if value.is_infinite() {
+        return Err(format!("floating literal '{}' is out of range for its type", literal));
+    }
+
+    Ok(ast::Number::Float(value, float_type))
+}
+
+/**
+ * Compute the value of a hex float such as `1.8p3` (the `0x` has already been stripped off).
+ */
+fn hex_float_value(body: &str) -> f64 {
+    let p = body.find(|c| c == 'p' || c == 'P').unwrap();
+    let (mantissa, exponent) = (&body[..p], &body[p + 1..]);
+    let mut exponent: i32 = exponent.parse().unwrap_or(if exponent.starts_with('-') {
+        i32::min_value() / 2
     } else {
-        let i = match literal.parse::<i64>() {
-            Ok(i) => i,
-            Err(_) => return Err(format!("bad integer literal '{}'", literal)),
-        };
-        Ok(Token::Number(ast::Number::Int(i)))
+        i32::max_value() / 2
+    });
+
+    // Accumulate the hex digits into an integer, keeping track of how far the binary point has to
+    // move. Once we have more digits than a u64 can hold, the remaining ones can't change the
+    // (double precision) result, so we only count them.
+    let mut value: u64 = 0;
+    let mut seen_point = false;
+    for ch in mantissa.chars() {
+        if ch == '.' {
+            seen_point = true;
+            continue;
+        }
+        let digit = ch.to_digit(16).unwrap() as u64;
+        if value >> 60 == 0 {
+            value = value * 16 + digit;
+            if seen_point {
+                exponent = exponent.saturating_sub(4);
+            }
+        } else if !seen_point {
+            exponent = exponent.saturating_add(4);
+        }
     }
+
+    // Scale in two steps so that large exponents on small mantissas (and vice versa) don't
+    // overflow or underflow on the way.
+    let half = exponent / 2;
+    value as f64 * 2f64.powi(half) * 2f64.powi(exponent - half)
 }
 
 /**
- * Lex as much of an identifer as possible from `chars`. Identifiers match the following regex: `[A-Za-z_][A-Za-z0-9_]*`
+ * Lex as much of an identifer as possible from `context`. Identifiers match the following regex:
+ * `[A-Za-z_][A-Za-z0-9_]*`
  *
- * This function assumes that the first character class has already been matched.
+ * This function assumes that the first character class has already been matched. The identifier
+ * is returned as a slice of the source; nothing is copied.
  */
-pub fn identifier(context: &mut Context) -> String {
-    let mut literal = String::new();
+pub fn identifier<'a>(context: &mut Context<'a>) -> &'a str {
+    let start = context.position();
 
     // The current character in the iterator we know is good (in the character class [A-Za-z_])
-    literal.push(context.next().unwrap());
+    context.next();
 
     loop {
-        let ch = match context.next() {
+        match context.peek() {
+            Some(b'A'...b'Z') | Some(b'a'...b'z') | Some(b'0'...b'9') | Some(b'_') => {
+                context.next();
+            },
+            // The character is part of another token.
+            _ => break,
+        };
+    }
+    context.slice(start, context.position())
+}
+
+/**
+ * Lex a string literal and encode it according to `encoding`.
+ *
+ * NOTE: This function assumes that the first quote (and any encoding prefix) has been seen
+ * already.
+ */
+pub fn string(context: &mut Context, encoding: ast::Encoding) -> Result<ast::StringLiteral, String> {
+    let mut units = vec![];
+    let narrow = encoding == ast::Encoding::Char || encoding == ast::Encoding::Utf8;
+
+    loop {
+        let ch = match context.peek() {
             Some(ch) => ch,
-            None => break,
+            None => return Err("unterminated string literal".to_string()),
         };
 
         match ch {
-            'A'...'Z'|'a'...'z'|'0'...'9'|'_' => literal.push(ch),
+            b'"' => {
+                context.next();
+                return Ok(ast::StringLiteral{
+                    encoding:   encoding,
+                    units:      units,
+                });
+            },
+            b'\n' => return Err("unterminated string literal".to_string()),
+            b'\\' => {
+                context.next();
+                match escape(context) {
+                    Ok(Escape::CodeUnit(value)) if value <= max_code_unit(encoding) => units.push(value),
+                    Ok(Escape::CodeUnit(_)) => {
+                        skip_rest_of_literal(context, b'"');
+                        return Err("escape sequence out of range for string literal".to_string());
+                    },
+                    Ok(Escape::CodePoint(c)) => encode(&mut units, encoding, c),
+                    Err(e) => {
+                        skip_rest_of_literal(context, b'"');
+                        return Err(e);
+                    },
+                }
+            },
+
+            // The source is UTF-8 already, so narrow strings can take its bytes as they are.
+            _ if ch < 0x80 || narrow => {
+                context.next();
+                units.push(ch as u32);
+            },
             _ => {
-                // The character is part of another token. Back up so we don't consume it.
-                context.step_back();
-                break;
-            }
-        };
+                let c = context.next_char().unwrap();
+                encode(&mut units, encoding, c);
+            },
+        }
     }
-    return literal;
 }
 
 /**
- * Lex a string from `chars` starting at `pos`.
+ * Return the largest code unit a string with this encoding can hold.
+ */
+fn max_code_unit(encoding: ast::Encoding) -> u32 {
+    match encoding {
+        ast::Encoding::Char | ast::Encoding::Utf8 => 0xff,
+        ast::Encoding::Char16 => 0xffff,
+        ast::Encoding::Char32 | ast::Encoding::Wide => 0xffffffff,
+    }
+}
+
+/**
+ * Append the code units for `ch` in the given encoding: UTF-8 bytes for narrow strings, UTF-16
+ * for `u""` strings and the code point itself for `U""` and `L""` strings.
+ */
+pub fn encode(units: &mut Vec<u32>, encoding: ast::Encoding, ch: char) {
+    match encoding {
+        ast::Encoding::Char | ast::Encoding::Utf8 => {
+            let mut buf = [0; 4];
+            units.extend(ch.encode_utf8(&mut buf).bytes().map(|b| b as u32));
+        },
+        ast::Encoding::Char16 => {
+            let mut buf = [0; 2];
+            units.extend(ch.encode_utf16(&mut buf).iter().map(|&u| u as u32));
+        },
+        ast::Encoding::Char32 | ast::Encoding::Wide => units.push(ch as u32),
+    }
+}
+
+/**
+ * Escape is the value of one backslash escape sequence.
  *
- * NOTE: This function assumes that the first quote has been seen already.
+ * Octal and hex escapes name a code unit directly (`'\xff'` is the byte 0xff no matter what the
+ * encoding is), while the simple escapes and universal character names name a character that
+ * still has to be encoded.
  */
-pub fn string(context: &mut Context) -> Result<String, String> {
-    let mut literal = String::new();
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Escape {
+    CodeUnit(u32),
+    CodePoint(char),
+}
+
+/**
+ * Lex an escape sequence such as `\n`, `\101`, `\x41` or `\u00e9`.
+ *
+ * NOTE: This function assumes that the backslash has been seen already.
+ */
+pub fn escape(context: &mut Context) -> Result<Escape, String> {
+    let ch = match context.peek() {
+        Some(ch) => ch,
+        None => return Err("EOF in escape sequence".to_string()),
+    };
+    context.next();
+
+    let hex_digit = |context: &Context| context.peek().and_then(|c| (c as char).to_digit(16));
+
+    Ok(Escape::CodePoint(match ch {
+        b'\'' | b'"' | b'?' | b'\\' => ch as char,
+        b'a' => '\x07',
+        b'b' => '\x08',
+        b'f' => '\x0c',
+        b'n' => '\n',
+        b'r' => '\r',
+        b't' => '\t',
+        b'v' => '\x0b',
+
+        // Octal escapes are one to three octal digits.
+        b'0'...b'7' => {
+            let mut value = (ch - b'0') as u32;
+            for _ in 0..2 {
+                match context.peek() {
+                    Some(digit @ b'0'...b'7') => {
+                        value = value * 8 + (digit - b'0') as u32;
+                        context.next();
+                    },
+                    _ => break,
+                }
+            }
+            return Ok(Escape::CodeUnit(value));
+        },
+
+        // Hex escapes keep going for as long as there are hex digits.
+        b'x' => {
+            let mut value: u32 = 0;
+            let mut digits = 0;
+            while let Some(digit) = hex_digit(context) {
+                value = match value.checked_mul(16) {
+                    Some(v) => v + digit,
+                    None => return Err("hex escape sequence out of range".to_string()),
+                };
+                digits += 1;
+                context.next();
+            }
+            if digits == 0 {
+                return Err("\\x used with no following hex digits".to_string());
+            }
+            return Ok(Escape::CodeUnit(value));
+        },
+
+        // Universal character names: `\u` takes exactly four hex digits and `\U` takes eight.
+        b'u' | b'U' => {
+            let ch = ch as char;
+            let len = if ch == 'u' { 4 } else { 8 };
+            let mut value: u32 = 0;
+            for _ in 0..len {
+                match hex_digit(context) {
+                    Some(digit) => {
+                        value = value * 16 + digit;
+                        context.next();
+                    },
+                    None => return Err(format!("incomplete universal character name \\{}", ch)),
+                }
+            }
+
+            // C11 6.4.3: a universal character name can't name a surrogate or anything in the
+            // basic character set other than `$`, `@` and `` ` ``.
+            if value < 0xa0 && value != 0x24 && value != 0x40 && value != 0x60 {
+                return Err(format!("universal character name \\{}{:0width$x} names a basic \
+                                    character", ch, value, width = len));
+            }
+            match ::std::char::from_u32(value) {
+                Some(c) => c,
+                None => return Err(format!("\\{}{:0width$x} is not a valid universal character",
+                                           ch, value, width = len)),
+            }
+        },
+
+        _ => {
+            context.step_back();
+            let ch = context.next_char().unwrap();
+            return Err(format!("Unrecognized escape sequence \\{}", ch));
+        },
+    }))
+}
+
+/**
+ * Lex a character constant such as `'a'`, `'\n'` or `'ab'` and return its value.
+ *
+ * Character constants have type `int`. A constant holding a single byte gets the value of that
+ * byte converted to a (signed) `char`, so `'\xff'` is -1. Constants holding several bytes (either
+ * several characters, or characters that take more than one byte in UTF-8) are "multi-character
+ * constants", whose value is implementation-defined. Like GCC and clang, we pack the bytes into
+ * the int big-endian, keeping only the last four.
+ *
+ * NOTE: This function assumes that the opening quote has been seen already.
+ */
+pub fn character(context: &mut Context) -> Result<i32, String> {
+    let mut bytes: Vec<u32> = vec![];
 
     loop {
-        let ch = match context.next() {
+        let ch = match context.peek() {
             Some(ch) => ch,
-            None => return Err(format!("unterminated string literal {:?}", literal)),
+            None => return Err("unterminated character constant".to_string()),
         };
 
         match ch {
-            '"' => return Ok(literal),
-            '\\' => {
-                // the next character must be an escape sequence
-                match context.peek() {
-                    Some('"') => literal.push('"'),
-                    Some('n') => literal.push('\n'),
-                    Some('r') => literal.push('\r'),
-                    Some('\\') => literal.push('\\'),
-                    None => return Err(format!("EOF while scanning string literal {:?}", literal)),
-                    Some(c) => return Err(format!("Unrecognized escape sequence \\{}", c)),
-                };
+            b'\'' => {
+                context.next();
+                break;
+            },
+            b'\n' => return Err("unterminated character constant".to_string()),
+            b'\\' => {
+                context.next();
+                match escape(context) {
+                    Ok(Escape::CodeUnit(value)) if value <= 0xff => bytes.push(value),
+                    Ok(Escape::CodeUnit(_)) => {
+                        skip_rest_of_literal(context, b'\'');
+                        return Err("escape sequence out of range for character constant".to_string());
+                    },
+                    Ok(Escape::CodePoint(c)) => encode(&mut bytes, ast::Encoding::Char, c),
+                    Err(e) => {
+                        skip_rest_of_literal(context, b'\'');
+                        return Err(e);
+                    },
+                }
+            },
+            // Like in narrow strings, non-ASCII characters are their UTF-8 bytes.
+            _ => {
+                context.next();
+                bytes.push(ch as u32);
+            },
+        }
+    }
+
+    match bytes.len() {
+        0 => Err("empty character constant".to_string()),
+        1 => Ok(bytes[0] as u8 as i8 as i32),
+        _ => Ok(bytes.iter().fold(0u32, |value, &b| (value << 8) | b) as i32),
+    }
+}
+
+/**
+ * After an error in the middle of a string or character literal, skip past the rest of it so that
+ * lexing can carry on after the closing `quote`. A literal can't span lines, so we also stop
+ * (without consuming it) at a newline.
+ */
+fn skip_rest_of_literal(context: &mut Context, quote: u8) {
+    loop {
+        match context.peek() {
+            Some(b'\n') | None => return,
+            Some(b'\\') => {
+                context.next();
+                if context.peek() != Some(b'\n') {
+                    context.next();
+                }
+            },
+            Some(ch) => {
                 context.next();
+                if ch == quote {
+                    return;
+                }
             },
-            _ => literal.push(ch),
         }
     }
 }
@@ -1,31 +1,137 @@
 
 use ast;
-use util;
+use source::{Diagnostic, FileId, Span};
+use util::intern::{StringId, Symbol};
 
 mod lex_subroutines;
+mod phases;
 
-pub type Context = util::StepbackIterator<char>;
+pub use self::lex_subroutines::encode;
+
+/**
+ * Context is the lexer's cursor into the source code. It walks the bytes of the source directly,
+ * so lexing never copies the input. Everything that isn't ASCII lives inside a comment, string or
+ * character constant, and those are the only places that need to care about UTF-8.
+ */
+pub struct Context<'a> {
+    src:    &'a str,
+    bytes:  &'a [u8],
+    pos:    usize,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Context{
+            src:    src,
+            bytes:  src.as_bytes(),
+            pos:    0,
+        }
+    }
+
+    /**
+     * Return the next byte and advance past it.
+     */
+    #[inline]
+    pub fn next(&mut self) -> Option<u8> {
+        let b = self.bytes.get(self.pos).cloned();
+        if b.is_some() {
+            self.pos += 1;
+        }
+        b
+    }
+
+    /**
+     * Return the next byte without advancing.
+     */
+    #[inline]
+    pub fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).cloned()
+    }
+
+    /**
+     * Return the byte most recently returned by `next()`.
+     */
+    #[inline]
+    pub fn peek_back(&self) -> Option<u8> {
+        if self.pos == 0 { None } else { Some(self.bytes[self.pos - 1]) }
+    }
+
+    /**
+     * Move one byte backward. This has an effect opposite that of `next()`.
+     */
+    #[inline]
+    pub fn step_back(&mut self) {
+        if self.pos > 0 { self.pos -= 1; }
+    }
+
+    /**
+     * Return and advance past the next whole (possibly multi-byte) character.
+     */
+    pub fn next_char(&mut self) -> Option<char> {
+        let ch = self.src[self.pos..].chars().next();
+        if let Some(ch) = ch {
+            self.pos += ch.len_utf8();
+        }
+        ch
+    }
+
+    /**
+     * Return the byte offset of the next byte `next()` will return.
+     */
+    #[inline]
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /**
+     * Return the source between two byte offsets.
+     */
+    pub fn slice(&self, start: usize, end: usize) -> &'a str {
+        &self.src[start..end]
+    }
+}
+
+/**
+ * Options controls the parts of lexing that are off by default.
+ */
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct Options {
+    /// Replace trigraphs like `??=` and `??/` before lexing, as C11 5.2.1.1 describes.
+    pub trigraphs:  bool,
+
+    /// Accept the digraphs `<:`, `:>`, `<%`, `%>`, `%:` and `%:%:` as spellings of `[`, `]`,
+    /// `{`, `}`, `#` and `##`.
+    pub digraphs:   bool,
+}
 
 /**
  * Each of the variants in this enum represents one kind of C token.
  *
- * This enum contains no positional data.
+ * This enum contains no positional data. The lexer pairs each Token with its Span in a Lexeme.
  */
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub enum Token {
-    /// String is a literal pulled directly from source, however, it will probably have some
-    /// special processing done for escape sequences. Example: `"hello"`
-    String(String),
+    /// String is a literal pulled directly from source, with its escape sequences processed and
+    /// its characters encoded as its prefix asks. Examples: `"hello"` or `L"hello"`. Adjacent
+    /// literals are concatenated by the parser, not the lexer.
+    String(StringId),
 
-    /// Character is a single-byte literal from the source code. Examples: `'c'` or `'\n'`
-    Character(char),
+    /// Character is a character constant from the source code, already evaluated to its `int`
+    /// value. Examples: `'c'`, `'\n'` or the multi-character constant `'ab'`.
+    Character(i32),
 
     /// Number is also a literal pulled directly from source. Examples: `98` or `3.14`
     Number(ast::Number),
 
-    /// Identifier could be a function call or variable, or even type declaration. Example: `main`
-    /// or `float`.
-    Identifier(String),
+    /// Identifier could be a function call or variable name. Example: `main` or `argc`.
+    Identifier(Symbol),
+
+    /// Keyword is one of the reserved words of C. Example: `int` or `while`.
+    Keyword(Keyword),
+
+    /// Error stands in for a piece of source the lexer couldn't make sense of, like a stray `$` or
+    /// a bad literal. The lexer reports a Diagnostic for it (with the same Span) and carries on.
+    Error,
 
     /// Operators have to be handled with correct precedence, but that's a problem for the parser.
     /// The lexer is only concerned with defining which Operators exist. Operators are either one
@@ -37,29 +143,81 @@ pub enum Token {
      */
     Comma,
     Period,
+    Arrow,      // ->
+    Question,   // ?
+    Colon,
+    Ellipsis,   // ...
+    Hash,       // #, which only means something to the preprocessor
+    HashHash,   // ##
     Semicolon,
     LParen, RParen,
     LBrace, RBrace,
     LSquareBracket, RSquareBracket,
 }
 
+/**
+ * Lexeme is a Token together with the Span of source code it was lexed from. This is what the
+ * lexer actually produces; the parser uses the Spans to point at the source in its errors.
+ */
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Lexeme {
+    pub token:      Token,
+    pub span:       Span,
+
+    /// True if this is the first token on its line. The preprocessor needs this to recognize
+    /// directives, which are a `#` at the start of a line.
+    pub line_start: bool,
+
+    /// True if there was whitespace (or a comment) right before this token. This matters to the
+    /// preprocessor when it turns tokens back into text, and for telling `#define f(x)` from
+    /// `#define f (x)`.
+    pub leading_space: bool,
+}
+
 /**
  * These have a direct correspondence to the C operators of the same name.
- *
- * TODO: Bitwise and comparison operators.
  */
-#[derive(Clone,Debug,PartialEq)]
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub enum Operator {
     /// Asterisk can be either multiplication or dereference, depending on parse context.
-    Asterisk,  // *
-    Add,       // +
-    Subtract,  // -
-    Divide,    // /
-    Modulo,    // %
-    And,       // &&
-    Or,        // ||
-    Assign,    // =
-    Reference, // &
+    Asterisk,         // *
+    Add,              // +
+    Subtract,         // -
+    Divide,           // /
+    Modulo,           // %
+    Increment,        // ++
+    Decrement,        // --
+
+    And,              // &&
+    Or,               // ||
+    Not,              // !
+
+    /// Ampersand can be either bitwise and or "address of", depending on parse context.
+    Ampersand,        // &
+    BitwiseOr,        // |
+    BitwiseXor,       // ^
+    BitwiseNot,       // ~
+    ShiftLeft,        // <<
+    ShiftRight,       // >>
+
+    Equal,            // ==
+    NotEqual,         // !=
+    Less,             // <
+    LessEqual,        // <=
+    Greater,          // >
+    GreaterEqual,     // >=
+
+    Assign,           // =
+    AddAssign,        // +=
+    SubtractAssign,   // -=
+    MultiplyAssign,   // *=
+    DivideAssign,     // /=
+    ModuloAssign,     // %=
+    AndAssign,        // &=
+    OrAssign,         // |=
+    XorAssign,        // ^=
+    ShiftLeftAssign,  // <<=
+    ShiftRightAssign, // >>=
 }
 
 impl Operator {
@@ -72,111 +230,577 @@ impl Operator {
         use self::Operator::*;
 
         Some(match s {
+            "*" => Asterisk,
             "+" => Add,
             "-" => Subtract,
-            "*" => Asterisk,
             "/" => Divide,
+            "%" => Modulo,
+            "++" => Increment,
+            "--" => Decrement,
             "&&" => And,
             "||" => Or,
+            "!" => Not,
+            "&" => Ampersand,
+            "|" => BitwiseOr,
+            "^" => BitwiseXor,
+            "~" => BitwiseNot,
+            "<<" => ShiftLeft,
+            ">>" => ShiftRight,
+            "==" => Equal,
+            "!=" => NotEqual,
+            "<" => Less,
+            "<=" => LessEqual,
+            ">" => Greater,
+            ">=" => GreaterEqual,
             "=" => Assign,
+            "+=" => AddAssign,
+            "-=" => SubtractAssign,
+            "*=" => MultiplyAssign,
+            "/=" => DivideAssign,
+            "%=" => ModuloAssign,
+            "&=" => AndAssign,
+            "|=" => OrAssign,
+            "^=" => XorAssign,
+            "<<=" => ShiftLeftAssign,
+            ">>=" => ShiftRightAssign,
             _ => return None,
         })
     }
 }
 
 /**
- * Convert from a str to a vector of Tokens. Handle comments correctly as part of lexing.
+ * The keywords of C11 (6.4.1). These can't be used as identifiers.
+ */
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Keyword {
+    Auto,
+    Break,
+    Case,
+    Char,
+    Const,
+    Continue,
+    Default,
+    Do,
+    Double,
+    Else,
+    Enum,
+    Extern,
+    Float,
+    For,
+    Goto,
+    If,
+    Inline,
+    Int,
+    Long,
+    Register,
+    Restrict,
+    Return,
+    Short,
+    Signed,
+    Sizeof,
+    Static,
+    Struct,
+    Switch,
+    Typedef,
+    Union,
+    Unsigned,
+    Void,
+    Volatile,
+    While,
+    Alignas,
+    Alignof,
+    Atomic,
+    Bool,
+    Complex,
+    Generic,
+    Imaginary,
+    Noreturn,
+    StaticAssert,
+    ThreadLocal,
+}
+
+impl Keyword {
+    /**
+     * Convert from the spelling of a keyword to a Keyword, or return None if `s` isn't a keyword.
+     * For example, this function would convert from `"while"` to `Keyword::While`.
+     */
+    pub fn from_str(s: &str) -> Option<Keyword> {
+        use self::Keyword::*;
+
+        Some(match s {
+            "auto" => Auto,
+            "break" => Break,
+            "case" => Case,
+            "char" => Char,
+            "const" => Const,
+            "continue" => Continue,
+            "default" => Default,
+            "do" => Do,
+            "double" => Double,
+            "else" => Else,
+            "enum" => Enum,
+            "extern" => Extern,
+            "float" => Float,
+            "for" => For,
+            "goto" => Goto,
+            "if" => If,
+            "inline" => Inline,
+            "int" => Int,
+            "long" => Long,
+            "register" => Register,
+            "restrict" => Restrict,
+            "return" => Return,
+            "short" => Short,
+            "signed" => Signed,
+            "sizeof" => Sizeof,
+            "static" => Static,
+            "struct" => Struct,
+            "switch" => Switch,
+            "typedef" => Typedef,
+            "union" => Union,
+            "unsigned" => Unsigned,
+            "void" => Void,
+            "volatile" => Volatile,
+            "while" => While,
+            "_Alignas" => Alignas,
+            "_Alignof" => Alignof,
+            "_Atomic" => Atomic,
+            "_Bool" => Bool,
+            "_Complex" => Complex,
+            "_Generic" => Generic,
+            "_Imaginary" => Imaginary,
+            "_Noreturn" => Noreturn,
+            "_Static_assert" => StaticAssert,
+            "_Thread_local" => ThreadLocal,
+            _ => return None,
+        })
+    }
+
+    /**
+     * Return the spelling of the keyword, such as `"while"` for `Keyword::While`.
+     */
+    pub fn as_str(&self) -> &'static str {
+        use self::Keyword::*;
+
+        match *self {
+            Auto => "auto",
+            Break => "break",
+            Case => "case",
+            Char => "char",
+            Const => "const",
+            Continue => "continue",
+            Default => "default",
+            Do => "do",
+            Double => "double",
+            Else => "else",
+            Enum => "enum",
+            Extern => "extern",
+            Float => "float",
+            For => "for",
+            Goto => "goto",
+            If => "if",
+            Inline => "inline",
+            Int => "int",
+            Long => "long",
+            Register => "register",
+            Restrict => "restrict",
+            Return => "return",
+            Short => "short",
+            Signed => "signed",
+            Sizeof => "sizeof",
+            Static => "static",
+            Struct => "struct",
+            Switch => "switch",
+            Typedef => "typedef",
+            Union => "union",
+            Unsigned => "unsigned",
+            Void => "void",
+            Volatile => "volatile",
+            While => "while",
+            Alignas => "_Alignas",
+            Alignof => "_Alignof",
+            Atomic => "_Atomic",
+            Bool => "_Bool",
+            Complex => "_Complex",
+            Generic => "_Generic",
+            Imaginary => "_Imaginary",
+            Noreturn => "_Noreturn",
+            StaticAssert => "_Static_assert",
+            ThreadLocal => "_Thread_local",
+        }
+    }
+}
+
+/**
+ * Convert from a str to a vector of Lexemes. Handle comments correctly as part of lexing.
  *
- * For example, the string `", ( {"` would be transformed into the Vector of Tokens
+ * For example, the string `", ( {"` would be transformed into Lexemes holding the Tokens
  * `vec![Comma, LParen, LBrace]`.
  *
- * The result of this function is just a sequence of Token without hierarchy. These Tokens should
- * be parsed to build a walkable AST.
+ * The result of this function is just a sequence of Tokens without hierarchy. These Tokens should
+ * be parsed to build a walkable AST. Every Span produced here points into file 0. If the source
+ * contains any lexical errors, the first one is returned; use `lex_file` to get all of them.
+ */
+pub fn lex(s: &str) -> Result<Vec<Lexeme>, Diagnostic> {
+    let (lexemes, mut errors) = lex_file(s, 0, Options::default());
+    if errors.is_empty() {
+        Ok(lexemes)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/**
+ * Return `text` as the lexer sees it, after translation phases 1 and 2. The preprocessor uses
+ * this to get the spelling of a token whose source contains a spliced line.
+ */
+pub fn translated(text: &str, options: Options) -> String {
+    phases::translate(text, options.trigraphs).text.into_owned()
+}
+
+/**
+ * Lex `s`, pointing every Span into `file`.
+ *
+ * The source first goes through translation phases 1 and 2 (see `phases.rs`), so line endings are
+ * normalized and spliced lines joined before any tokens are made. Spans still point at the
+ * physical lines of `s`.
+ *
+ * This never gives up: each lexical error is recorded as a Diagnostic, an Error token is put in
+ * its place, and lexing continues after it. That way a file with five stray characters gets five
+ * errors in one go instead of needing five compiles.
  */
-pub fn lex(s: &str) -> Result<Vec<Token>, String> {
-    let chars:Vec<char> = s.chars().collect();
-    let mut context = Context::new(chars);
-    let mut tokens = vec![];
-
-    // use an anonymous scope here so `push_tok` is dropped before we `Ok(tokens)`. Why? Because
-    // for some reason, the drop keyword wasn't actually dropping `push_tok`.
-    {
-        let mut push_tok = |tok| {
-            // debug printing can be added here to easily record
-            //
-            // 1. what new tokens are pushed.
-            // 2. when they were pushed, relative to the other tokens.
-            //
-            tokens.push(tok);
+pub fn lex_file(s: &str, file: FileId, options: Options) -> (Vec<Lexeme>, Vec<Diagnostic>) {
+    let translated = phases::translate(s, options.trigraphs);
+    let mut context = Context::new(&translated.text);
+    let mut lines = LineCounter::new(s, file, &translated);
+
+    // Real C code averages somewhere around one token for every five or six bytes.
+    let mut tokens = Vec::with_capacity(s.len() / 5);
+    let mut errors = vec![];
+    let mut line_start = true;
+    let mut leading_space = false;
+
+    // iterate through bytes and process tokens as we go
+    loop {
+        let start = context.position();
+        let ch = match context.next() {
+            Some(ch) => ch,
+            None => break,
         };
 
-        // iterate through chars and process tokens as we go
-        loop {
-            let ch = match context.next() {
-                Some(ch) => ch,
-                None => break,
-            };
+        let token = match ch {
+            // ignore whitespace
+            b'\n' => {
+                line_start = true;
+                leading_space = true;
+                continue;
+            },
+            b' '|b'\t'|b'\x0b'|b'\x0c' => {
+                leading_space = true;
+                continue;
+            },
 
-            match ch {
-                '0'...'9'|'.' => {
+            b'a'...b'z'|b'A'...b'Z'|b'_' => {
+                context.step_back();
+                let identifier = lex_subroutines::identifier(&mut context);
+
+                // An identifier immediately followed by a quote might be the encoding prefix of a
+                // string literal, like `L"wide"`.
+                let encoding = match identifier {
+                    "u8" => Some(ast::Encoding::Utf8),
+                    "u" => Some(ast::Encoding::Char16),
+                    "U" => Some(ast::Encoding::Char32),
+                    "L" => Some(ast::Encoding::Wide),
+                    _ => None,
+                };
+                match encoding {
+                    Some(encoding) if context.peek() == Some(b'"') => {
+                        context.next(); // consume the opening quote
+                        match lex_subroutines::string(&mut context, encoding) {
+                            Ok(s) => Token::String(StringId::intern(s)),
+                            Err(e) => error_token(&mut errors, &mut lines, start, context.position(), e),
+                        }
+                    },
+                    _ => match Keyword::from_str(identifier) {
+                        Some(keyword) => Token::Keyword(keyword),
+                        None => Token::Identifier(Symbol::intern(identifier)),
+                    },
+                }
+            }
+            b'0'...b'9' => {
+                context.step_back();
+                match lex_subroutines::number(&mut context) {
+                    Ok(number) => Token::Number(number),
+                    Err(e) => error_token(&mut errors, &mut lines, start, context.position(), e),
+                }
+            },
+            b'.' => match context.peek() {
+                // A number like `.5`
+                Some(b'0'...b'9') => {
                     context.step_back();
-                    let number = try!(lex_subroutines::number(&mut context));
-                    push_tok(number);
+                    match lex_subroutines::number(&mut context) {
+                        Ok(number) => Token::Number(number),
+                        Err(e) => error_token(&mut errors, &mut lines, start, context.position(), e),
+                    }
                 },
-                'a'...'z'|'A'...'Z'|'_' => {
-                    context.step_back();
-                    push_tok(Token::Identifier(lex_subroutines::identifier(&mut context)));
+                Some(b'.') => {
+                    context.next();
+                    if eat(&mut context, b'.') {
+                        Token::Ellipsis
+                    } else {
+                        context.step_back();
+                        Token::Period
+                    }
+                },
+                _ => Token::Period,
+            },
+            b'"' => match lex_subroutines::string(&mut context, ast::Encoding::Char) {
+                Ok(s) => Token::String(StringId::intern(s)),
+                Err(e) => error_token(&mut errors, &mut lines, start, context.position(), e),
+            },
+            b'\'' => match lex_subroutines::character(&mut context) {
+                Ok(value) => Token::Character(value),
+                Err(e) => error_token(&mut errors, &mut lines, start, context.position(), e),
+            },
+
+            // single-character tokens
+            b'{' => Token::LBrace,
+            b'}' => Token::RBrace,
+            b'[' => Token::LSquareBracket,
+            b']' => Token::RSquareBracket,
+            b'(' => Token::LParen,
+            b')' => Token::RParen,
+            b',' => Token::Comma,
+            b';' => Token::Semicolon,
+            b'?' => Token::Question,
+            b'#' => if eat(&mut context, b'#') { Token::HashHash } else { Token::Hash },
+            b':' if options.digraphs && eat(&mut context, b'>') => Token::RSquareBracket,
+            b':' => Token::Colon,
+
+            // Operators are lexed with "maximal munch": we always take the longest operator that
+            // matches, so `a+++b` is `a ++ + b` just like in every other C compiler.
+            b'+' => Token::Operator(
+                if eat(&mut context, b'+') { Operator::Increment }
+                else if eat(&mut context, b'=') { Operator::AddAssign }
+                else { Operator::Add }),
+            b'-' => {
+                if eat(&mut context, b'>') { Token::Arrow }
+                else if eat(&mut context, b'-') { Token::Operator(Operator::Decrement) }
+                else if eat(&mut context, b'=') { Token::Operator(Operator::SubtractAssign) }
+                else { Token::Operator(Operator::Subtract) }
+            },
+            b'*' => Token::Operator(
+                if eat(&mut context, b'=') { Operator::MultiplyAssign } else { Operator::Asterisk }),
+            b'%' if options.digraphs && eat(&mut context, b'>') => Token::RBrace,
+            b'%' if options.digraphs && eat(&mut context, b':') => {
+                if context.peek() == Some(b'%') {
+                    context.next();
+                    if eat(&mut context, b':') {
+                        Token::HashHash
+                    } else {
+                        context.step_back();
+                        Token::Hash
+                    }
+                } else {
+                    Token::Hash
+                }
+            },
+            b'%' => Token::Operator(
+                if eat(&mut context, b'=') { Operator::ModuloAssign } else { Operator::Modulo }),
+            b'=' => Token::Operator(
+                if eat(&mut context, b'=') { Operator::Equal } else { Operator::Assign }),
+            b'!' => Token::Operator(
+                if eat(&mut context, b'=') { Operator::NotEqual } else { Operator::Not }),
+            b'^' => Token::Operator(
+                if eat(&mut context, b'=') { Operator::XorAssign } else { Operator::BitwiseXor }),
+            b'~' => Token::Operator(Operator::BitwiseNot),
+            b'&' => Token::Operator(
+                if eat(&mut context, b'&') { Operator::And }
+                else if eat(&mut context, b'=') { Operator::AndAssign }
+                else { Operator::Ampersand }),
+            b'|' => Token::Operator(
+                if eat(&mut context, b'|') { Operator::Or }
+                else if eat(&mut context, b'=') { Operator::OrAssign }
+                else { Operator::BitwiseOr }),
+            b'<' if options.digraphs && eat(&mut context, b':') => Token::LSquareBracket,
+            b'<' if options.digraphs && eat(&mut context, b'%') => Token::LBrace,
+            b'<' => Token::Operator(
+                if eat(&mut context, b'<') {
+                    if eat(&mut context, b'=') { Operator::ShiftLeftAssign } else { Operator::ShiftLeft }
                 }
-                '"' => push_tok(Token::String(try!(lex_subroutines::string(&mut context)))),
-                '\'' => (), // TODO: lex character
-
-                // single-character tokens
-                '{' => push_tok(Token::LBrace),
-                '}' => push_tok(Token::RBrace),
-                '[' => push_tok(Token::LSquareBracket),
-                ']' => push_tok(Token::RSquareBracket),
-                '(' => push_tok(Token::LParen),
-                ')' => push_tok(Token::RParen),
-                ',' => push_tok(Token::Comma),
-                ';' => push_tok(Token::Semicolon),
-                ' '|'\n'|'\t' => (), // ignore whitespace
-
-                // TODO: more sophisticated operator lexing. Most of these can actually be
-                // two-character operators.
-                '+' => push_tok(Token::Operator(Operator::Add)),
-                '-' => push_tok(Token::Operator(Operator::Subtract)),
-                '*' => push_tok(Token::Operator(Operator::Asterisk)),
-                '=' => push_tok(Token::Operator(Operator::Assign)),
-                '%' => push_tok(Token::Operator(Operator::Modulo)),
-
-                // comments are handled in this block
-                '/' => {
-                    match context.peek() {
-                        Some('*') => {
-                            // TODO: handle comment until a `*/` symbol
-                        },
-
-                        Some('/') => loop {
-                            // comment till the end of the line
+                else if eat(&mut context, b'=') { Operator::LessEqual }
+                else { Operator::Less }),
+            b'>' => Token::Operator(
+                if eat(&mut context, b'>') {
+                    if eat(&mut context, b'=') { Operator::ShiftRightAssign } else { Operator::ShiftRight }
+                }
+                else if eat(&mut context, b'=') { Operator::GreaterEqual }
+                else { Operator::Greater }),
+
+            // comments are handled in this block
+            b'/' => {
+                match context.peek() {
+                    Some(b'*') => {
+                        // Block comment. These don't nest, so the first `*/` ends the comment.
+                        context.next(); // consume the `*`
+                        loop {
+                            match context.next() {
+                                Some(b'*') if context.peek() == Some(b'/') => {
+                                    context.next();
+                                    break;
+                                },
+                                Some(_) => (),
+                                None => {
+                                    // Point at the `/*` rather than the whole rest of the file.
+                                    errors.push(lines.error(start, start + 2, "unterminated block comment"));
+                                    tokens.push(Lexeme{
+                                        token:          Token::Error,
+                                        span:           lines.span(start, start + 2),
+                                        line_start:     line_start,
+                                        leading_space:  leading_space,
+                                    });
+                                    break;
+                                },
+                            }
+                        }
+                        leading_space = true;
+                        continue;
+                    },
+
+                    Some(b'/') => {
+                        loop {
+                            // comment till the end of the line. A backslash at the end of the
+                            // line has already spliced the next line onto this one.
                             match context.next() {
-                                // Backslash escapes newlines, even in comments. We know we can
-                                // safely skip the next character no matter what it is.
-                                Some('\\') => { context.next(); },
-                                Some('\n') | None => break,
+                                Some(b'\n') => {
+                                    line_start = true;
+                                    break;
+                                },
+                                None => break,
                                 _ => (),
                             }
-                        },
+                        }
+                        leading_space = true;
+                        continue;
+                    },
 
-                        _ => push_tok(Token::Operator(Operator::Divide)),
-                    }
-                },
+                    _ => Token::Operator(
+                        if eat(&mut context, b'=') { Operator::DivideAssign } else { Operator::Divide }),
+                }
+            },
 
-                _ => return Err(format!("unexpected character '{}'", ch)),
+            _ => {
+                context.step_back();
+                let ch = context.next_char().unwrap();
+                error_token(&mut errors, &mut lines, start, context.position(),
+                            format!("unexpected character '{}'", ch))
+            },
+        };
+
+        // debug printing can be added here to easily record
+        //
+        // 1. what new tokens are pushed.
+        // 2. when they were pushed, relative to the other tokens.
+        //
+        tokens.push(Lexeme{
+            token:          token,
+            span:           lines.span(start, context.position()),
+            line_start:     line_start,
+            leading_space:  leading_space,
+        });
+        line_start = false;
+        leading_space = false;
+    }
+
+    (tokens, errors)
+}
+
+/**
+ * Record a lexical error covering the bytes from `start` to `end`, and return the Error token
+ * that takes the place of the bad source.
+ */
+fn error_token<S: Into<String>>(errors: &mut Vec<Diagnostic>, lines: &mut LineCounter,
+                                start: usize, end: usize, message: S) -> Token {
+    errors.push(lines.error(start, end, message));
+    Token::Error
+}
+
+/**
+ * If the next byte is `ch`, consume it and return true. Otherwise leave the context alone.
+ */
+#[inline]
+fn eat(context: &mut Context, ch: u8) -> bool {
+    if context.peek() == Some(ch) {
+        context.next();
+        true
+    } else {
+        false
+    }
+}
+
+/**
+ * LineCounter turns byte offsets into Spans. Tokens are produced in order, so instead of building
+ * a table of line starts up front, it just walks forward from the previous token to the next one,
+ * counting newlines and characters as it goes.
+ *
+ * Offsets come in relative to the translated source the lexer sees, and are mapped back to the
+ * original source before counting, so Spans describe physical lines.
+ */
+struct LineCounter<'a> {
+    bytes:      &'a [u8],
+    translated: &'a phases::Translated<'a>,
+    file:       FileId,
+    pos:        usize,  // byte offset (in the original source) that `line` and `column` describe
+    line:       usize,
+    column:     usize,
+}
+
+impl<'a> LineCounter<'a> {
+    fn new(s: &'a str, file: FileId, translated: &'a phases::Translated<'a>) -> Self {
+        LineCounter{
+            bytes:      s.as_bytes(),
+            translated: translated,
+            file:       file,
+            pos:        0,
+            line:       1,
+            column:     1,
+        }
+    }
+
+    /**
+     * Return the Span covering the bytes of translated source from `start` up to `end`. `start`
+     * must not be before the start of the previous Span.
+     */
+    fn span(&mut self, start: usize, end: usize) -> Span {
+        let start = self.translated.original_start(start);
+        let end = self.translated.original_end(end).max(start);
+        for (i, &b) in self.bytes[self.pos..start].iter().enumerate() {
+            // A line ends with `\n`, `\r\n` or a lone `\r`.
+            let newline = b == b'\n' ||
+                (b == b'\r' && self.bytes.get(self.pos + i + 1) != Some(&b'\n'));
+            if newline {
+                self.line += 1;
+                self.column = 1;
+            } else if b != b'\r' && b & 0xc0 != 0x80 {
+                // Count characters rather than bytes by skipping UTF-8 continuation bytes (and the
+                // `\r` of a `\r\n`).
+                self.column += 1;
             }
         }
+        self.pos = start;
+
+        Span{
+            file:   self.file,
+            offset: start,
+            len:    end - start,
+            line:   self.line,
+            column: self.column,
+        }
     }
 
-    Ok(tokens)
+    fn error<S: Into<String>>(&mut self, start: usize, end: usize, message: S) -> Diagnostic {
+        Diagnostic::new(self.span(start, end), message)
+    }
 }
 
 #[cfg(test)]
@@ -184,49 +808,575 @@ mod test {
     use super::*;
     use ast;
 
+    /**
+     * Lex `s` and throw away the Spans, since most tests only care about the Tokens.
+     */
+    fn tokens(s: &str) -> Result<Vec<Token>, Diagnostic> {
+        lex(s).map(|lexemes| lexemes.into_iter().map(|l| l.token).collect())
+    }
+
     #[test]
     fn unexpected_character() {
-        assert!(lex("$").is_err());
-        assert!(lex("@").is_err());
+        assert!(tokens("$").is_err());
+        assert!(tokens("@").is_err());
+    }
+
+    #[test]
+    fn error_recovery() {
+        let (lexemes, errors) = lex_file("a $ b @@ 12abc \"bad\\q\" '' c\n\"open\nd", 0, Options::default());
+        let tokens: Vec<Token> = lexemes.iter().map(|l| l.token).collect();
+        assert_eq!(tokens, vec![
+            Token::Identifier(Symbol::intern("a")),
+            Token::Error,
+            Token::Identifier(Symbol::intern("b")),
+            Token::Error,
+            Token::Error,
+            Token::Error,
+            Token::Error,
+            Token::Error,
+            Token::Identifier(Symbol::intern("c")),
+            Token::Error,
+            Token::Identifier(Symbol::intern("d")),
+        ]);
+
+        let messages: Vec<&str> = errors.iter().map(|e| &e.message[..]).collect();
+        assert_eq!(messages, vec![
+            "unexpected character '$'",
+            "unexpected character '@'",
+            "unexpected character '@'",
+            "invalid suffix 'abc' on integer literal '12abc'",
+            "Unrecognized escape sequence \\q",
+            "empty character constant",
+            "unterminated string literal",
+        ]);
+
+        // Each Error token has the same Span as its Diagnostic.
+        let error_spans: Vec<Span> = lexemes.iter()
+            .filter(|l| l.token == Token::Error)
+            .map(|l| l.span)
+            .collect();
+        let diagnostic_spans: Vec<Span> = errors.iter().map(|e| e.span).collect();
+        assert_eq!(error_spans, diagnostic_spans);
+        assert_eq!((errors[4].span.column, errors[4].span.len), (16, 7));
+        assert_eq!((errors[6].span.line, errors[6].span.column), (2, 1));
+    }
+
+    #[test]
+    fn error_recovery_in_comments() {
+        let (lexemes, errors) = lex_file("a /* b", 0, Options::default());
+        assert_eq!(lexemes.len(), 2);
+        assert_eq!(lexemes[1].token, Token::Error);
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].span.offset, errors[0].span.len), (2, 2));
     }
 
     #[test]
     fn numbers() {
-        assert_eq!(lex("123").unwrap(), vec![Token::Number(ast::Number::Int(123))]);
-        assert_eq!(lex("12.3").unwrap(), vec![Token::Number(ast::Number::Float(12.3))]);
-        assert_eq!(lex("012").unwrap(), vec![Token::Number(ast::Number::Int(12))]);
-        assert_eq!(lex("0120}").unwrap(), vec![Token::Number(ast::Number::Int(120)), Token::RBrace]);
+        use ast::{Number, IntType, FloatType};
+
+        assert_eq!(tokens("123").unwrap(), vec![Token::Number(Number::Int(123, IntType::Int))]);
+        assert_eq!(tokens("12.3").unwrap(), vec![Token::Number(Number::Float(12.3, FloatType::Double))]);
+        assert_eq!(tokens("012").unwrap(), vec![Token::Number(Number::Int(10, IntType::Int))]);
+        assert_eq!(tokens("0120}").unwrap(), vec![Token::Number(Number::Int(80, IntType::Int)), Token::RBrace]);
+        assert_eq!(tokens("0").unwrap(), vec![Token::Number(Number::Int(0, IntType::Int))]);
+    }
+
+    fn number(s: &str) -> ast::Number {
+        match tokens(s).unwrap()[..] {
+            [Token::Number(ref n)] => n.clone(),
+            ref t => panic!("expected a single number token, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn integer_bases() {
+        use ast::{Number, IntType};
+
+        assert_eq!(number("0xFF"), Number::Int(255, IntType::Int));
+        assert_eq!(number("0Xff"), Number::Int(255, IntType::Int));
+        assert_eq!(number("0777"), Number::Int(511, IntType::Int));
+        assert_eq!(number("0b101"), Number::Int(5, IntType::Int));
+        assert!(tokens("08").is_err());
+        assert!(tokens("0b102").is_err());
+        assert!(tokens("0x").is_err());
+    }
+
+    #[test]
+    fn integer_suffixes() {
+        use ast::{Number, IntType};
+
+        assert_eq!(number("10u"), Number::Int(10, IntType::UnsignedInt));
+        assert_eq!(number("10L"), Number::Int(10, IntType::Long));
+        assert_eq!(number("10ul"), Number::Int(10, IntType::UnsignedLong));
+        assert_eq!(number("10LU"), Number::Int(10, IntType::UnsignedLong));
+        assert_eq!(number("10ll"), Number::Int(10, IntType::LongLong));
+        assert_eq!(number("10uLL"), Number::Int(10, IntType::UnsignedLongLong));
+        assert_eq!(number("10llU"), Number::Int(10, IntType::UnsignedLongLong));
+        assert!(tokens("10lL").is_err());
+        assert!(tokens("10uu").is_err());
+        assert!(tokens("10lul").is_err());
+        assert!(tokens("10f").is_err());
+        assert!(tokens("123abc").is_err());
+    }
+
+    #[test]
+    fn integer_types() {
+        use ast::{Number, IntType};
+
+        // Decimal literals skip the unsigned types...
+        assert_eq!(number("2147483647"), Number::Int(2147483647, IntType::Int));
+        assert_eq!(number("2147483648"), Number::Int(2147483648, IntType::Long));
+        assert_eq!(number("9223372036854775807"), Number::Int(9223372036854775807, IntType::Long));
+        assert!(tokens("9223372036854775808").is_err());
+
+        // ...but hex and octal literals don't.
+        assert_eq!(number("0x7fffffff"), Number::Int(0x7fffffff, IntType::Int));
+        assert_eq!(number("0x80000000"), Number::Int(0x80000000, IntType::UnsignedInt));
+        assert_eq!(number("0x100000000"), Number::Int(0x100000000, IntType::Long));
+        assert_eq!(number("0x8000000000000000"), Number::Int(0x8000000000000000, IntType::UnsignedLong));
+        assert_eq!(number("037777777777"), Number::Int(0xffffffff, IntType::UnsignedInt));
+
+        assert_eq!(number("4294967296u"), Number::Int(4294967296, IntType::UnsignedLong));
+        assert_eq!(number("18446744073709551615u"), Number::Int(18446744073709551615, IntType::UnsignedLong));
+        assert!(tokens("18446744073709551616u").is_err());
+        assert!(tokens("0x1ffffffffffffffff").is_err());
+    }
+
+    #[test]
+    fn floats() {
+        use ast::{Number, FloatType};
+
+        assert_eq!(number("1.5"), Number::Float(1.5, FloatType::Double));
+        assert_eq!(number(".5"), Number::Float(0.5, FloatType::Double));
+        assert_eq!(number("5."), Number::Float(5.0, FloatType::Double));
+        assert_eq!(number("1e9"), Number::Float(1e9, FloatType::Double));
+        assert_eq!(number("1.e-2"), Number::Float(0.01, FloatType::Double));
+        assert_eq!(number("2.5E+3"), Number::Float(2500.0, FloatType::Double));
+        assert_eq!(number("1.0f"), Number::Float(1.0, FloatType::Float));
+        assert_eq!(number("0.1f"), Number::Float(0.1f32 as f64, FloatType::Float));
+        assert_eq!(number("1.0L"), Number::Float(1.0, FloatType::LongDouble));
+        assert_eq!(number("1e9f"), Number::Float(1e9, FloatType::Float));
+
+        assert!(tokens("1.2.3").is_err());
+        assert!(tokens("1e").is_err());
+        assert!(tokens("1e+").is_err());
+        assert!(tokens("1.0u").is_err());
+        assert!(tokens("1e999").is_err());
+        assert!(tokens("1e39f").is_err());
+
+        // `.` on its own is still a Period.
+        assert_eq!(tokens("a.b").unwrap(), vec![
+            Token::Identifier(Symbol::intern("a")),
+            Token::Period,
+            Token::Identifier(Symbol::intern("b")),
+        ]);
+    }
+
+    #[test]
+    fn hex_floats() {
+        use ast::{Number, FloatType};
+
+        assert_eq!(number("0x1p0"), Number::Float(1.0, FloatType::Double));
+        assert_eq!(number("0x1.8p1"), Number::Float(3.0, FloatType::Double));
+        assert_eq!(number("0x.8p0"), Number::Float(0.5, FloatType::Double));
+        assert_eq!(number("0xAp-2f"), Number::Float(2.5, FloatType::Float));
+        assert_eq!(number("0x1P+10L"), Number::Float(1024.0, FloatType::LongDouble));
+        assert_eq!(number("0x1.fffffffffffffp1023"), Number::Float(::std::f64::MAX, FloatType::Double));
+        assert!(tokens("0x1.8").is_err());
+        assert!(tokens("0x1p").is_err());
+        assert!(tokens("0x1p99999").is_err());
+        assert!(tokens("0x1g.8p1").is_err());
+        assert!(tokens("0x.p1").is_err());
     }
 
     #[test]
     fn identifiers() {
-        assert_eq!(lex("int ident1, _ident2;").unwrap(),
+        assert_eq!(tokens("int ident1, _ident2;").unwrap(),
             vec![
-                Token::Identifier("int".to_string()),
-                Token::Identifier("ident1".to_string()),
+                Token::Keyword(Keyword::Int),
+                Token::Identifier(Symbol::intern("ident1")),
                 Token::Comma,
-                Token::Identifier("_ident2".to_string()),
+                Token::Identifier(Symbol::intern("_ident2")),
                 Token::Semicolon,
             ]
         );
     }
 
+    #[test]
+    fn keywords() {
+        let all = "auto break case char const continue default do double else enum extern float \
+                   for goto if inline int long register restrict return short signed sizeof \
+                   static struct switch typedef union unsigned void volatile while _Alignas \
+                   _Alignof _Atomic _Bool _Complex _Generic _Imaginary _Noreturn _Static_assert \
+                   _Thread_local";
+        let lexed = tokens(all).unwrap();
+        assert_eq!(lexed.len(), 44);
+        for (token, spelling) in lexed.into_iter().zip(all.split_whitespace()) {
+            match token {
+                Token::Keyword(keyword) => assert_eq!(keyword.as_str(), spelling),
+                t => panic!("expected {} to be a keyword, got {:?}", spelling, t),
+            }
+        }
+
+        // Keywords are case sensitive and have to match exactly.
+        assert_eq!(tokens("Int integer _bool returnx").unwrap(), vec![
+            Token::Identifier(Symbol::intern("Int")),
+            Token::Identifier(Symbol::intern("integer")),
+            Token::Identifier(Symbol::intern("_bool")),
+            Token::Identifier(Symbol::intern("returnx")),
+        ]);
+    }
+
     #[test]
     fn strings() {
-        assert_eq!(lex(r##""\n\\\"""##).unwrap(), vec![Token::String("\n\\\"".to_string())]);
-        assert!(lex("\"hello ").is_err());
-        assert!(lex("\"hello \\").is_err());
+        assert_eq!(tokens(r##""\n\\\"""##).unwrap(), vec![Token::String(StringId::intern(ast::StringLiteral::from_str("\n\\\"")))]);
+        assert!(tokens("\"hello ").is_err());
+        assert!(tokens("\"hello \\").is_err());
+        assert!(tokens("\"hello\nworld\"").is_err());
+    }
+
+    fn string(s: &str) -> ast::StringLiteral {
+        match tokens(s).unwrap()[..] {
+            [Token::String(id)] => id.get(),
+            ref t => panic!("expected a single string token, got {:?}", t),
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(string(r#""\a\b\f\n\r\t\v\'\"\?\\""#).units,
+                   vec![7, 8, 12, 10, 13, 9, 11, 39, 34, 63, 92]);
+
+        // Octal and hex escapes produce arbitrary bytes, including NULs and non-UTF-8 bytes.
+        assert_eq!(string(r#""a\0b""#).units, vec![97, 0, 98]);
+        assert_eq!(string(r#""\101\1012""#).units, vec![65, 65, 50]);
+        assert_eq!(string(r#""\xff\x00\x7F""#).units, vec![0xff, 0, 0x7f]);
+        assert_eq!(string(r#""\377""#).units, vec![0xff]);
+
+        // Universal character names and non-ASCII source characters are UTF-8 encoded.
+        assert_eq!(string(r#""\u00e9""#).units, vec![0xc3, 0xa9]);
+        assert_eq!(string("\"é\"").units, vec![0xc3, 0xa9]);
+
+        assert!(tokens(r#""\x100""#).is_err());
+        assert!(tokens(r#""\400""#).is_err());
+        assert!(tokens(r#""\q""#).is_err());
+    }
+
+    #[test]
+    fn string_prefixes() {
+        use ast::{Encoding, StringLiteral};
+
+        assert_eq!(string(r#"u8"é""#), StringLiteral{encoding: Encoding::Utf8, units: vec![0xc3, 0xa9]});
+        assert_eq!(string(r#"L"é""#), StringLiteral{encoding: Encoding::Wide, units: vec![0xe9]});
+        assert_eq!(string(r#"U"\U0001F600""#), StringLiteral{encoding: Encoding::Char32, units: vec![0x1f600]});
+        assert_eq!(string(r#"u"\U0001F600""#), StringLiteral{encoding: Encoding::Char16, units: vec![0xd83d, 0xde00]});
+
+        // Escapes can be as wide as the code units.
+        assert_eq!(string(r#"L"\x12345678""#).units, vec![0x12345678]);
+        assert_eq!(string(r#"u"\xffff""#).units, vec![0xffff]);
+        assert!(tokens(r#"u"\x10000""#).is_err());
+
+        // Only those exact identifiers are prefixes.
+        assert_eq!(tokens(r#"u16"a""#).unwrap(), vec![
+            Token::Identifier(Symbol::intern("u16")),
+            Token::String(StringId::intern(StringLiteral::from_str("a"))),
+        ]);
+        assert_eq!(tokens(r#"L "a""#).unwrap(), vec![
+            Token::Identifier(Symbol::intern("L")),
+            Token::String(StringId::intern(StringLiteral::from_str("a"))),
+        ]);
+    }
+
+    #[test]
+    fn characters() {
+        assert_eq!(tokens("'a'").unwrap(), vec![Token::Character(97)]);
+        assert_eq!(tokens("'\"'").unwrap(), vec![Token::Character(34)]);
+        assert_eq!(tokens("'a' 'b'").unwrap(), vec![Token::Character(97), Token::Character(98)]);
+    }
+
+    #[test]
+    fn character_escapes() {
+        let value = |s| match tokens(s).unwrap()[..] {
+            [Token::Character(value)] => value,
+            ref t => panic!("expected a single character token, got {:?}", t),
+        };
+
+        // simple escapes
+        assert_eq!(value(r"'\n'"), 10);
+        assert_eq!(value(r"'\''"), 39);
+        assert_eq!(value(r"'\\'"), 92);
+        assert_eq!(value(r"'\?'"), 63);
+        assert_eq!(value(r"'\a'"), 7);
+        assert_eq!(value(r"'\v'"), 11);
+
+        // octal escapes take at most three digits
+        assert_eq!(value(r"'\0'"), 0);
+        assert_eq!(value(r"'\101'"), 65);
+        assert_eq!(value(r"'\1011'"), 65 * 256 + 49);
+
+        // hex escapes take as many digits as they can get
+        assert_eq!(value(r"'\x41'"), 65);
+        assert_eq!(value(r"'\x0041'"), 65);
+
+        // plain `char` is signed, so a byte above 0x7f is negative
+        assert_eq!(value(r"'\xff'"), -1);
+        assert_eq!(value(r"'\377'"), -1);
+
+        // universal character names are UTF-8 encoded, giving a multi-character constant
+        assert_eq!(value(r"'\u00e9'"), 0xc3a9);
+        assert_eq!(value(r"'\U0001F600'"), 0xf09f9880u32 as i32);
+        assert_eq!(value("'é'"), 0xc3a9);
+    }
+
+    #[test]
+    fn multi_character_constants() {
+        let value = |s| match tokens(s).unwrap()[..] {
+            [Token::Character(value)] => value,
+            ref t => panic!("expected a single character token, got {:?}", t),
+        };
+
+        assert_eq!(value("'ab'"), 0x6162);
+        assert_eq!(value("'abcd'"), 0x61626364);
+        // Only the last four characters fit in an int.
+        assert_eq!(value("'abcde'"), 0x62636465);
+        assert_eq!(value(r"'\0\1'"), 1);
+    }
+
+    #[test]
+    fn bad_characters() {
+        assert!(tokens("''").is_err());
+        assert!(tokens("'a").is_err());
+        assert!(tokens("'a\n'").is_err());
+        assert!(tokens(r"'\q'").is_err());
+        assert!(tokens(r"'\x'").is_err());
+        assert!(tokens(r"'\x100'").is_err());
+        assert!(tokens(r"'\400'").is_err());
+        assert!(tokens(r"'\u0041'").is_err()); // basic character set members can't be named
+        assert!(tokens(r"'\ud800'").is_err()); // surrogates aren't characters
+        assert!(tokens(r"'\u12'").is_err());
     }
 
     #[test]
     fn one_line_comments() {
-        assert_eq!(lex("").unwrap(), vec![]);
-        assert_eq!(lex("//").unwrap(), vec![]);
-        assert_eq!(lex("// hello ").unwrap(), vec![]);
-        assert_eq!(lex("// hello \n\n").unwrap(), vec![]);
-        assert_eq!(lex(", // hello").unwrap(), vec![Token::Comma]);
-        assert_eq!(lex(", // hello \\\n goodbye").unwrap(), vec![Token::Comma]); // escaped newline
-        assert_eq!(lex(", // hello \n ;").unwrap(), vec![Token::Comma, Token::Semicolon]);
+        assert_eq!(tokens("").unwrap(), vec![]);
+        assert_eq!(tokens("//").unwrap(), vec![]);
+        assert_eq!(tokens("// hello ").unwrap(), vec![]);
+        assert_eq!(tokens("// hello \n\n").unwrap(), vec![]);
+        assert_eq!(tokens(", // hello").unwrap(), vec![Token::Comma]);
+        assert_eq!(tokens(", // hello \\\n goodbye").unwrap(), vec![Token::Comma]); // escaped newline
+        assert_eq!(tokens(", // hello \n ;").unwrap(), vec![Token::Comma, Token::Semicolon]);
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(tokens("a\r\n\x0bb\x0c\t;").unwrap(), vec![
+            Token::Identifier(Symbol::intern("a")),
+            Token::Identifier(Symbol::intern("b")),
+            Token::Semicolon,
+        ]);
+    }
+
+    #[test]
+    fn line_splicing() {
+        assert_eq!(tokens("in\\\nt ma\\\r\nin = 1\\\n2;").unwrap(), vec![
+            Token::Keyword(Keyword::Int),
+            Token::Identifier(Symbol::intern("main")),
+            Token::Operator(Operator::Assign),
+            Token::Number(ast::Number::Int(12, ast::IntType::Int)),
+            Token::Semicolon,
+        ]);
+        assert_eq!(tokens("\"ab\\\ncd\"").unwrap(),
+                   vec![Token::String(StringId::intern(ast::StringLiteral::from_str("abcd")))]);
+        assert_eq!(tokens("+\\\n=").unwrap(), vec![Token::Operator(Operator::AddAssign)]);
+    }
+
+    /**
+     * Spans should describe the physical lines of the file even after lines are spliced or line
+     * endings are normalized.
+     */
+    #[test]
+    fn spans_after_translation() {
+        let lexemes = lex("ab\\\ncd e\r\nf\rg").unwrap();
+        let spans: Vec<(usize, usize, usize, usize)> = lexemes.iter()
+            .map(|l| (l.span.line, l.span.column, l.span.offset, l.span.len))
+            .collect();
+        assert_eq!(spans, vec![(1, 1, 0, 6), (2, 4, 7, 1), (3, 1, 10, 1), (4, 1, 12, 1)]);
+
+        let (_, errors) = lex_file("a \\\n $", 0, Options::default());
+        assert_eq!((errors[0].span.line, errors[0].span.column), (2, 2));
+    }
+
+    #[test]
+    fn trigraphs_and_digraphs() {
+        let plain = Options::default();
+        let both = Options{ trigraphs: true, digraphs: true };
+        let lex_with = |s, options| -> Vec<Token> {
+            let (lexemes, errors) = lex_file(s, 0, options);
+            assert!(errors.is_empty(), "{:?}", errors);
+            lexemes.into_iter().map(|l| l.token).collect()
+        };
+
+        let brackets = vec![Token::LSquareBracket, Token::RSquareBracket, Token::LBrace, Token::RBrace];
+        assert_eq!(lex_with("<: :> <% %>", both), brackets);
+        assert_eq!(lex_with("??( ??) ??< ??>", both), brackets);
+        assert_eq!(lex_with("??-??!", both),
+                   vec![Token::Operator(Operator::BitwiseNot), Token::Operator(Operator::BitwiseOr)]);
+        assert_eq!(lex_with("a??/\nb", both), vec![Token::Identifier(Symbol::intern("ab"))]);
+
+        // Without the options, these are ordinary operators and question marks.
+        assert_eq!(lex_with("<: %>", plain), vec![
+            Token::Operator(Operator::Less),
+            Token::Colon,
+            Token::Operator(Operator::Modulo),
+            Token::Operator(Operator::Greater),
+        ]);
+        assert_eq!(lex_with("\"??)\"", plain),
+                   vec![Token::String(StringId::intern(ast::StringLiteral::from_str("??)")))]);
+    }
+
+    #[test]
+    fn hashes_and_line_starts() {
+        let lexemes = lex("#include x\n  # a ## b // c\n#/* d\n */e\n%:").unwrap();
+        let tokens: Vec<(Token, bool)> = lexemes.iter().map(|l| (l.token, l.line_start)).collect();
+        assert_eq!(tokens, vec![
+            (Token::Hash, true),
+            (Token::Identifier(Symbol::intern("include")), false),
+            (Token::Identifier(Symbol::intern("x")), false),
+            (Token::Hash, true),
+            (Token::Identifier(Symbol::intern("a")), false),
+            (Token::HashHash, false),
+            (Token::Identifier(Symbol::intern("b")), false),
+            (Token::Hash, true),
+            (Token::Identifier(Symbol::intern("e")), false), // the comment doesn't end the line
+            (Token::Operator(Operator::Modulo), true),
+            (Token::Colon, false),
+        ]);
+
+        let (lexemes, _) = lex_file("%:%: %:%", 0, Options{ trigraphs: false, digraphs: true });
+        let tokens: Vec<Token> = lexemes.iter().map(|l| l.token).collect();
+        assert_eq!(tokens, vec![Token::HashHash, Token::Hash, Token::Operator(Operator::Modulo)]);
+    }
+
+    #[test]
+    fn leading_space() {
+        let lexemes = lex("a b/**/c\nd(e)...").unwrap();
+        let spaces: Vec<bool> = lexemes.iter().map(|l| l.leading_space).collect();
+        assert_eq!(spaces, vec![false, true, true, true, false, false, false, false]);
+        assert_eq!(lexemes[7].token, Token::Ellipsis);
+        assert_eq!(tokens("a..b").unwrap()[1..3], [Token::Period, Token::Period]);
+    }
+
+    #[test]
+    fn operators() {
+        let all = "* + - / % ++ -- && || ! & | ^ ~ << >> == != < <= > >= \
+                   = += -= *= /= %= &= |= ^= <<= >>=";
+        let expected: Vec<Token> = all.split_whitespace()
+            .map(|s| Token::Operator(Operator::from_str(s).unwrap()))
+            .collect();
+        assert_eq!(tokens(all).unwrap(), expected);
+
+        assert_eq!(tokens("a->b ? c : d").unwrap(), vec![
+            Token::Identifier(Symbol::intern("a")),
+            Token::Arrow,
+            Token::Identifier(Symbol::intern("b")),
+            Token::Question,
+            Token::Identifier(Symbol::intern("c")),
+            Token::Colon,
+            Token::Identifier(Symbol::intern("d")),
+        ]);
+    }
+
+    #[test]
+    fn maximal_munch() {
+        use super::Operator::*;
+        let ops = |s| tokens(s).unwrap().into_iter().map(|t| match t {
+            Token::Operator(op) => op,
+            t => panic!("expected operator, got {:?}", t),
+        }).collect::<Vec<Operator>>();
+
+        assert_eq!(ops("+++"), vec![Increment, Add]);
+        assert_eq!(ops("<<<="), vec![ShiftLeft, LessEqual]);
+        assert_eq!(ops(">>>="), vec![ShiftRight, GreaterEqual]);
+        assert_eq!(ops("&&&"), vec![And, Ampersand]);
+        assert_eq!(ops("|||="), vec![Or, OrAssign]);
+        assert_eq!(ops("===!"), vec![Equal, Assign, Not]);
+        assert_eq!(ops("- -"), vec![Subtract, Subtract]);
+        assert_eq!(tokens("x--->y").unwrap(), vec![
+            Token::Identifier(Symbol::intern("x")),
+            Token::Operator(Decrement),
+            Token::Arrow,
+            Token::Identifier(Symbol::intern("y")),
+        ]);
+    }
+
+    #[test]
+    fn spans() {
+        let lexemes = lex("int a;\n  return \"hi\";").unwrap();
+        let spans: Vec<(usize, usize, usize, usize)> = lexemes.iter()
+            .map(|l| (l.span.offset, l.span.len, l.span.line, l.span.column))
+            .collect();
+        assert_eq!(spans, vec![
+            (0, 3, 1, 1),   // int
+            (4, 1, 1, 5),   // a
+            (5, 1, 1, 6),   // ;
+            (9, 6, 2, 3),   // return
+            (16, 4, 2, 10), // "hi"
+            (20, 1, 2, 14), // ;
+        ]);
+    }
+
+    #[test]
+    fn error_spans() {
+        let err = lex("a\n  $").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 3));
+        assert_eq!(err.message, "unexpected character '$'");
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(tokens("/**/").unwrap(), vec![]);
+        assert_eq!(tokens("hello /*hello*/goodbye").unwrap(), vec![
+            Token::Identifier(Symbol::intern("hello")),
+            Token::Identifier(Symbol::intern("goodbye")),
+        ]);
+        assert_eq!(tokens("a /* comment */ */").unwrap(), vec![
+            Token::Identifier(Symbol::intern("a")),
+            Token::Operator(Operator::Asterisk),
+            Token::Operator(Operator::Divide),
+        ]);
+        assert_eq!(tokens("this /*is my */file /* this is a\n multiline comment */").unwrap(), vec![
+            Token::Identifier(Symbol::intern("this")),
+            Token::Identifier(Symbol::intern("file")),
+        ]);
+
+        // Block comments don't nest, and `//` means nothing inside of one.
+        assert_eq!(tokens("/* /* */ a").unwrap(), vec![Token::Identifier(Symbol::intern("a"))]);
+        assert_eq!(tokens("/* // */ a").unwrap(), vec![Token::Identifier(Symbol::intern("a"))]);
+        assert_eq!(tokens("/***/ a /*/ b */").unwrap(), vec![Token::Identifier(Symbol::intern("a"))]);
+
+        // A comment can separate two tokens that would otherwise be one.
+        assert_eq!(tokens("a/**/b").unwrap(), vec![
+            Token::Identifier(Symbol::intern("a")),
+            Token::Identifier(Symbol::intern("b")),
+        ]);
+    }
+
+    #[test]
+    fn block_comments_keep_line_numbers() {
+        let lexemes = lex("/* one\ntwo\n */ a /* three */\n b").unwrap();
+        let positions: Vec<(usize, usize)> = lexemes.iter()
+            .map(|l| (l.span.line, l.span.column))
+            .collect();
+        assert_eq!(positions, vec![(3, 5), (4, 2)]);
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let err = lex("a;\n  /* never closed\n int b;").unwrap_err();
+        assert_eq!(err.message, "unterminated block comment");
+        assert_eq!((err.span.line, err.span.column), (2, 3));
+        assert!(lex("/*/").is_err());
+        assert!(lex("/* *").is_err());
     }
 
     #[test]
@@ -239,29 +1389,29 @@ mod test {
                 }
             "##;
 
-        let tokens = lex(simple_program).unwrap();
+        let tokens = tokens(simple_program).unwrap();
         assert_eq!(tokens,
             vec![
-                Token::Identifier("int".to_string()),
-                Token::Identifier("main".to_string()),
+                Token::Keyword(Keyword::Int),
+                Token::Identifier(Symbol::intern("main")),
                 Token::LParen,
-                    Token::Identifier("int".to_string()),
-                    Token::Identifier("argc".to_string()),
+                    Token::Keyword(Keyword::Int),
+                    Token::Identifier(Symbol::intern("argc")),
                     Token::Comma,
-                    Token::Identifier("char".to_string()),
+                    Token::Keyword(Keyword::Char),
                     Token::Operator(Operator::Asterisk),
-                    Token::Identifier("argv".to_string()),
+                    Token::Identifier(Symbol::intern("argv")),
                     Token::LSquareBracket,
                     Token::RSquareBracket,
                 Token::RParen,
                 Token::LBrace,
-                    Token::Identifier("printf".to_string()),
+                    Token::Identifier(Symbol::intern("printf")),
                     Token::LParen,
-                        Token::String("Hello world\n".to_string()),
+                        Token::String(StringId::intern(ast::StringLiteral::from_str("Hello world\n"))),
                     Token::RParen,
                     Token::Semicolon,
-                    Token::Identifier("return".to_string()),
-                    Token::Number(ast::Number::Int(0)),
+                    Token::Keyword(Keyword::Return),
+                    Token::Number(ast::Number::Int(0, ast::IntType::Int)),
                     Token::Semicolon,
                 Token::RBrace,
             ]
@@ -0,0 +1,156 @@
+/*!
+ * Translation phases 1 and 2 (C11 5.1.1.2)
+ * ========================================
+ *
+ * Before C source is split into tokens, it goes through two rewriting passes:
+ *
+ * 1. Line endings are normalized, and (optionally) trigraphs like `??=` are replaced by the
+ *    character they stand for.
+ * 2. Every backslash immediately followed by a newline is deleted, splicing two physical lines
+ *    into one logical line. This can happen anywhere, even in the middle of an identifier.
+ *
+ * The lexer works on the result, but errors have to point at the file the user actually wrote. So
+ * alongside the rewritten text we keep a list of the places where it drifts from the original,
+ * which is enough to turn any offset in one back into an offset in the other.
+ */
+
+use std::borrow::Cow;
+
+/**
+ * Translated is source code after phases 1 and 2.
+ */
+pub struct Translated<'a> {
+    pub text:   Cow<'a, str>,
+
+    /// Every point where bytes were removed, as `(translated offset, original offset)` pairs in
+    /// increasing order. Between two breaks the text and the original line up byte for byte.
+    breaks:     Vec<(usize, usize)>,
+}
+
+impl<'a> Translated<'a> {
+    /**
+     * Map the offset of the first byte of a token back to the original source.
+     */
+    pub fn original_start(&self, offset: usize) -> usize {
+        // A token starting right where a splice was removed starts after the splice.
+        match self.breaks.binary_search_by(|&(t, _)| t.cmp(&offset)) {
+            Ok(i) => self.breaks[i].1,
+            Err(0) => offset,
+            Err(i) => self.breaks[i - 1].1 + offset - self.breaks[i - 1].0,
+        }
+    }
+
+    /**
+     * Map the offset just past the last byte of a token back to the original source.
+     */
+    pub fn original_end(&self, offset: usize) -> usize {
+        // A token ending right where a splice was removed ends before the splice.
+        match self.breaks.binary_search_by(|&(t, _)| t.cmp(&offset)) {
+            Ok(0) | Err(0) => offset,
+            Ok(i) | Err(i) => self.breaks[i - 1].1 + offset - self.breaks[i - 1].0,
+        }
+    }
+}
+
+/**
+ * Run phases 1 and 2 over `s`. Most files have no carriage returns, trigraphs or spliced lines, in
+ * which case `s` is borrowed as is.
+ */
+pub fn translate<'a>(s: &'a str, trigraphs: bool) -> Translated<'a> {
+    let bytes = s.as_bytes();
+    let untouched = !bytes.iter().enumerate().any(|(i, &b)| match b {
+        b'\r' => true,
+        b'\\' => bytes.get(i + 1) == Some(&b'\n'),
+        b'?' => trigraphs && bytes.get(i + 1) == Some(&b'?'),
+        _ => false,
+    });
+    if untouched {
+        return Translated{
+            text:   Cow::Borrowed(s),
+            breaks: vec![],
+        };
+    }
+
+    let mut text = Vec::with_capacity(bytes.len());
+    let mut breaks = vec![];
+    let mut i = 0;
+    while let Some((ch, len)) = phase_one(bytes, i, trigraphs) {
+        if ch == b'\\' {
+            if let Some((b'\n', newline_len)) = phase_one(bytes, i + len, trigraphs) {
+                i += len + newline_len;
+                breaks.push((text.len(), i));
+                continue;
+            }
+        }
+
+        text.push(ch);
+        i += len;
+        if len > 1 {
+            breaks.push((text.len(), i));
+        }
+    }
+
+    // Only ASCII bytes are ever replaced or removed, so the text is still valid UTF-8.
+    Translated{
+        text:   Cow::Owned(String::from_utf8(text).unwrap()),
+        breaks: breaks,
+    }
+}
+
+/**
+ * Return the phase 1 character at byte `i` of `bytes`, along with how many bytes of source it was
+ * spelled with. Newlines are `\n`, `\r\n` or `\r`, and trigraphs are three bytes long.
+ */
+fn phase_one(bytes: &[u8], i: usize, trigraphs: bool) -> Option<(u8, usize)> {
+    match bytes.get(i) {
+        None => None,
+        Some(&b'\r') => Some((b'\n', if bytes.get(i + 1) == Some(&b'\n') { 2 } else { 1 })),
+        Some(&b'?') if trigraphs && bytes.get(i + 1) == Some(&b'?') => {
+            let replacement = match bytes.get(i + 2) {
+                Some(&b'=') => b'#',
+                Some(&b'(') => b'[',
+                Some(&b'/') => b'\\',
+                Some(&b')') => b']',
+                Some(&b'\'') => b'^',
+                Some(&b'<') => b'{',
+                Some(&b'!') => b'|',
+                Some(&b'>') => b'}',
+                Some(&b'-') => b'~',
+                _ => return Some((b'?', 1)),
+            };
+            Some((replacement, 3))
+        },
+        Some(&b) => Some((b, 1)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splices_and_line_endings() {
+        assert_eq!(translate("int a;\n", false).text, "int a;\n");
+        assert_eq!(translate("a\r\nb\rc", false).text, "a\nb\nc");
+        assert_eq!(translate("ab\\\ncd", false).text, "abcd");
+        assert_eq!(translate("ab\\\r\ncd\\\n\\\ne", false).text, "abcde");
+        assert_eq!(translate("a\\ \nb", false).text, "a\\ \nb"); // not a splice
+    }
+
+    #[test]
+    fn trigraphs() {
+        assert_eq!(translate("??=??(??)??<??>??!??'??-", true).text, "#[]{}|^~");
+        assert_eq!(translate("??=", false).text, "??=");
+        assert_eq!(translate("???=", true).text, "?#");
+        assert_eq!(translate("a??/\nb", true).text, "ab");
+    }
+
+    #[test]
+    fn offsets() {
+        let t = translate("ab\\\ncd e", false);
+        assert_eq!(t.text, "abcd e");
+        assert_eq!((t.original_start(0), t.original_end(4)), (0, 6));
+        assert_eq!((t.original_start(2), t.original_end(2)), (4, 2));
+        assert_eq!((t.original_start(5), t.original_end(6)), (7, 8));
+    }
+}
@@ -16,13 +16,14 @@ mod build;
 pub mod lexer;
 
 use parser::context::Context;
-use parser::lexer::Token;
+use parser::lexer::Lexeme;
+use source::Diagnostic;
 
 /**
- * Parse a series of Tokens into a complete Program AST. No evaluation or optimization is done
+ * Parse a series of Lexemes into a complete Program AST. No evaluation or optimization is done
  * during this phase.
  */
-pub fn parse(tokens: Vec<Token>) -> Result<ast::Program, String> {
+pub fn parse(tokens: Vec<Lexeme>) -> Result<ast::Program, Diagnostic> {
     let mut context = Context::new(tokens);
     build::program(&mut context)
 }
@@ -31,7 +32,7 @@ pub fn parse(tokens: Vec<Token>) -> Result<ast::Program, String> {
  * Parse a program string directly by first lexing the tokens and then passing them to the parser.
  */
 #[allow(unused)]
-pub fn parse_str(s: &str) -> Result<ast::Program, String> {
+pub fn parse_str(s: &str) -> Result<ast::Program, Diagnostic> {
     let tokens = try!(lexer::lex(s));
     parse(tokens)
 }
@@ -41,11 +42,11 @@ pub fn parse_str(s: &str) -> Result<ast::Program, String> {
  * this phase.
  */
 #[allow(unused)]
-pub fn parse_expr(tokens: Vec<Token>) -> Result<ast::Expression, String> {
+pub fn parse_expr(tokens: Vec<Lexeme>) -> Result<ast::Expression, Diagnostic> {
     let mut context = Context::new(tokens);
     let expr = try!(build::expression(&mut context));
     if !context.is_exhausted() {
-        return Err(format!("tokens remained after parsing expression"))
+        return Err(Diagnostic::new(context.peek_span(), "tokens remained after parsing expression"))
     }
     Ok(expr)
 }
@@ -55,7 +56,7 @@ pub fn parse_expr(tokens: Vec<Token>) -> Result<ast::Expression, String> {
  * parser.
  */
 #[allow(unused)]
-pub fn parse_expr_str(s: &str) -> Result<ast::Expression, String> {
+pub fn parse_expr_str(s: &str) -> Result<ast::Expression, Diagnostic> {
     let tokens = try!(lexer::lex(s));
     parse_expr(tokens)
 }
@@ -71,6 +72,7 @@ mod test {
         assert_eq!(program, Program{
             globals:    vec![],
             functions:  vec![],
+            structs:    vec![],
         });
     }
 
@@ -84,15 +86,15 @@ mod test {
             globals: vec![
                 Declaration{
                     name: "a".to_string(),
-                    _type: Type{
-                        base_name:      "int".to_string(),
-                        modifiers:      vec!["const".to_string()],
-                        length:         None,
-                        pointer_levels: 0,
+                    _type: Type::Basic{
+                        base_name:  "int".to_string(),
+                        modifiers:  vec!["const".to_string()],
                     },
+                    initializer: None,
                 },
             ],
             functions: vec![],
+            structs: vec![],
         });
     }
 
@@ -106,15 +108,21 @@ mod test {
             globals: vec![
                 Declaration{
                     name: "pointer".to_string(),
-                    _type: Type{
-                        base_name:      "short".to_string(),
-                        modifiers:      vec!["unsigned".to_string()],
-                        length:         None,
-                        pointer_levels: 2,
+                    _type: Type::Pointer{
+                        to: box Type::Pointer{
+                            to: box Type::Basic{
+                                base_name:  "short".to_string(),
+                                modifiers:  vec!["unsigned".to_string()],
+                            },
+                            qualifiers: vec![],
+                        },
+                        qualifiers: vec![],
                     },
+                    initializer: None,
                 },
             ],
             functions: vec![],
+            structs: vec![],
         });
     }
 
@@ -129,24 +137,500 @@ mod test {
             functions: vec![
                 Function{
                     name: "hello".to_string(),
-                    return_type: Type{
-                        base_name:      "void".to_string(),
-                        modifiers:      vec![],
-                        length:         None,
-                        pointer_levels: 0,
+                    return_type: Type::Basic{
+                        base_name:  "void".to_string(),
+                        modifiers:  vec![],
                     },
                     arguments: vec![],
                     statements: vec![],
                 },
             ],
+            structs: vec![],
         });
     }
 
+    fn declaration(name: &str, _type: Type) -> Declaration {
+        Declaration{
+            name:           name.to_string(),
+            _type:          _type,
+            initializer:    None,
+        }
+    }
+
+    fn int_type(modifiers: &[&str], pointer_levels: usize) -> Type {
+        let mut _type = Type::Basic{
+            base_name:  "int".to_string(),
+            modifiers:  modifiers.iter().map(|m| m.to_string()).collect(),
+        };
+        for _ in 0..pointer_levels {
+            _type = pointer(_type);
+        }
+        _type
+    }
+
+    fn pointer(to: Type) -> Type {
+        Type::Pointer{to: box to, qualifiers: vec![]}
+    }
+
+    #[test]
+    fn type_specifier_keywords() {
+        let program = parse_str("long long int a; unsigned b; static const int *c;").unwrap();
+        assert_eq!(program.globals, vec![
+            declaration("a", int_type(&["long", "long"], 0)),
+            declaration("b", int_type(&["unsigned"], 0)),
+            declaration("c", int_type(&["static", "const"], 1)),
+        ]);
+
+        // Without keywords, there's nothing to say this is a declaration.
+        assert!(parse_str("foo a;").is_err());
+        assert!(parse_str("const a;").is_err());
+    }
+
+    fn item(designators: Vec<Designator>, initializer: Initializer) -> DesignatedInitializer {
+        DesignatedInitializer{
+            designators:    designators,
+            initializer:    initializer,
+        }
+    }
+
+    #[test]
+    fn initializers() {
+        let program = parse_str(r##"
+                         const int NUM_ROWS = 100;
+                         int grid = {{1, 0}, {0, 1,},};
+                         int point = {.y = 2, .x = 1};
+                         int sparse = {[3] = 7, 8, .a[2].b = 9};
+                         char *greeting = "hello";
+                         "##).unwrap();
+        let initializers: Vec<Option<Initializer>> = program.globals.into_iter().map(|g| g.initializer).collect();
+
+        let value = |n| item(vec![], Initializer::Expression(int(n)));
+        assert_eq!(initializers, vec![
+            Some(Initializer::Expression(int(100))),
+            Some(Initializer::List(vec![
+                item(vec![], Initializer::List(vec![value(1), value(0)])),
+                item(vec![], Initializer::List(vec![value(0), value(1)])),
+            ])),
+            Some(Initializer::List(vec![
+                item(vec![Designator::Field("y".to_string())], Initializer::Expression(int(2))),
+                item(vec![Designator::Field("x".to_string())], Initializer::Expression(int(1))),
+            ])),
+            Some(Initializer::List(vec![
+                item(vec![Designator::Index(int(3))], Initializer::Expression(int(7))),
+                value(8),
+                item(vec![Designator::Field("a".to_string()), Designator::Index(int(2)), Designator::Field("b".to_string())],
+                     Initializer::Expression(int(9))),
+            ])),
+            Some(Initializer::Expression(Expression::String(StringLiteral::from_str("hello")))),
+        ]);
+
+        assert_eq!(body("int fd = 1; for (int i = fd; i; i--) {}")[0],
+            Statement::Declaration(vec![Declaration{
+                name:           "fd".to_string(),
+                _type:          int_type(&[], 0),
+                initializer:    Some(Initializer::Expression(int(1))),
+            }]));
+
+        assert!(parse_str("int a = ;").is_err());
+        assert!(parse_str("int a = 1, 2;").is_err());
+        assert!(parse_str("int a = {};").is_err());
+        assert!(parse_str("int a = {1 2};").is_err());
+        assert!(parse_str("int a = {.x 1};").is_err());
+        assert!(parse_str("int a = {[1 = 1};").is_err());
+        assert!(parse_str("int f(int a = 1) {}").is_err());
+    }
+
+    /**
+     * `return x;` used to be mistaken for a declaration of `x` with type `return`.
+     */
+    #[test]
+    fn statements_dispatch_on_keywords() {
+        let program = parse_str(r##"
+                         int f(void) {
+                             int x;
+                             x;
+                             return x;
+                             return;
+                             break;
+                             continue;
+                         }
+                         "##).unwrap();
+
+        assert_eq!(program.functions[0].arguments, vec![]);
+        assert_eq!(program.functions[0].statements, vec![
+            Statement::Declaration(vec![declaration("x", int_type(&[], 0))]),
+            Statement::Expression(Expression::Variable("x".to_string())),
+            Statement::Return(Some(Expression::Variable("x".to_string()))),
+            Statement::Return(None),
+            Statement::Break,
+            Statement::Continue,
+        ]);
+
+        assert!(parse_str("int f() { return x }").is_err());
+        assert!(parse_str("int f() { int return; }").is_err());
+    }
+
+    fn int(n: u64) -> Expression {
+        Expression::Number(Number::Int(n, IntType::Int))
+    }
+
+    fn body(s: &str) -> Vec<Statement> {
+        let program = parse_str(&format!("void f() {{ {} }}", s)).unwrap();
+        program.functions[0].statements.clone()
+    }
+
+    fn expr(e: Expression) -> Box<Statement> {
+        box Statement::Expression(e)
+    }
+
+    #[test]
+    fn if_and_else() {
+        assert_eq!(body("if (a) b; else { c; d; }"), vec![
+            Statement::If{
+                condition:  var("a"),
+                then:       expr(var("b")),
+                _else:      Some(box Statement::Compound(vec![
+                    Statement::Expression(var("c")),
+                    Statement::Expression(var("d")),
+                ])),
+            },
+        ]);
+
+        // The `else` goes with the closest `if`.
+        assert_eq!(body("if (a) if (b) c; else d;"), vec![
+            Statement::If{
+                condition:  var("a"),
+                then:       box Statement::If{
+                    condition:  var("b"),
+                    then:       expr(var("c")),
+                    _else:      Some(expr(var("d"))),
+                },
+                _else:      None,
+            },
+        ]);
+
+        assert!(parse_str("void f() { if a; }").is_err());
+        assert!(parse_str("void f() { if (a) }").is_err());
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(body("while (a) { break; continue; } do a; while (b); while (*p);"), vec![
+            Statement::While{condition: var("a"), body: box Statement::Compound(vec![Statement::Break, Statement::Continue])},
+            Statement::DoWhile{body: expr(var("a")), condition: var("b")},
+            Statement::While{
+                condition:  Expression::UnaryOp(UnaryOp::Dereference, box var("p")),
+                body:       box Statement::Empty,
+            },
+        ]);
+
+        assert_eq!(body("for (int i; i < 10; i += 1) a; for (i = 0;;) {}"), vec![
+            Statement::For{
+                init:       Some(box Statement::Declaration(vec![declaration("i", int_type(&[], 0))])),
+                condition:  Some(binary(var("i"), BinaryOp::Less, int(10))),
+                step:       Some(binary(var("i"), BinaryOp::AddAssign, int(1))),
+                body:       expr(var("a")),
+            },
+            Statement::For{
+                init:       Some(expr(binary(var("i"), BinaryOp::Assign, int(0)))),
+                condition:  None,
+                step:       None,
+                body:       box Statement::Compound(vec![]),
+            },
+        ]);
+
+        assert!(parse_str("void f() { do a; }").is_err());
+        assert!(parse_str("void f() { do a; while (b) }").is_err());
+        assert!(parse_str("void f() { for (a; b) c; }").is_err());
+    }
+
+    #[test]
+    fn switch_and_labels() {
+        assert_eq!(body("switch (x) { case 1: case 2: a; break; default: { b; } }"), vec![
+            Statement::Switch{value: var("x"), body: box Statement::Compound(vec![
+                Statement::Case(int(1), box Statement::Case(int(2), expr(var("a")))),
+                Statement::Break,
+                Statement::Default(box Statement::Compound(vec![Statement::Expression(var("b"))])),
+            ])},
+        ]);
+
+        assert_eq!(body("again: x; goto again; end: ;"), vec![
+            Statement::Labeled("again".to_string(), expr(var("x"))),
+            Statement::Goto("again".to_string()),
+            Statement::Labeled("end".to_string(), box Statement::Empty),
+        ]);
+
+        // A label needs a statement after it, and a goto needs a label.
+        assert!(parse_str("void f() { end: }").is_err());
+        assert!(parse_str("void f() { goto; }").is_err());
+        assert!(parse_str("void f() { case 1 a; }").is_err());
+    }
+
+    #[test]
+    fn nested_blocks() {
+        assert_eq!(body("int x; { int x; { x; } {} }"), vec![
+            Statement::Declaration(vec![declaration("x", int_type(&[], 0))]),
+            Statement::Compound(vec![
+                Statement::Declaration(vec![declaration("x", int_type(&[], 0))]),
+                Statement::Compound(vec![Statement::Expression(var("x"))]),
+                Statement::Compound(vec![]),
+            ]),
+        ]);
+
+        assert!(parse_str("void f() { { x; }").is_err());
+    }
+
+    fn array(of: Type, length: Option<u64>) -> Type {
+        Type::Array{of: box of, length: length.map(|n| box int(n))}
+    }
+
+    fn function(returns: Type, parameters: Vec<Type>, variadic: bool) -> Type {
+        Type::Function{returns: box returns, parameters: parameters, variadic: variadic}
+    }
+
+    fn char_type() -> Type {
+        Type::Basic{base_name: "char".to_string(), modifiers: vec![]}
+    }
+
+    #[test]
+    fn declarators() {
+        let types = |s: &str| -> Vec<Type> {
+            parse_str(s).unwrap().globals.into_iter().map(|g| g._type).collect()
+        };
+        assert_eq!(types("int a[10]; int m[3][4]; int *p[2]; int (*q)[2]; int x[];"), vec![
+            array(int_type(&[], 0), Some(10)),
+            array(array(int_type(&[], 0), Some(4)), Some(3)),
+            array(int_type(&[], 1), Some(2)),
+            pointer(array(int_type(&[], 0), Some(2))),
+            array(int_type(&[], 0), None),
+        ]);
+        assert_eq!(types("int (*fp)(int, char); char *(*argv)[]; int *(*(f))(void);"), vec![
+            pointer(function(int_type(&[], 0), vec![int_type(&[], 0), char_type()], false)),
+            pointer(array(pointer(char_type()), None)),
+            pointer(function(int_type(&[], 1), vec![], false)),
+        ]);
+
+        // Prototypes are declarations of a function type, and their parameters needn't be named.
+        assert_eq!(types("int printf(const char *, ...); void (*signal(int, void (*)(int)))(int);"), vec![
+            function(int_type(&[], 0), vec![pointer(Type::Basic{
+                base_name:  "char".to_string(),
+                modifiers:  vec!["const".to_string()],
+            })], true),
+            function(pointer(function(Type::Basic{base_name: "void".to_string(), modifiers: vec![]}, vec![int_type(&[], 0)], false)),
+                     vec![int_type(&[], 0), pointer(function(Type::Basic{base_name: "void".to_string(), modifiers: vec![]}, vec![int_type(&[], 0)], false))],
+                     false),
+        ]);
+
+        // Qualifiers after a `*` apply to the pointer.
+        assert_eq!(types("int *const volatile p;"), vec![Type::Pointer{
+            to:         box int_type(&[], 0),
+            qualifiers: vec!["const".to_string(), "volatile".to_string()],
+        }]);
+
+        // A function's parameters can have any type, and must be named in a definition.
+        let program = parse_str("int main(int argc, char *argv[]) { return argc; }").unwrap();
+        assert_eq!(program.functions[0].arguments, vec![
+            declaration("argc", int_type(&[], 0)),
+            declaration("argv", array(pointer(char_type()), None)),
+        ]);
+        assert_eq!(parse_str("int f(int) { return 0; }").unwrap_err().message, "parameter name omitted");
+
+        assert_eq!(parse_str("int f()[3];").unwrap_err().message, "function can't return an array");
+        assert_eq!(parse_str("int f[3]();").unwrap_err().message, "declared as an array of functions");
+        assert_eq!(parse_str("int *;").unwrap_err().message, "expected variable name in declaration, got Semicolon");
+    }
+
+    #[test]
+    fn multiple_declarators() {
+        let program = parse_str("int a, *b, c[3] = {0}, f(int);").unwrap();
+        assert_eq!(program.globals, vec![
+            declaration("a", int_type(&[], 0)),
+            declaration("b", int_type(&[], 1)),
+            Declaration{
+                name:           "c".to_string(),
+                _type:          array(int_type(&[], 0), Some(3)),
+                initializer:    Some(Initializer::List(vec![item(vec![], Initializer::Expression(int(0)))])),
+            },
+            declaration("f", function(int_type(&[], 0), vec![int_type(&[], 0)], false)),
+        ]);
+
+        // The specifiers are shared, but each declarator has its own pointers.
+        assert_eq!(body("const char *s = 0, c;")[0], Statement::Declaration(vec![
+            Declaration{
+                name:           "s".to_string(),
+                _type:          pointer(Type::Basic{base_name: "char".to_string(), modifiers: vec!["const".to_string()]}),
+                initializer:    Some(Initializer::Expression(int(0))),
+            },
+            declaration("c", Type::Basic{base_name: "char".to_string(), modifiers: vec!["const".to_string()]}),
+        ]));
+        assert_eq!(body("for (int i, j = 1;;) ;")[0], Statement::For{
+            init:       Some(box Statement::Declaration(vec![
+                declaration("i", int_type(&[], 0)),
+                Declaration{
+                    name:           "j".to_string(),
+                    _type:          int_type(&[], 0),
+                    initializer:    Some(Initializer::Expression(int(1))),
+                },
+            ])),
+            condition:  None,
+            step:       None,
+            body:       box Statement::Empty,
+        });
+
+        // A function definition can't share its declaration.
+        assert!(parse_str("int a, f() {}").is_err());
+        assert!(parse_str("int f() {}, a;").is_err());
+        assert!(parse_str("int a,;").is_err());
+    }
+
+    fn struct_type(kind: StructKind, tag: Option<&str>, fields: Option<Vec<Declaration>>) -> Type {
+        Type::Struct{
+            _struct:    Struct{kind: kind, tag: tag.map(|t| t.to_string()), fields: fields},
+            modifiers:  vec![],
+        }
+    }
+
+    #[test]
+    fn structs_and_unions() {
+        let point = Struct{
+            kind:   StructKind::Struct,
+            tag:    Some("point".to_string()),
+            fields: Some(vec![declaration("x", int_type(&[], 0)), declaration("y", int_type(&[], 0))]),
+        };
+        let program = parse_str("struct point { int x, y; }; union list; \
+                                 struct point p, *q; union { int i; struct point p; } u;").unwrap();
+        assert_eq!(program.structs, vec![point, Struct{
+            kind:   StructKind::Union,
+            tag:    Some("list".to_string()),
+            fields: None,
+        }]);
+        assert_eq!(program.globals, vec![
+            declaration("p", struct_type(StructKind::Struct, Some("point"), None)),
+            declaration("q", pointer(struct_type(StructKind::Struct, Some("point"), None))),
+            declaration("u", struct_type(StructKind::Union, None, Some(vec![
+                declaration("i", int_type(&[], 0)),
+                declaration("p", struct_type(StructKind::Struct, Some("point"), None)),
+            ]))),
+        ]);
+
+        // Definitions can be nested, and appear anywhere a type can.
+        assert_eq!(body("static struct outer { struct inner { int a; } in; } o;")[0], Statement::Declaration(vec![
+            declaration("o", Type::Struct{
+                _struct:    Struct{
+                    kind:   StructKind::Struct,
+                    tag:    Some("outer".to_string()),
+                    fields: Some(vec![declaration("in", struct_type(StructKind::Struct, Some("inner"), Some(vec![
+                        declaration("a", int_type(&[], 0)),
+                    ])))]),
+                },
+                modifiers:  vec!["static".to_string()],
+            }),
+        ]));
+        assert_eq!(body("struct node;")[0], Statement::Struct(Struct{
+            kind:   StructKind::Struct,
+            tag:    Some("node".to_string()),
+            fields: None,
+        }));
+        assert_eq!(parse_expr_str("(struct point *) p").unwrap(),
+                   Expression::Cast(pointer(struct_type(StructKind::Struct, Some("point"), None)), box var("p")));
+
+        assert_eq!(parse_str("struct;").unwrap_err().message, "expected '{' or tag name after 'struct', got Semicolon");
+        assert_eq!(parse_str("struct s int a;").unwrap_err().message, "two or more data types in declaration specifiers");
+        assert_eq!(parse_str("struct s { int x } a;").unwrap_err().message, "expected ';' after struct member, got RBrace");
+        assert!(parse_str("struct s { int x = 1; };").is_err());
+        assert!(parse_str("struct s { int x;").is_err());
+    }
+
+    #[test]
+    fn casts() {
+        assert_eq!(parse_expr_str("(unsigned char) -x").unwrap(), Expression::Cast(
+            Type::Basic{base_name: "char".to_string(), modifiers: vec!["unsigned".to_string()]},
+            box Expression::UnaryOp(UnaryOp::Negate, box var("x")),
+        ));
+        assert_eq!(parse_expr_str("(int (*)[2]) p + 1").unwrap(), binary(
+            Expression::Cast(pointer(array(int_type(&[], 0), Some(2))), box var("p")),
+            BinaryOp::Add,
+            int(1),
+        ));
+
+        // Parentheses around an expression are still just parentheses.
+        assert_eq!(parse_expr_str("(x) - 1").unwrap(), binary(Expression::Parenthetical(box var("x")), BinaryOp::Subtract, int(1)));
+        assert_eq!(parse_expr_str("(int x) 1").unwrap_err().message, "type name can't declare a variable");
+    }
+
+    #[test]
+    fn prototypes_are_not_definitions() {
+        let program = parse_str("void write_int(int fd, int n); int main() { write_int(1, 2); }").unwrap();
+        let names: Vec<&str> = program.functions.iter().map(|f| &f.name[..]).collect();
+        assert_eq!(names, vec!["main"]);
+        assert_eq!(program.globals, vec![declaration("write_int", function(
+            Type::Basic{base_name: "void".to_string(), modifiers: vec![]},
+            vec![int_type(&[], 0), int_type(&[], 0)],
+            false,
+        ))]);
+
+        // A function body has to be a block.
+        assert!(parse_str("int main() return 0;").is_err());
+    }
+
+    #[test]
+    fn typedef_names() {
+        let program = parse_str("typedef unsigned long size_t, *size_p; size_t n; static const size_p p; \
+                                 typedef struct node { struct node *next; } node; node list;").unwrap();
+        let size_t = Type::Basic{base_name: "long".to_string(), modifiers: vec!["unsigned".to_string()]};
+        assert_eq!(program.globals, vec![
+            declaration("n", size_t),
+            declaration("p", Type::Pointer{
+                to:         box Type::Basic{
+                    base_name:  "long".to_string(),
+                    modifiers:  vec!["static".to_string(), "unsigned".to_string()],
+                },
+                qualifiers: vec!["const".to_string()],
+            }),
+            declaration("list", struct_type(StructKind::Struct, Some("node"), None)),
+        ]);
+        assert_eq!(program.structs, vec![Struct{
+            kind:   StructKind::Struct,
+            tag:    Some("node".to_string()),
+            fields: Some(vec![declaration("next", pointer(struct_type(StructKind::Struct, Some("node"), None)))]),
+        }]);
+
+        // A typedef name is hidden by a variable of the same name, until the end of its scope.
+        assert_eq!(body("typedef int T; T * x; (T) x; { int T; T * x; } T y;"), vec![
+            Statement::Empty,
+            Statement::Declaration(vec![declaration("x", int_type(&[], 1))]),
+            Statement::Expression(Expression::Cast(int_type(&[], 0), box var("x"))),
+            Statement::Compound(vec![
+                Statement::Declaration(vec![declaration("T", int_type(&[], 0))]),
+                Statement::Expression(binary(var("T"), BinaryOp::Multiply, var("x"))),
+            ]),
+            Statement::Declaration(vec![declaration("y", int_type(&[], 0))]),
+        ]);
+        assert!(parse_str("void f() { { typedef int T; } T x; }").is_err());
+
+        // An argument hides one too, and so does a variable in its own initializer, since each
+        // name is in scope from the end of its declarator.
+        let program = parse_str("typedef int T; void f(int T) { T * x; }").unwrap();
+        assert_eq!(program.functions[0].statements, vec![
+            Statement::Expression(binary(var("T"), BinaryOp::Multiply, var("x"))),
+        ]);
+        assert_eq!(body("typedef int T; T T = sizeof(T);"), vec![
+            Statement::Empty,
+            Statement::Declaration(vec![Declaration{
+                name:           "T".to_string(),
+                _type:          int_type(&[], 0),
+                initializer:    Some(Initializer::Expression(Expression::Sizeof(box Expression::Parenthetical(box var("T"))))),
+            }]),
+        ]);
+        assert_eq!(parse_str("typedef int T; T long x;").unwrap_err().message,
+                   "two or more data types in declaration specifiers");
+    }
+
     #[test]
     fn empty_function_call() {
         let expr = parse_expr_str("hello()").unwrap();
         assert_eq!(expr, Expression::FunctionCall{
-            name: "hello".to_string(),
+            function: box var("hello"),
             args: vec![],
         });
     }
@@ -155,38 +639,273 @@ mod test {
     fn function_call_with_parameters() {
         let expr = parse_expr_str(r##"concatenate_these_strings("alpha", "beta", "charlie", "delta")"##).unwrap();
         assert_eq!(expr, Expression::FunctionCall{
-            name: "concatenate_these_strings".to_string(),
+            function: box var("concatenate_these_strings"),
             args: vec![
-                Expression::String("alpha".to_string()),
-                Expression::String("beta".to_string()),
-                Expression::String("charlie".to_string()),
-                Expression::String("delta".to_string()),
+                Expression::String(StringLiteral::from_str("alpha")),
+                Expression::String(StringLiteral::from_str("beta")),
+                Expression::String(StringLiteral::from_str("charlie")),
+                Expression::String(StringLiteral::from_str("delta")),
             ],
         });
     }
 
 
+    #[test]
+    fn postfix_operators() {
+        let call = |function, args| Expression::FunctionCall{function: box function, args: args};
+        let index = |array, index| Expression::ArrayIndex{array: box array, index: box index};
+        let member = |s, field: &str| Expression::MemberAccess{struct_name: box s, field_name: field.to_string()};
+
+        assert_eq!(parse_expr_str("table[i](x)").unwrap(), call(index(var("table"), var("i")), vec![var("x")]));
+        assert_eq!(parse_expr_str("get_cb()(1)").unwrap(), call(call(var("get_cb"), vec![]), vec![int(1)]));
+        assert_eq!(parse_expr_str("m[i][j + 1]").unwrap(), index(index(var("m"), var("i")), binary(var("j"), BinaryOp::Add, int(1))));
+
+        // `p->x` means `(*p).x`.
+        assert_eq!(parse_expr_str("a.b->c.d").unwrap(),
+            member(member(Expression::UnaryOp(UnaryOp::Dereference, box member(var("a"), "b")), "c"), "d"));
+        assert_eq!(parse_expr_str("s.handlers[2](s.arg)").unwrap(),
+            call(index(member(var("s"), "handlers"), int(2)), vec![member(var("s"), "arg")]));
+
+        // Postfix operators bind tighter than prefix ones.
+        assert_eq!(parse_expr_str("-*p++").unwrap(),
+            Expression::UnaryOp(UnaryOp::Negate,
+                box Expression::UnaryOp(UnaryOp::Dereference,
+                    box Expression::UnaryOp(UnaryOp::PostIncrement, box var("p")))));
+        assert_eq!(parse_expr_str("a++ + ++b - c--").unwrap(),
+            binary(
+                binary(Expression::UnaryOp(UnaryOp::PostIncrement, box var("a")), BinaryOp::Add,
+                       Expression::UnaryOp(UnaryOp::PreIncrement, box var("b"))),
+                BinaryOp::Subtract,
+                Expression::UnaryOp(UnaryOp::PostDecrement, box var("c"))));
+        assert_eq!(parse_expr_str("(f)(x)[0]").unwrap(),
+            index(call(Expression::Parenthetical(box var("f")), vec![var("x")]), int(0)));
+
+        assert!(parse_expr_str("a[1").is_err());
+        assert!(parse_expr_str("a.1").is_err());
+        assert!(parse_expr_str("p->").is_err());
+        assert!(parse_expr_str("f(1,)").is_err());
+    }
+
+    #[test]
+    fn string_concatenation() {
+        assert_eq!(parse_expr_str(r#""hello, " "world" "!""#).unwrap(),
+                   Expression::String(StringLiteral::from_str("hello, world!")));
+
+        // Escapes end at the end of their own literal.
+        assert_eq!(parse_expr_str(r#""\x12" "3""#).unwrap(),
+                   Expression::String(StringLiteral{encoding: Encoding::Char, units: vec![0x12, 0x33]}));
+
+        // A prefix on any of the pieces applies to the whole thing.
+        assert_eq!(parse_expr_str(r#""é" L"x" "\xff""#).unwrap(),
+                   Expression::String(StringLiteral{encoding: Encoding::Wide, units: vec![0xe9, 0x78, 0xff]}));
+        assert_eq!(parse_expr_str(r#"u8"a" "b""#).unwrap(),
+                   Expression::String(StringLiteral{encoding: Encoding::Utf8, units: vec![0x61, 0x62]}));
+        assert!(parse_expr_str(r#"L"a" u"b""#).is_err());
+        assert!(parse_expr_str(r#"u8"a" U"b""#).is_err());
+    }
+
     #[test]
     fn operator_precedence() {
         let expr = parse_expr_str("1 - 2 * 3 + 4").unwrap();
 
         assert_eq!(expr,
             Expression::BinaryOp(
-                box Expression::Number(Number::Int(1)),
-                BinaryOp::Subtract,
                 box Expression::BinaryOp(
+                    box Expression::Number(Number::Int(1, IntType::Int)),
+                    BinaryOp::Subtract,
                     box Expression::BinaryOp(
-                        box Expression::Number(Number::Int(2)),
+                        box Expression::Number(Number::Int(2, IntType::Int)),
                         BinaryOp::Multiply,
-                        box Expression::Number(Number::Int(3)),
+                        box Expression::Number(Number::Int(3, IntType::Int)),
                     ),
-                    BinaryOp::Add,
-                    box Expression::Number(Number::Int(4)),
                 ),
+                BinaryOp::Add,
+                box Expression::Number(Number::Int(4, IntType::Int)),
             )
         );
     }
 
+    fn var(name: &str) -> Expression {
+        Expression::Variable(name.to_string())
+    }
+
+    fn binary(lhs: Expression, op: BinaryOp, rhs: Expression) -> Expression {
+        Expression::BinaryOp(box lhs, op, box rhs)
+    }
+
+    #[test]
+    fn left_associativity() {
+        assert_eq!(parse_expr_str("a - b - c").unwrap(),
+            binary(binary(var("a"), BinaryOp::Subtract, var("b")), BinaryOp::Subtract, var("c")));
+        assert_eq!(parse_expr_str("a / b % c").unwrap(),
+            binary(binary(var("a"), BinaryOp::Divide, var("b")), BinaryOp::Modulo, var("c")));
+        assert_eq!(parse_expr_str("a << b >> c").unwrap(),
+            binary(binary(var("a"), BinaryOp::ShiftLeft, var("b")), BinaryOp::ShiftRight, var("c")));
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        assert_eq!(parse_expr_str("a = b += c").unwrap(),
+            binary(var("a"), BinaryOp::Assign, binary(var("b"), BinaryOp::AddAssign, var("c"))));
+        assert_eq!(parse_expr_str("a <<= b || c").unwrap(),
+            binary(var("a"), BinaryOp::ShiftLeftAssign, binary(var("b"), BinaryOp::Or, var("c"))));
+    }
+
+    #[test]
+    fn c_precedence_levels() {
+        // || < && < | < ^ < & < == < < < << < +
+        assert_eq!(parse_expr_str("a || b && c | d ^ e & f == g < h << i + j").unwrap(),
+            binary(var("a"), BinaryOp::Or,
+                binary(var("b"), BinaryOp::And,
+                    binary(var("c"), BinaryOp::BitwiseOr,
+                        binary(var("d"), BinaryOp::BitwiseXor,
+                            binary(var("e"), BinaryOp::BitwiseAnd,
+                                binary(var("f"), BinaryOp::Equal,
+                                    binary(var("g"), BinaryOp::Less,
+                                        binary(var("h"), BinaryOp::ShiftLeft,
+                                            binary(var("i"), BinaryOp::Add, var("j")))))))))));
+
+        // The same chain written the other way around.
+        assert_eq!(parse_expr_str("a + b << c >= d != e & f ^ g | h && i || j").unwrap(),
+            binary(
+                binary(
+                    binary(
+                        binary(
+                            binary(
+                                binary(
+                                    binary(
+                                        binary(
+                                            binary(var("a"), BinaryOp::Add, var("b")),
+                                            BinaryOp::ShiftLeft, var("c")),
+                                        BinaryOp::GreaterEqual, var("d")),
+                                    BinaryOp::NotEqual, var("e")),
+                                BinaryOp::BitwiseAnd, var("f")),
+                            BinaryOp::BitwiseXor, var("g")),
+                        BinaryOp::BitwiseOr, var("h")),
+                    BinaryOp::And, var("i")),
+                BinaryOp::Or, var("j")));
+    }
+
+    fn conditional(condition: Expression, then: Expression, _else: Expression) -> Expression {
+        Expression::Conditional{condition: box condition, then: box then, _else: box _else}
+    }
+
+    #[test]
+    fn conditional_operator() {
+        assert_eq!(parse_expr_str("a || b ? c : d").unwrap(),
+            conditional(binary(var("a"), BinaryOp::Or, var("b")), var("c"), var("d")));
+
+        // It's right-associative, and anything can go in the middle.
+        assert_eq!(parse_expr_str("a ? b, c : d ? e : f").unwrap(),
+            conditional(var("a"), binary(var("b"), BinaryOp::Comma, var("c")), conditional(var("d"), var("e"), var("f"))));
+
+        // Assignment is looser on both sides. Like clang, we parse `a ? b : c = d` as an
+        // assignment to the conditional expression, and leave it to later to reject it.
+        assert_eq!(parse_expr_str("a = b ? c : d").unwrap(),
+            binary(var("a"), BinaryOp::Assign, conditional(var("b"), var("c"), var("d"))));
+        assert_eq!(parse_expr_str("a ? b : c = d").unwrap(),
+            binary(conditional(var("a"), var("b"), var("c")), BinaryOp::Assign, var("d")));
+
+        assert!(parse_expr_str("a ? b").is_err());
+        assert!(parse_expr_str("a ? b : c : d").is_err());
+    }
+
+    #[test]
+    fn comma_and_parentheses() {
+        assert_eq!(parse_expr_str("a = 1, b += 2, c").unwrap(),
+            binary(
+                binary(binary(var("a"), BinaryOp::Assign, int(1)), BinaryOp::Comma, binary(var("b"), BinaryOp::AddAssign, int(2))),
+                BinaryOp::Comma, var("c")));
+        assert_eq!(parse_expr_str("(a + b) * (c, d)").unwrap(),
+            binary(
+                Expression::Parenthetical(box binary(var("a"), BinaryOp::Add, var("b"))),
+                BinaryOp::Multiply,
+                Expression::Parenthetical(box binary(var("c"), BinaryOp::Comma, var("d")))));
+
+        // Commas between arguments separate them, unless they're in parentheses.
+        assert_eq!(parse_expr_str("f(a, (b, c), d = e)").unwrap(), Expression::FunctionCall{
+            function: box var("f"),
+            args: vec![
+                var("a"),
+                Expression::Parenthetical(box binary(var("b"), BinaryOp::Comma, var("c"))),
+                binary(var("d"), BinaryOp::Assign, var("e")),
+            ],
+        });
+
+        assert!(parse_expr_str("(a + b").is_err());
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(parse_expr_str("!~-*&++--a").unwrap(),
+            Expression::UnaryOp(UnaryOp::Not,
+                box Expression::UnaryOp(UnaryOp::BitwiseNot,
+                    box Expression::UnaryOp(UnaryOp::Negate,
+                        box Expression::UnaryOp(UnaryOp::Dereference,
+                            box Expression::UnaryOp(UnaryOp::Reference,
+                                box Expression::UnaryOp(UnaryOp::PreIncrement,
+                                    box Expression::UnaryOp(UnaryOp::PreDecrement,
+                                        box var("a")))))))));
+
+        // Unary operators bind tighter than any binary operator.
+        assert_eq!(parse_expr_str("-a * !b").unwrap(),
+            binary(
+                Expression::UnaryOp(UnaryOp::Negate, box var("a")),
+                BinaryOp::Multiply,
+                Expression::UnaryOp(UnaryOp::Not, box var("b"))));
+
+        // So do `sizeof` and `_Alignof`, but not as tightly as postfix operators. A type in
+        // parentheses after `sizeof` is the whole operand, while an expression in parentheses is
+        // only the start of one.
+        assert_eq!(parse_expr_str("sizeof -a[1] + sizeof sizeof b").unwrap(),
+            binary(
+                Expression::Sizeof(box Expression::UnaryOp(UnaryOp::Negate, box Expression::ArrayIndex{
+                    array:  box var("a"),
+                    index:  box int(1),
+                })),
+                BinaryOp::Add,
+                Expression::Sizeof(box Expression::Sizeof(box var("b")))));
+        assert_eq!(parse_expr_str("sizeof(int *) * 2").unwrap(),
+            binary(Expression::SizeofType(int_type(&[], 1)), BinaryOp::Multiply, int(2)));
+        assert_eq!(parse_expr_str("sizeof (a).b").unwrap(),
+            Expression::Sizeof(box Expression::MemberAccess{
+                struct_name:    box Expression::Parenthetical(box var("a")),
+                field_name:     "b".to_string(),
+            }));
+        assert_eq!(parse_expr_str("!_Alignof(long) - 1").unwrap(),
+            binary(
+                Expression::UnaryOp(UnaryOp::Not, box Expression::Alignof(Type::Basic{
+                    base_name:  "long".to_string(),
+                    modifiers:  vec![],
+                })),
+                BinaryOp::Subtract,
+                int(1)));
+        assert!(parse_expr_str("_Alignof a").is_err());
+        assert!(parse_expr_str("sizeof").is_err());
+    }
+
+    #[test]
+    fn error_positions() {
+        let err = parse_str("int a;\nint *b c;").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 8));
+        assert_eq!(err.message, "expected semicolon after global variable declaration");
+
+        let err = parse_str("void f() {\n    1 + 2 3;\n}").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (2, 11));
+
+        let err = parse_expr_str("1 +").unwrap_err();
+        assert_eq!((err.span.line, err.span.column), (1, 3));
+    }
+
+    #[test]
+    fn error_tokens_are_skipped() {
+        let (lexemes, errors) = lexer::lex_file("int a; $ int b @;", 0, lexer::Options::default());
+        assert_eq!(errors.len(), 2);
+        let program = parse(lexemes).unwrap();
+        let names: Vec<&str> = program.globals.iter().map(|g| &g.name[..]).collect();
+        assert_eq!(names, vec!["a", "b"]);
+    }
+
     /**
      * Test a simple function definition with a single statement inside.
      */
@@ -203,33 +922,34 @@ mod test {
             functions: vec![
                 Function{
                     name: "get_num_cores".to_string(),
-                    return_type: Type{
-                        base_name:      "void".to_string(),
-                        modifiers:      vec!["inline".to_string(), "const".to_string()],
-                        length:         None,
-                        pointer_levels: 0,
+                    return_type: Type::Basic{
+                        base_name:  "void".to_string(),
+                        modifiers:  vec!["inline".to_string(), "const".to_string()],
                     },
                     arguments: vec![
                         Declaration{
                             name: "a".to_string(),
-                            _type: Type{
-                                base_name:      "int".to_string(),
-                                modifiers:      vec![],
-                                length:         None,
-                                pointer_levels: 1,
-                            }
+                            _type: Type::Pointer{
+                                to: box Type::Basic{
+                                    base_name:  "int".to_string(),
+                                    modifiers:  vec![],
+                                },
+                                qualifiers: vec![],
+                            },
+                            initializer: None,
                         },
                     ],
                     statements: vec![
                         Statement::Expression(
                             Expression::UnaryOp(
                                 UnaryOp::Negate,
-                                box Expression::Number(Number::Int(10)),
+                                box Expression::Number(Number::Int(10, IntType::Int)),
                             )
                         ),
                     ],
                 },
             ],
+            structs: vec![],
         });
     }
 }
@@ -0,0 +1,306 @@
+/*!
+ * Conditional compilation
+ * =======================
+ *
+ * `#if`, `#ifdef`, `#ifndef`, `#elif`, `#else` and `#endif`.
+ *
+ * Each file keeps a stack of the conditionals it's inside. When a group is left out, we skip
+ * straight over its tokens, only looking for the directives that might end it. Anything can be in
+ * a skipped group, so the lexer's complaints about it are thrown away unread.
+ */
+
+use ast::{IntType, Number};
+use parser::lexer::{Lexeme, Token};
+use preprocessor::{directive_at, expression, macro_name, name, Preprocessor};
+use preprocessor::macros::PPToken;
+use source::Diagnostic;
+
+/**
+ * Conditional is an `#if` (or `#ifdef` or `#ifndef`) whose `#endif` hasn't been reached yet.
+ */
+pub struct Conditional {
+    directive:  Lexeme, // the `if` of the `#if`, to point at if it's never closed
+    taken:      bool,   // whether one of the groups has been (or is being) read
+    seen_else:  bool,
+}
+
+impl Preprocessor {
+    /**
+     * Carry out a conditional directive. `directive` is the name of the directive, and `rest` is
+     * the rest of the line.
+     */
+    pub(super) fn conditional(&mut self, directive: Lexeme, rest: &[Lexeme]) {
+        match name(directive.token) {
+            Some("if") => {
+                let taken = self.condition(directive, rest);
+                self.open_conditional(directive, taken);
+            },
+            Some(which @ "ifdef") | Some(which @ "ifndef") => {
+                let taken = match rest.first() {
+                    Some(lexeme) => match macro_name(lexeme.token) {
+                        Some(name) => self.macros.contains_key(&name) == (which == "ifdef"),
+                        None => {
+                            self.error(*lexeme, "macro name must be an identifier");
+                            false
+                        },
+                    },
+                    None => {
+                        self.error(directive, "macro name missing");
+                        false
+                    },
+                };
+                self.open_conditional(directive, taken);
+            },
+
+            Some("elif") => {
+                let (taken, seen_else) = match self.conditionals().last() {
+                    Some(open) => (open.taken, open.seen_else),
+                    None => return self.error(directive, "#elif without #if"),
+                };
+                if seen_else {
+                    self.error(directive, "#elif after #else");
+                }
+
+                // Once a group has been read, the rest are skipped without being evaluated.
+                if !taken && !seen_else && self.condition(directive, rest) {
+                    self.conditionals().last_mut().unwrap().taken = true;
+                } else {
+                    self.skip_group();
+                }
+            },
+            Some("else") => {
+                let taken = match self.conditionals().last_mut() {
+                    Some(open) if open.seen_else => None,
+                    Some(open) => {
+                        open.seen_else = true;
+                        Some(open.taken)
+                    },
+                    None => return self.error(directive, "#else without #if"),
+                };
+                match taken {
+                    Some(false) => self.conditionals().last_mut().unwrap().taken = true,
+                    Some(true) => self.skip_group(),
+                    None => {
+                        self.error(directive, "#else after #else");
+                        self.skip_group();
+                    },
+                }
+            },
+            Some("endif") => {
+                if self.conditionals().pop().is_none() {
+                    self.error(directive, "#endif without #if");
+                }
+            },
+
+            _ => unreachable!(),
+        }
+    }
+
+    /**
+     * Report any conditionals left open at the end of a file.
+     */
+    pub(super) fn close_conditionals(&mut self, open: Vec<Conditional>) {
+        for conditional in open {
+            self.error(conditional.directive, "unterminated conditional directive");
+        }
+    }
+
+    fn conditionals(&mut self) -> &mut Vec<Conditional> {
+        &mut self.stack.last_mut().unwrap().conditionals
+    }
+
+    fn open_conditional(&mut self, directive: Lexeme, taken: bool) {
+        self.conditionals().push(Conditional{
+            directive:  directive,
+            taken:      taken,
+            seen_else:  false,
+        });
+        if !taken {
+            self.skip_group();
+        }
+    }
+
+    /**
+     * Evaluate the condition of an `#if` or `#elif`. A condition with an error in it is false.
+     */
+    fn condition(&mut self, directive: Lexeme, rest: &[Lexeme]) -> bool {
+        let tokens = match self.replace_defined(rest) {
+            Ok(tokens) => tokens,
+            Err(diagnostic) => {
+                self.errors.push(diagnostic);
+                return false;
+            },
+        };
+        let expanded: Vec<Lexeme> = self.expand_all(tokens).into_iter().map(|t| t.lexeme).collect();
+
+        let end = rest.last().unwrap_or(&directive).span;
+        match expression::evaluate(&expanded, end) {
+            Ok(value) => value.value != 0,
+            Err(diagnostic) => {
+                self.errors.push(diagnostic);
+                false
+            },
+        }
+    }
+
+    /**
+     * Replace each `defined NAME` and `defined(NAME)` with 1 if NAME is a macro and 0 otherwise.
+     * This has to happen before macro expansion, or NAME would be expanded.
+     */
+    fn replace_defined(&self, rest: &[Lexeme]) -> Result<Vec<PPToken>, Diagnostic> {
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < rest.len() {
+            let lexeme = rest[i];
+            i += 1;
+            if name(lexeme.token) != Some("defined") {
+                tokens.push(PPToken::new(lexeme));
+                continue;
+            }
+
+            let parens = rest.get(i).map(|l| l.token) == Some(Token::LParen);
+            if parens {
+                i += 1;
+            }
+            let defined = match rest.get(i).and_then(|l| macro_name(l.token)) {
+                Some(name) => self.macros.contains_key(&name),
+                None => return Err(Diagnostic::new(rest.get(i).unwrap_or(&lexeme).span,
+                                                   "macro name missing after 'defined'")),
+            };
+            i += 1;
+            if parens {
+                if rest.get(i).map(|l| l.token) != Some(Token::RParen) {
+                    return Err(Diagnostic::new(rest[i - 1].span, "missing ')' after 'defined'"));
+                }
+                i += 1;
+            }
+
+            tokens.push(PPToken::new(Lexeme{
+                token: Token::Number(Number::Int(defined as u64, IntType::Int)),
+                ..lexeme
+            }));
+        }
+        Ok(tokens)
+    }
+
+    /**
+     * Skip to the `#elif`, `#else` or `#endif` that ends the current group, leaving it to be read
+     * next. Conditionals nested inside the group are skipped whole.
+     */
+    fn skip_group(&mut self) {
+        let frame = self.stack.last_mut().unwrap();
+        let mut depth = 0;
+        while frame.pos < frame.lexemes.len() {
+            let lexeme = frame.lexemes[frame.pos];
+            if lexeme.token == Token::Error {
+                frame.errors.pop_front();
+            }
+
+            match directive_at(&frame.lexemes, frame.pos) {
+                Some("if") | Some("ifdef") | Some("ifndef") => depth += 1,
+                Some("elif") | Some("else") | Some("endif") if depth == 0 => return,
+                Some("endif") => depth -= 1,
+                _ => (),
+            }
+            frame.pos += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use preprocessor::test::preprocess;
+
+    fn ok(s: &str) -> String {
+        let (output, errors) = preprocess(s, &[]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        output
+    }
+
+    #[test]
+    fn if_elif_else() {
+        assert_eq!(ok("#if 1\na\n#else\nb\n#endif\nc"), "a c");
+        assert_eq!(ok("#if 0\na\n#else\nb\n#endif\nc"), "b c");
+        assert_eq!(ok("#if 0\na\n#elif 2 > 1\nb\n#elif 1\nc\n#else\nd\n#endif"), "b");
+        assert_eq!(ok("#if 0\na\n#elif 0\nb\n#else\nc\n#endif"), "c");
+        assert_eq!(ok("#if 0\na\n#endif"), "");
+    }
+
+    #[test]
+    fn nesting() {
+        assert_eq!(ok("#if 1\n#if 0\na\n#else\nb\n#endif\nc\n#endif"), "b c");
+        assert_eq!(ok("#if 0\n#if 1\na\n#else\nb\n#endif\nc\n#else\nd\n#endif"), "d");
+        assert_eq!(ok("#if 0\n#ifdef X\n#elif 1\na\n#else\n#endif\n#elif 1\nb\n#endif"), "b");
+    }
+
+    #[test]
+    fn ifdef_and_ifndef() {
+        assert_eq!(ok("#define X\n#ifdef X\na\n#endif\n#ifdef Y\nb\n#endif"), "a");
+        assert_eq!(ok("#define X\n#ifndef X\na\n#endif\n#ifndef Y\nb\n#endif"), "b");
+        assert_eq!(ok("#define X\n#undef X\n#ifdef X\na\n#endif"), "");
+    }
+
+    #[test]
+    fn defined() {
+        assert_eq!(ok("#define X\n#if defined X && defined(X) && !defined Y && !defined ( Y )\na\n#endif"), "a");
+        assert_eq!(ok("#define VERSION 3\n#define X\n#if defined(X) && VERSION >= 3\nok\n#endif"), "ok");
+
+        // The name isn't expanded before it's checked.
+        assert_eq!(ok("#define X Y\n#if defined(X) && !defined(Y)\na\n#endif"), "a");
+    }
+
+    #[test]
+    fn expressions_are_expanded() {
+        assert_eq!(ok("#define TWO 2\n#define SUM(a, b) ((a) + (b))\n#if SUM(TWO, 1) == 3\na\n#endif"), "a");
+        assert_eq!(ok("#if UNKNOWN == 0 && !UNKNOWN\na\n#endif"), "a");
+        assert_eq!(ok("#if int\na\n#endif"), "");
+    }
+
+    #[test]
+    fn skipped_groups_are_lenient() {
+        let skipped = "#if 0\ndon't\n$ @ \"unterminated\n1.2.3e\n#bogus directive\n#if 1 / 0\n#endif\n\
+                       #define f(\n#include <nowhere.h>\n#else\nok\n#endif";
+        assert_eq!(ok(skipped), "ok");
+
+        // Only groups that are skipped are lenient.
+        let (_, errors) = preprocess("#if 1\n$\n#else\n$\n#endif", &[]);
+        assert_eq!(errors, vec!["main.c:2:1: unexpected character '$'"]);
+    }
+
+    #[test]
+    fn errors() {
+        let (output, errors) = preprocess("#else\n#elif 1\n#endif\n#if 1\n#else\n#else\n#elif 1\n#endif\n\
+                                           #if\n#endif\n#ifdef\n#endif\n#ifdef 3\n#endif\n#if defined\n#endif\n\
+                                           #if defined(X\n#endif\n#if 1 +\na\n#endif\nb", &[]);
+        assert_eq!(output, "b");
+        assert_eq!(errors, vec![
+            "main.c:1:2: #else without #if",
+            "main.c:2:2: #elif without #if",
+            "main.c:3:2: #endif without #if",
+            "main.c:6:2: #else after #else",
+            "main.c:7:2: #elif after #else",
+            "main.c:9:2: #if with no expression",
+            "main.c:11:2: macro name missing",
+            "main.c:13:8: macro name must be an identifier",
+            "main.c:15:5: macro name missing after 'defined'",
+            "main.c:17:13: missing ')' after 'defined'",
+            "main.c:19:7: expected value in expression",
+        ]);
+    }
+
+    #[test]
+    fn unterminated_conditionals() {
+        let (output, errors) = preprocess("#include \"a.h\"\nb\n#endif\n#if 1\nc", &[("a.h", "#if 1\na")]);
+        assert_eq!(output, "a b c");
+        assert_eq!(errors, vec![
+            "a.h:1:2: unterminated conditional directive",
+            "main.c:3:2: #endif without #if",
+            "main.c:4:2: unterminated conditional directive",
+        ]);
+    }
+
+    #[test]
+    fn conditionals_inside_macro_arguments() {
+        assert_eq!(ok("#define f(x) [x]\nf(\n#if 1\na\n#else\nb\n#endif\n)"), "[ a ]");
+    }
+}
@@ -0,0 +1,212 @@
+/*!
+ * Line control, diagnostics and pragmas
+ * =====================================
+ *
+ * `#line` (and the line markers `-E` writes), `#error`, `#warning`, and `#pragma`.
+ *
+ * `#line` doesn't move any tokens. It changes where the lines after it are *presumed* to come
+ * from, which is what error messages, `__FILE__` and `__LINE__` report (see `SourceMap`).
+ */
+
+use ast::Encoding;
+use parser::lexer::{Lexeme, Token};
+use preprocessor::{name, Preprocessor};
+use preprocessor::macros::PPToken;
+
+/**
+ * The largest line number `#line` accepts (C11 6.10.4p3).
+ */
+const MAX_LINE: usize = 2147483647;
+
+impl Preprocessor {
+    /**
+     * `#line N` or `#line N "file"`. With `marker` set, this is instead a line marker like
+     * `# N "file" 1`, whose flags are ignored. `args` starts with the line number.
+     */
+    pub(super) fn line(&mut self, directive: Lexeme, args: &[Lexeme], marker: bool) {
+        // Unless it's already in the right form, a `#line` is macro expanded first.
+        let args: Vec<Lexeme> = match args.first().map(|l| l.token) {
+            Some(Token::Number(_)) => args.to_vec(),
+            _ if marker => args.to_vec(),
+            _ => {
+                let tokens = args.iter().map(|&l| PPToken::new(l)).collect();
+                self.expand_all(tokens).into_iter().map(|t| t.lexeme).collect()
+            },
+        };
+
+        let number = match args.first() {
+            Some(&lexeme) if matches_number(lexeme) => lexeme,
+            _ => {
+                let at = args.first().cloned().unwrap_or(directive);
+                return self.error(at, "#line directive requires a positive integer argument");
+            },
+        };
+        let spelling = self.spelling(number);
+        if !spelling.bytes().all(|b| b'0' <= b && b <= b'9') {
+            return self.error(number, "#line directive requires a simple digit sequence");
+        }
+        let line = match spelling.parse() {
+            Ok(line) if line <= MAX_LINE && (line > 0 || marker) => line,
+            _ => return self.error(number, "line number out of range in #line directive"),
+        };
+
+        let file_name = match args.get(1).map(|l| l.token) {
+            None => None,
+            Some(Token::String(id)) if id.get().encoding == Encoding::Char => {
+                let bytes: Vec<u8> = id.get().units.iter().map(|&unit| unit as u8).collect();
+                Some(String::from_utf8_lossy(&bytes).into_owned())
+            },
+            Some(_) => return self.error(args[1], "invalid filename for #line directive"),
+        };
+        let extra = if marker {
+            args[2..].iter().position(|l| !matches_number(*l)).map(|i| args[i + 2])
+        } else {
+            args.get(2).cloned()
+        };
+        if let Some(extra) = extra {
+            return self.error(extra, "extra tokens at end of #line directive");
+        }
+
+        // The directive applies from the line after it.
+        let last = *self.stack.last().map(|frame| &frame.lexemes[frame.pos - 1]).unwrap_or(&directive);
+        self.sources.add_line_map(last.span.file, last.span.line + 1, line, file_name);
+    }
+
+    /**
+     * `#error` or `#warning`, followed by a message. The message is made of whatever tokens follow,
+     * spaced as they were in the source.
+     */
+    pub(super) fn error_directive(&mut self, directive: Lexeme, rest: &[Lexeme]) {
+        let mut message = format!("#{}", self.spelling(directive));
+        for (i, &lexeme) in rest.iter().enumerate() {
+            if i == 0 || lexeme.leading_space {
+                message.push(' ');
+            }
+            message.push_str(&self.spelling(lexeme));
+        }
+
+        if name(directive.token) == Some("error") {
+            self.error(directive, message);
+        } else {
+            self.warning(directive, message);
+        }
+    }
+
+    /**
+     * `#pragma`. Pragmas we don't know about are ignored, with a warning.
+     */
+    pub(super) fn pragma(&mut self, rest: &[Lexeme]) {
+        let first = match rest.first() {
+            Some(&first) => first,
+            None => return,
+        };
+
+        match name(first.token) {
+            Some("once") => if let Some(frame) = self.stack.last() {
+                self.once.insert(frame.key.clone());
+            },
+            Some("STDC") => self.stdc_pragma(first, &rest[1..]),
+            _ => self.warning(first, "unknown pragma ignored"),
+        }
+    }
+
+    /**
+     * The standard pragmas, `#pragma STDC FP_CONTRACT`, `FENV_ACCESS` and `CX_LIMITED_RANGE`, each
+     * followed by `ON`, `OFF` or `DEFAULT` (C11 6.10.6p2). rust-cc never contracts floating point
+     * expressions, has no floating point environment and no complex types, so they're checked and
+     * then have no effect.
+     */
+    fn stdc_pragma(&mut self, stdc: Lexeme, rest: &[Lexeme]) {
+        match rest.first().and_then(|l| name(l.token)) {
+            Some("FP_CONTRACT") | Some("FENV_ACCESS") | Some("CX_LIMITED_RANGE") => (),
+            _ => return self.warning(rest.first().cloned().unwrap_or(stdc),
+                                     "unknown pragma in STDC namespace"),
+        }
+
+        match rest.get(1).and_then(|l| name(l.token)) {
+            Some("ON") | Some("OFF") | Some("DEFAULT") if rest.len() == 2 => (),
+            _ => self.warning(*rest.get(1).unwrap_or(&rest[0]), "expected 'ON' or 'OFF' or 'DEFAULT' in pragma"),
+        }
+    }
+}
+
+fn matches_number(lexeme: Lexeme) -> bool {
+    match lexeme.token {
+        Token::Number(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use preprocessor::test::preprocess;
+
+    #[test]
+    fn line_changes_diagnostics() {
+        let (_, errors) = preprocess("$\n#line 100\n$\n\n$\n#line 7 \"parser.y\"\n$\n#line 20\n$", &[]);
+        assert_eq!(errors, vec![
+            "main.c:1:1: unexpected character '$'",
+            "main.c:100:1: unexpected character '$'",
+            "main.c:102:1: unexpected character '$'",
+            "parser.y:7:1: unexpected character '$'",
+            "parser.y:20:1: unexpected character '$'",
+        ]);
+
+        // It only changes the file it's in.
+        let (_, errors) = preprocess("#include \"a.h\"\n$", &[("a.h", "#line 50 \"gen.c\"\n$")]);
+        assert_eq!(errors, vec!["gen.c:50:1: unexpected character '$'", "main.c:2:1: unexpected character '$'"]);
+    }
+
+    #[test]
+    fn line_changes_file_and_line_macros() {
+        let (output, errors) = preprocess("#define NAME \"x.c\"\n#define N 10\n#line N NAME\n__LINE__ __FILE__", &[]);
+        assert_eq!((output, errors), ("10 \"x.c\"".to_string(), vec![]));
+    }
+
+    #[test]
+    fn line_markers() {
+        let (output, errors) = preprocess("# 1 \"main.c\"\n# 5 \"a.h\" 1\n__LINE__ __FILE__\n# 2 \"main.c\" 2\n__LINE__", &[]);
+        assert_eq!((output, errors), ("5 \"a.h\" 2".to_string(), vec![]));
+    }
+
+    #[test]
+    fn line_errors() {
+        let (_, errors) = preprocess("#line\n#line x\n#line 0x10\n#line 0\n#line 2147483648\n\
+                                      #line 5 x\n#line 5 L\"x\"\n#line 5 \"x\" 1", &[]);
+        assert_eq!(errors, vec![
+            "main.c:1:2: #line directive requires a positive integer argument",
+            "main.c:2:7: #line directive requires a positive integer argument",
+            "main.c:3:7: #line directive requires a simple digit sequence",
+            "main.c:4:7: line number out of range in #line directive",
+            "main.c:5:7: line number out of range in #line directive",
+            "main.c:6:9: invalid filename for #line directive",
+            "main.c:7:9: invalid filename for #line directive",
+            "main.c:8:13: extra tokens at end of #line directive",
+        ]);
+    }
+
+    #[test]
+    fn error_and_warning() {
+        let (output, errors) = preprocess("#error this   config isn't supported\na\n#warning  deprecated(x)\n\
+                                           #if 0\n#error skipped\n#endif\nb", &[]);
+        assert_eq!(output, "a b");
+        assert_eq!(errors, vec![
+            "main.c:1:2: #error this config isn't supported",
+            "main.c:3:2: warning: #warning deprecated(x)",
+        ]);
+    }
+
+    #[test]
+    fn pragmas() {
+        let (output, errors) = preprocess("#pragma\n#pragma STDC FP_CONTRACT ON\n#pragma STDC FENV_ACCESS DEFAULT\n\
+                                           #pragma STDC CX_LIMITED_RANGE maybe\n#pragma STDC bogus\n\
+                                           #pragma weird stuff\n#pragma GCC system_header\na", &[]);
+        assert_eq!(output, "a");
+        assert_eq!(errors, vec![
+            "main.c:4:31: warning: expected 'ON' or 'OFF' or 'DEFAULT' in pragma",
+            "main.c:5:14: warning: unknown pragma in STDC namespace",
+            "main.c:6:9: warning: unknown pragma ignored",
+            "main.c:7:9: warning: unknown pragma ignored",
+        ]);
+    }
+}
@@ -0,0 +1,328 @@
+/*!
+ * `#if` expressions
+ * =================
+ *
+ * The controlling expression of an `#if` or `#elif` is an integer constant expression, evaluated
+ * in `intmax_t` (or `uintmax_t`, if an unsigned value gets involved) after macro expansion. By the
+ * time the tokens get here, every `defined` has already been replaced with 0 or 1, and any name
+ * that's left isn't a macro, so it counts as 0 (C11 6.10.1).
+ */
+
+use std::cmp::Ordering;
+
+use ast::{IntType, Number};
+use parser::lexer::{Lexeme, Operator, Token};
+use source::{Diagnostic, Span};
+
+/**
+ * Value is an `intmax_t` or a `uintmax_t`. Unsigned values are stored as their bits.
+ */
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Value {
+    pub value:      i64,
+    pub unsigned:   bool,
+}
+
+impl Value {
+    fn signed(value: i64) -> Self {
+        Value{
+            value:      value,
+            unsigned:   false,
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        self.value != 0
+    }
+}
+
+/**
+ * Evaluate the tokens of an `#if` line. `end` is where to point errors about the line ending too
+ * soon.
+ */
+pub fn evaluate(tokens: &[Lexeme], end: Span) -> Result<Value, Diagnostic> {
+    if tokens.is_empty() {
+        return Err(Diagnostic::new(end, "#if with no expression"));
+    }
+
+    let mut evaluator = Evaluator{
+        tokens: tokens,
+        pos:    0,
+        end:    end,
+    };
+    let value = try!(evaluator.conditional(true));
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(lexeme) => Err(Diagnostic::new(lexeme.span, "missing binary operator before token")),
+    }
+}
+
+/**
+ * Evaluator is a small precedence-climbing parser that evaluates as it goes. Each method takes
+ * `live`, which is false in the parts of the expression that aren't evaluated (like the right
+ * side of `0 && x`), so that `0 && 1 / 0` isn't a division by zero.
+ */
+struct Evaluator<'a> {
+    tokens: &'a [Lexeme],
+    pos:    usize,
+    end:    Span,
+}
+
+impl<'a> Evaluator<'a> {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).map(|l| l.token)
+    }
+
+    fn span(&self) -> Span {
+        self.tokens.get(self.pos).map_or(self.end, |l| l.span)
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), Diagnostic> {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(Diagnostic::new(self.span(), message))
+        }
+    }
+
+    /**
+     * `a ? b : c`, the loosest operator allowed. (The comma operator isn't allowed.)
+     */
+    fn conditional(&mut self, live: bool) -> Result<Value, Diagnostic> {
+        let condition = try!(self.binary(1, live));
+        if self.peek() != Some(Token::Question) {
+            return Ok(condition);
+        }
+        self.pos += 1;
+
+        let yes = try!(self.conditional(live && condition.is_true()));
+        try!(self.expect(Token::Colon, "expected ':' in conditional expression"));
+        let no = try!(self.conditional(live && !condition.is_true()));
+        let mut result = if condition.is_true() { yes } else { no };
+        result.unsigned = yes.unsigned || no.unsigned;
+        Ok(result)
+    }
+
+    /**
+     * Binary operators that bind at least as tightly as `min_precedence`.
+     */
+    fn binary(&mut self, min_precedence: u8, live: bool) -> Result<Value, Diagnostic> {
+        let mut lhs = try!(self.unary(live));
+        loop {
+            let op = match self.peek() {
+                Some(Token::Operator(op)) if precedence(op) >= min_precedence => op,
+                _ => return Ok(lhs),
+            };
+            let span = self.span();
+            self.pos += 1;
+
+            // The right side of `&&` and `||` is only evaluated if it matters.
+            let rhs_live = match op {
+                Operator::And => live && lhs.is_true(),
+                Operator::Or => live && !lhs.is_true(),
+                _ => live,
+            };
+            let rhs = try!(self.binary(precedence(op) + 1, rhs_live));
+            lhs = try!(apply(op, lhs, rhs, live, span));
+        }
+    }
+
+    fn unary(&mut self, live: bool) -> Result<Value, Diagnostic> {
+        let span = self.span();
+        let token = match self.peek() {
+            Some(token) => token,
+            None => return Err(Diagnostic::new(span, "expected value in expression")),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Operator(Operator::Add) => self.unary(live),
+            Token::Operator(Operator::Subtract) => {
+                let v = try!(self.unary(live));
+                Ok(Value{ value: v.value.wrapping_neg(), unsigned: v.unsigned })
+            },
+            Token::Operator(Operator::BitwiseNot) => {
+                let v = try!(self.unary(live));
+                Ok(Value{ value: !v.value, unsigned: v.unsigned })
+            },
+            Token::Operator(Operator::Not) => {
+                let v = try!(self.unary(live));
+                Ok(Value::signed(!v.is_true() as i64))
+            },
+            Token::LParen => {
+                let v = try!(self.conditional(live));
+                try!(self.expect(Token::RParen, "expected ')' in preprocessor expression"));
+                Ok(v)
+            },
+
+            Token::Number(Number::Int(value, ty)) => Ok(Value{
+                value:      value as i64,
+                unsigned:   value > i64::max_value() as u64 || is_unsigned(ty),
+            }),
+            Token::Number(Number::Float(..)) =>
+                Err(Diagnostic::new(span, "floating point literal in preprocessor expression")),
+            Token::Character(value) => Ok(Value::signed(value as i64)),
+
+            // Names left after macro expansion aren't macros, and are replaced with 0.
+            Token::Identifier(_) | Token::Keyword(_) => Ok(Value::signed(0)),
+
+            _ => Err(Diagnostic::new(span, "invalid token at start of a preprocessor expression")),
+        }
+    }
+}
+
+/**
+ * How tightly each binary operator binds. Operators that aren't allowed in `#if` get 0, which is
+ * looser than anything `binary()` asks for, so they end the expression.
+ */
+fn precedence(op: Operator) -> u8 {
+    match op {
+        Operator::Or => 1,
+        Operator::And => 2,
+        Operator::BitwiseOr => 3,
+        Operator::BitwiseXor => 4,
+        Operator::Ampersand => 5,
+        Operator::Equal | Operator::NotEqual => 6,
+        Operator::Less | Operator::LessEqual | Operator::Greater | Operator::GreaterEqual => 7,
+        Operator::ShiftLeft | Operator::ShiftRight => 8,
+        Operator::Add | Operator::Subtract => 9,
+        Operator::Asterisk | Operator::Divide | Operator::Modulo => 10,
+        _ => 0,
+    }
+}
+
+fn is_unsigned(ty: IntType) -> bool {
+    match ty {
+        IntType::UnsignedInt | IntType::UnsignedLong | IntType::UnsignedLongLong => true,
+        _ => false,
+    }
+}
+
+/**
+ * Apply a binary operator. If either operand is unsigned, both are converted to `uintmax_t` first,
+ * except for shifts, where only the left operand matters.
+ */
+fn apply(op: Operator, lhs: Value, rhs: Value, live: bool, span: Span) -> Result<Value, Diagnostic> {
+    let unsigned = lhs.unsigned || rhs.unsigned;
+    let (a, b) = (lhs.value, rhs.value);
+    let compare = || if unsigned { (a as u64).cmp(&(b as u64)) } else { a.cmp(&b) };
+    let boolean = |b: bool| Ok(Value::signed(b as i64));
+    let arithmetic = |value: i64| Ok(Value{ value: value, unsigned: unsigned });
+
+    match op {
+        Operator::Or => boolean(lhs.is_true() || rhs.is_true()),
+        Operator::And => boolean(lhs.is_true() && rhs.is_true()),
+        Operator::BitwiseOr => arithmetic(a | b),
+        Operator::BitwiseXor => arithmetic(a ^ b),
+        Operator::Ampersand => arithmetic(a & b),
+        Operator::Equal => boolean(a == b),
+        Operator::NotEqual => boolean(a != b),
+        Operator::Less => boolean(compare() == Ordering::Less),
+        Operator::LessEqual => boolean(compare() != Ordering::Greater),
+        Operator::Greater => boolean(compare() == Ordering::Greater),
+        Operator::GreaterEqual => boolean(compare() != Ordering::Less),
+        Operator::Add => arithmetic(a.wrapping_add(b)),
+        Operator::Subtract => arithmetic(a.wrapping_sub(b)),
+        Operator::Asterisk => arithmetic(a.wrapping_mul(b)),
+
+        Operator::Divide | Operator::Modulo if b == 0 => if live {
+            Err(Diagnostic::new(span, "division by zero in preprocessor expression"))
+        } else {
+            arithmetic(0)
+        },
+        Operator::Divide if unsigned => arithmetic(((a as u64) / (b as u64)) as i64),
+        Operator::Divide => arithmetic(a.wrapping_div(b)),
+        Operator::Modulo if unsigned => arithmetic(((a as u64) % (b as u64)) as i64),
+        Operator::Modulo => arithmetic(a.wrapping_rem(b)),
+
+        // Shifting by a negative amount shifts the other way, and shifting everything out leaves
+        // zero (or all ones, for a negative signed value shifted right).
+        Operator::ShiftLeft | Operator::ShiftRight => {
+            let left = (op == Operator::ShiftLeft) == (rhs.unsigned || b >= 0);
+            let amount = if rhs.unsigned || b >= 0 { b as u64 } else { (b as u64).wrapping_neg() };
+            let value = match (left, lhs.unsigned) {
+                (true, _) => if amount >= 64 { 0 } else { a << amount },
+                (false, true) => if amount >= 64 { 0 } else { ((a as u64) >> amount) as i64 },
+                (false, false) => if amount >= 64 { if a < 0 { -1 } else { 0 } } else { a >> amount },
+            };
+            Ok(Value{ value: value, unsigned: lhs.unsigned })
+        },
+
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use parser::lexer;
+
+    fn eval(s: &str) -> Result<Value, String> {
+        let lexemes = lexer::lex(s).unwrap();
+        evaluate(&lexemes, Span::default()).map_err(|e| e.to_string())
+    }
+
+    fn signed(value: i64) -> Result<Value, String> {
+        Ok(Value::signed(value))
+    }
+
+    fn unsigned(value: u64) -> Result<Value, String> {
+        Ok(Value{ value: value as i64, unsigned: true })
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1 + 2 * 3"), signed(7));
+        assert_eq!(eval("(1 + 2) * 3"), signed(9));
+        assert_eq!(eval("10 - 2 - 3"), signed(5));
+        assert_eq!(eval("-7 / 2"), signed(-3));
+        assert_eq!(eval("-7 % 2"), signed(-1));
+        assert_eq!(eval("~0"), signed(-1));
+        assert_eq!(eval("1 << 4 | 3 & 5 ^ 2"), signed(19));
+        assert_eq!(eval("-8 >> 1"), signed(-4));
+        assert_eq!(eval("1 << 64"), signed(0));
+        assert_eq!(eval("9223372036854775807 + 1"), signed(i64::min_value()));
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(eval("1 < 2 && 2 <= 2 && 3 > 2 && 3 >= 4"), signed(0));
+        assert_eq!(eval("1 == 1 || 1 / 0"), signed(1));
+        assert_eq!(eval("0 && 1 / 0"), signed(0));
+        assert_eq!(eval("!5 + !0"), signed(1));
+        assert_eq!(eval("1 ? 2 : 3"), signed(2));
+        assert_eq!(eval("0 ? 1 / 0 : 0 ? 3 : 4"), signed(4));
+        assert_eq!(eval("'a' == 97"), signed(1));
+    }
+
+    #[test]
+    fn names_are_zero() {
+        assert_eq!(eval("FOO"), signed(0));
+        assert_eq!(eval("FOO + int + 1"), signed(1));
+    }
+
+    #[test]
+    fn unsigned_arithmetic() {
+        assert_eq!(eval("-1 < 0"), signed(1));
+        assert_eq!(eval("-1 < 0u"), signed(0));
+        assert_eq!(eval("0u - 1"), unsigned(u64::max_value()));
+        assert_eq!(eval("0xffffffffffffffff / 2"), unsigned(u64::max_value() / 2));
+        assert_eq!(eval("0xffffffffffffffff >> 63"), unsigned(1));
+        assert_eq!(eval("1 ? -1 : 0u"), unsigned(u64::max_value()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval("").unwrap_err(), "0:0: #if with no expression");
+        assert_eq!(eval("1 +").unwrap_err(), "0:0: expected value in expression");
+        assert_eq!(eval("(1").unwrap_err(), "0:0: expected ')' in preprocessor expression");
+        assert_eq!(eval("1 2").unwrap_err(), "1:3: missing binary operator before token");
+        assert_eq!(eval("1 / 0").unwrap_err(), "1:3: division by zero in preprocessor expression");
+        assert_eq!(eval("1.5").unwrap_err(), "1:1: floating point literal in preprocessor expression");
+        assert_eq!(eval("\"a\"").unwrap_err(), "1:1: invalid token at start of a preprocessor expression");
+        assert_eq!(eval("1 ? 2").unwrap_err(), "0:0: expected ':' in conditional expression");
+        assert_eq!(eval("1, 2").unwrap_err(), "1:2: missing binary operator before token");
+        assert_eq!(eval("a = 1").unwrap_err(), "1:3: missing binary operator before token");
+    }
+}
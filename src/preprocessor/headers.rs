@@ -0,0 +1,91 @@
+/*!
+ * Built-in headers
+ * ================
+ *
+ * rust-cc has no C library to lean on, so it carries its own copies of the freestanding headers
+ * (the ones C11 4p6 requires even without a hosted library) that real code can't do without,
+ * written for rust-cc's type sizes. They're compiled into the binary and appear as virtual files
+ * in `BUILTIN_INCLUDE_DIR`, which is searched after the `-I` directories.
+ *
+ * `rust-cc-builtins.h` declares the functions built into rust-cc itself.
+ */
+
+/**
+ * The directory the built-in headers appear to be in. It can't be confused with a real one.
+ */
+pub const BUILTIN_INCLUDE_DIR: &'static str = "<rust-cc>/include";
+
+pub const HEADERS: [(&'static str, &'static str); 6] = [
+    ("limits.h", include_str!("headers/limits.h")),
+    ("rust-cc-builtins.h", include_str!("headers/rust-cc-builtins.h")),
+    ("stdarg.h", include_str!("headers/stdarg.h")),
+    ("stdbool.h", include_str!("headers/stdbool.h")),
+    ("stddef.h", include_str!("headers/stddef.h")),
+    ("stdint.h", include_str!("headers/stdint.h")),
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ast::{IntType, Number};
+    use parser;
+    use parser::lexer::{self, Token};
+    use preprocessor::test::{preprocess, preprocessor};
+    use source::File;
+
+    #[test]
+    fn every_header_can_be_included_twice() {
+        for &(name, _) in &HEADERS {
+            let include = format!("#include <{}>\n", name);
+            let (once, errors) = preprocess(&include, &[]);
+            assert_eq!(errors, Vec::<String>::new(), "{}", name);
+            assert_eq!(preprocess(&(include.clone() + &include), &[]), (once, vec![]), "{}", name);
+        }
+    }
+
+    #[test]
+    fn every_header_can_be_parsed() {
+        for &(name, _) in &HEADERS {
+            let mut preprocessor = preprocessor(&[]);
+            let (lexemes, errors) = preprocessor.preprocess(File{
+                name:   "main.c".to_string(),
+                buf:    format!("#include <{}>\nint main() {{ return 0; }}", name),
+            });
+            assert!(errors.is_empty(), "{}: {:?}", name, errors);
+            if let Err(e) = parser::parse(lexemes) {
+                panic!("{}: {}", name, preprocessor.sources.describe(&e));
+            }
+        }
+    }
+
+    #[test]
+    fn limits_match_rust_cc() {
+        let (output, errors) = preprocess("#include <limits.h>\n\
+                                           INT_MAX UINT_MAX LONG_MAX ULONG_MAX LLONG_MAX ULLONG_MAX", &[]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let tokens: Vec<Token> = lexer::lex(&output).unwrap().into_iter().map(|l| l.token).collect();
+        let types = [IntType::Int, IntType::UnsignedInt, IntType::Long, IntType::UnsignedLong,
+                     IntType::LongLong, IntType::UnsignedLongLong];
+        let expected: Vec<Token> = types.iter().map(|&t| Token::Number(Number::Int(t.max_value(), t))).collect();
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn headers_agree_with_each_other() {
+        let source = "#include <limits.h>\n#include <stdint.h>\n#include <stdbool.h>\n\
+                      #if INT_MIN == INT32_MIN && LONG_MAX == INTMAX_MAX && SIZE_MAX == ULONG_MAX && \
+                          UINT64_C(1) << 63 == LLONG_MIN && SCHAR_MIN == -128 && true && !false\n\
+                      ok\n\
+                      #endif";
+        let (output, errors) = preprocess(source, &[]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(output.ends_with(" ok"), "{}", output);
+    }
+
+    #[test]
+    fn include_paths_come_first() {
+        let (output, _) = preprocess("#include <stdbool.h>\n#include <stddef.h>", &[("sys/stdbool.h", "mine")]);
+        assert!(output.starts_with("mine "), "{}", output);
+        assert!(output.contains(" size_t "), "{}", output);
+    }
+}
@@ -0,0 +1,17 @@
+/*
+ * rust-cc-builtins.h
+ *
+ * Declare the compiler builtins of rust-cc. These have no definitions anywhere; rust-cc knows
+ * what they do.
+ *
+ * Code that should also build with other compilers can check for `__RUST_CC__` and define them
+ * some other way when it isn't set. See integration/files/rust-cc-builtins.h.
+ */
+
+#ifndef __RUST_CC_BUILTINS_H
+#define __RUST_CC_BUILTINS_H
+
+// Write `integer` in decimal, followed by a newline, to `file_descriptor`.
+void write_int(int file_descriptor, int integer);
+
+#endif // __RUST_CC_BUILTINS_H
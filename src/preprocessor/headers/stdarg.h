@@ -0,0 +1,18 @@
+/*
+ * stdarg.h
+ *
+ * Variable arguments (C11 7.16). There's no portable way to write these in C, so they're built
+ * into rust-cc.
+ */
+
+#ifndef __RUST_CC_STDARG_H
+#define __RUST_CC_STDARG_H
+
+typedef __builtin_va_list va_list;
+
+#define va_start(ap, parmN) __builtin_va_start(ap, parmN)
+#define va_arg(ap, type) __builtin_va_arg(ap, type)
+#define va_copy(dest, src) __builtin_va_copy(dest, src)
+#define va_end(ap) __builtin_va_end(ap)
+
+#endif // __RUST_CC_STDARG_H
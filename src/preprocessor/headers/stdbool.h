@@ -0,0 +1,16 @@
+/*
+ * stdbool.h
+ *
+ * Boolean type and values (C11 7.18).
+ */
+
+#ifndef __RUST_CC_STDBOOL_H
+#define __RUST_CC_STDBOOL_H
+
+#define bool _Bool
+#define true 1
+#define false 0
+
+#define __bool_true_false_are_defined 1
+
+#endif // __RUST_CC_STDBOOL_H
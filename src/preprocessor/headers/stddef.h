@@ -0,0 +1,23 @@
+/*
+ * stddef.h
+ *
+ * Common definitions (C11 7.19), for rust-cc's LP64 data model.
+ */
+
+#ifndef __RUST_CC_STDDEF_H
+#define __RUST_CC_STDDEF_H
+
+typedef long ptrdiff_t;
+typedef unsigned long size_t;
+typedef int wchar_t;
+
+typedef struct {
+	long long __max_align_ll;
+	long double __max_align_ld;
+} max_align_t;
+
+#define NULL ((void *)0)
+
+#define offsetof(type, member) ((size_t)&((type *)0)->member)
+
+#endif // __RUST_CC_STDDEF_H
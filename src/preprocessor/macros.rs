@@ -0,0 +1,647 @@
+/*!
+ * Macros
+ * ======
+ *
+ * `#define`, `#undef`, and expanding the macros they define.
+ *
+ * Expansion follows Dave Prosser's algorithm, which is where the rules in the C standard came from.
+ * Every token carries a "hide set": the names of the macros it was produced by. A name is never
+ * expanded again while it's in its own hide set, so `#define foo foo` turns `foo` into `foo` and
+ * stops, instead of looping forever. (The standard calls such tokens "painted blue".)
+ *
+ * The result of an expansion is pushed back onto the input and read again, so macros in it are
+ * expanded in turn, and a function-like macro at the end of it can take its arguments from the
+ * tokens that follow.
+ */
+
+use std::mem;
+use std::rc::Rc;
+
+use parser::lexer::{self, Lexeme, Token};
+use preprocessor::{macro_name, Preprocessor};
+use preprocessor::predefined::Builtin;
+use source::File;
+use util::intern::Symbol;
+
+/**
+ * Macro is the definition of a macro, from `#define`.
+ */
+#[derive(Debug)]
+pub struct Macro {
+    /// The parameters of a function-like macro, or None for an object-like one. The last
+    /// parameter of a variadic macro is `__VA_ARGS__`.
+    pub params:     Option<Vec<Symbol>>,
+    pub variadic:   bool,
+    pub body:       Vec<Lexeme>,
+
+    /// Set for `__FILE__` and `__LINE__`, which have no body and are expanded specially.
+    pub builtin:    Option<Builtin>,
+}
+
+impl Macro {
+    /**
+     * Two definitions of the same macro are allowed only if they're the same: the same
+     * parameters, and the same tokens separated by whitespace in the same places (C11 6.10.3).
+     */
+    fn same_as(&self, other: &Macro) -> bool {
+        self.params == other.params && self.variadic == other.variadic &&
+            self.builtin == other.builtin && self.body.len() == other.body.len() &&
+            self.body.iter().zip(&other.body).enumerate().all(|(i, (a, b))| {
+                a.token == b.token && (i == 0 || a.leading_space == b.leading_space)
+            })
+    }
+
+    fn param_index(&self, token: Token) -> Option<usize> {
+        match (&self.params, macro_name(token)) {
+            (&Some(ref params), Some(name)) => params.iter().position(|&p| p == name),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * PPToken is a preprocessing token: a Lexeme, plus the hide set that says which macros it may no
+ * longer be expanded by.
+ */
+#[derive(Clone,Debug)]
+pub struct PPToken {
+    pub lexeme: Lexeme,
+    pub hide:   Vec<Symbol>,
+}
+
+impl PPToken {
+    pub fn new(lexeme: Lexeme) -> Self {
+        PPToken{
+            lexeme: lexeme,
+            hide:   vec![],
+        }
+    }
+}
+
+impl Preprocessor {
+    /**
+     * `#define NAME body` or `#define NAME(params) body`.
+     */
+    pub(super) fn define(&mut self, directive: Lexeme, rest: &[Lexeme]) {
+        let name_lexeme = match rest.first() {
+            Some(&lexeme) => lexeme,
+            None => return self.error(directive, "macro name missing"),
+        };
+        let name = match macro_name(name_lexeme.token) {
+            Some(name) => name,
+            None => return self.error(name_lexeme, "macro name must be an identifier"),
+        };
+        if name.as_str() == "defined" {
+            return self.error(name_lexeme, "'defined' cannot be used as a macro name");
+        }
+
+        // It's a function-like macro only if the `(` comes right after the name, without a space.
+        let mut i = 1;
+        let mut params = None;
+        let mut variadic = false;
+        if rest.get(1).map_or(false, |l| l.token == Token::LParen && !l.leading_space) {
+            let mut names = vec![];
+            i = 2;
+            if rest.get(i).map(|l| l.token) == Some(Token::RParen) {
+                i += 1;
+            } else {
+                loop {
+                    let at = rest.get(i).cloned().unwrap_or(rest[i - 1]);
+                    match rest.get(i).map(|l| l.token) {
+                        Some(Token::Ellipsis) => {
+                            variadic = true;
+                            names.push(Symbol::intern("__VA_ARGS__"));
+                        },
+                        Some(token) => match macro_name(token) {
+                            Some(param) if param.as_str() == "__VA_ARGS__" => return self.error(at,
+                                "__VA_ARGS__ can only appear in the expansion of a variadic macro"),
+                            Some(param) if names.contains(&param) => return self.error(at,
+                                format!("duplicate macro parameter '{}'", param)),
+                            Some(param) => names.push(param),
+                            None => return self.error(at, "invalid token in macro parameter list"),
+                        },
+                        None => return self.error(at, "missing ')' in macro parameter list"),
+                    }
+                    i += 1;
+
+                    match rest.get(i).map(|l| l.token) {
+                        Some(Token::RParen) => break,
+                        Some(Token::Comma) if !variadic => i += 1,
+                        _ => {
+                            let at = rest.get(i).cloned().unwrap_or(rest[i - 1]);
+                            return self.error(at, if variadic {
+                                "missing ')' after '...'"
+                            } else {
+                                "expected ',' or ')' in macro parameter list"
+                            });
+                        },
+                    }
+                }
+                i += 1;
+            }
+            params = Some(names);
+        }
+
+        let definition = Macro{
+            params:     params,
+            variadic:   variadic,
+            body:       rest[i..].to_vec(),
+            builtin:    None,
+        };
+        if let Err((at, message)) = check_body(&definition) {
+            return self.error(at, message);
+        }
+
+        if let Some(old) = self.macros.get(&name) {
+            if !old.same_as(&definition) {
+                let message = format!("'{}' macro redefined", name);
+                self.error(name_lexeme, message);
+            }
+        }
+        self.macros.insert(name, Rc::new(definition));
+    }
+
+    /**
+     * `#undef NAME`. Undefining a name that isn't a macro is fine.
+     */
+    pub(super) fn undef(&mut self, directive: Lexeme, rest: &[Lexeme]) {
+        match rest.first() {
+            Some(lexeme) => match macro_name(lexeme.token) {
+                Some(name) => { self.macros.remove(&name); },
+                None => self.error(*lexeme, "macro name must be an identifier"),
+            },
+            None => self.error(directive, "macro name missing"),
+        }
+    }
+
+    /**
+     * Return the next token with every macro expanded.
+     */
+    pub(super) fn next_expanded(&mut self) -> Option<PPToken> {
+        loop {
+            let token = match self.next_token() {
+                Some(token) => token,
+                None => return None,
+            };
+            let definition = match macro_name(token.lexeme.token) {
+                Some(name) if !token.hide.contains(&name) => match self.macros.get(&name) {
+                    Some(definition) => (name, definition.clone()),
+                    None => return Some(token),
+                },
+                _ => return Some(token),
+            };
+
+            // A name straight from a file starts a new expansion, and everything it expands to
+            // belongs where the name was.
+            if token.hide.is_empty() && !self.isolated {
+                self.expansion_site = token.lexeme.span;
+            }
+
+            if let Some(builtin) = definition.1.builtin {
+                return Some(self.expand_builtin(definition.0, builtin, &token));
+            }
+            if !self.expand(definition.0, &definition.1, &token) {
+                return Some(token);
+            }
+        }
+    }
+
+    /**
+     * Fully expand `tokens` on their own, without reading anything after them. This is how macro
+     * arguments are expanded before they're substituted, and how `#include` and `#if` lines are
+     * expanded.
+     */
+    pub(super) fn expand_all(&mut self, tokens: Vec<PPToken>) -> Vec<PPToken> {
+        let mut input = tokens;
+        input.reverse();
+        let pending = mem::replace(&mut self.pending, input);
+        let isolated = mem::replace(&mut self.isolated, true);
+
+        let mut output = vec![];
+        while let Some(token) = self.next_expanded() {
+            output.push(token);
+        }
+
+        self.pending = pending;
+        self.isolated = isolated;
+        output
+    }
+
+    /**
+     * Expand the macro `name` that `token` invokes, and push the result back onto the input to be
+     * rescanned. Return false if `token` turns out not to be an invocation, which happens when a
+     * function-like macro's name isn't followed by a `(`.
+     */
+    fn expand(&mut self, name: Symbol, definition: &Macro, token: &PPToken) -> bool {
+        let mut hide;
+        let args = match definition.params {
+            None => {
+                hide = token.hide.clone();
+                vec![]
+            },
+            Some(_) => {
+                match self.next_token() {
+                    Some(ref next) if next.lexeme.token == Token::LParen => (),
+                    next => {
+                        self.pending.extend(next);
+                        return false;
+                    },
+                }
+                let (args, rparen) = match self.arguments(name, definition, token) {
+                    Some(result) => result,
+                    None => return true,
+                };
+
+                // The hide set is the names hidden at both ends of the invocation. Whatever
+                // happened to the tokens in between doesn't matter.
+                hide = token.hide.iter().cloned().filter(|name| rparen.hide.contains(name)).collect();
+                args
+            },
+        };
+        hide.push(name);
+
+        let mut result = self.substitute(definition, &args);
+        for t in &mut result {
+            for &name in &hide {
+                if !t.hide.contains(&name) {
+                    t.hide.push(name);
+                }
+            }
+        }
+
+        // The expansion goes where the macro name was.
+        if let Some(first) = result.first_mut() {
+            first.lexeme.line_start = token.lexeme.line_start;
+            first.lexeme.leading_space = token.lexeme.leading_space;
+        }
+        result.reverse();
+        self.pending.extend(result);
+        true
+    }
+
+    /**
+     * Read the arguments of an invocation of a function-like macro, just after its `(`. Return the
+     * arguments and the closing `)`, or None (after reporting the problem) if they're wrong.
+     */
+    fn arguments(&mut self, name: Symbol, definition: &Macro, token: &PPToken)
+                 -> Option<(Vec<Vec<PPToken>>, PPToken)> {
+        let params = definition.params.as_ref().map_or(0, |params| params.len());
+        let mut args = vec![vec![]];
+        let mut depth = 0;
+        let rparen = loop {
+            let next = match self.next_token() {
+                Some(next) => next,
+                None => {
+                    self.error(token.lexeme, format!("unterminated argument list invoking macro '{}'", name));
+                    return None;
+                },
+            };
+
+            match next.lexeme.token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => break next,
+                Token::RParen => depth -= 1,
+
+                // Commas between parentheses don't separate arguments, and neither do the commas
+                // in the variable arguments of a variadic macro.
+                Token::Comma if depth == 0 && !(definition.variadic && args.len() == params) => {
+                    args.push(vec![]);
+                    continue;
+                },
+                _ => (),
+            }
+            args.last_mut().unwrap().push(next);
+        };
+
+        // `f()` passes no arguments to a macro with no parameters, not one empty argument. And the
+        // variable arguments can be left out entirely.
+        if params == 0 && args.len() == 1 && args[0].is_empty() {
+            args.clear();
+        }
+        if definition.variadic && args.len() + 1 == params {
+            args.push(vec![]);
+        }
+
+        if args.len() != params {
+            self.error(token.lexeme, format!("macro '{}' takes {} argument{}, but {} {} given",
+                                             name, params, if params == 1 { "" } else { "s" },
+                                             args.len(), if args.len() == 1 { "was" } else { "were" }));
+            return None;
+        }
+        Some((args, rparen))
+    }
+
+    /**
+     * Replace the parameters in the body of a macro with its arguments, and carry out the `#` and
+     * `##` operators.
+     */
+    fn substitute(&mut self, definition: &Macro, args: &[Vec<PPToken>]) -> Vec<PPToken> {
+        let body = &definition.body;
+        let mut expanded: Vec<Option<Vec<PPToken>>> = vec![None; args.len()];
+        let mut output: Vec<PPToken> = vec![];
+
+        // Whether the last thing added to `output` was empty (an empty argument), and whether a
+        // `##` is waiting to paste it to the next thing.
+        let mut last_empty = false;
+        let mut paste = false;
+
+        let mut i = 0;
+        while i < body.len() {
+            let lexeme = body[i];
+            let mut item = if lexeme.token == Token::HashHash {
+                paste = true;
+                i += 1;
+                continue;
+            } else if lexeme.token == Token::Hash && definition.params.is_some() {
+                // `check_body` made sure a parameter follows.
+                let p = definition.param_index(body[i + 1].token).unwrap();
+                i += 2;
+                vec![self.stringize(lexeme, &args[p])]
+            } else if let Some(p) = definition.param_index(lexeme.token) {
+                // Arguments are macro expanded first, unless they're an operand of `##`.
+                i += 1;
+                if paste || body.get(i).map(|l| l.token) == Some(Token::HashHash) {
+                    args[p].clone()
+                } else {
+                    if expanded[p].is_none() {
+                        expanded[p] = Some(self.expand_all(args[p].clone()));
+                    }
+                    expanded[p].clone().unwrap()
+                }
+            } else {
+                i += 1;
+                vec![PPToken::new(lexeme)]
+            };
+
+            // The argument takes the place of its parameter.
+            if let Some(first) = item.first_mut() {
+                first.lexeme.line_start = false;
+                first.lexeme.leading_space = lexeme.leading_space;
+            }
+
+            if paste && !last_empty && !item.is_empty() {
+                let lhs = output.pop().unwrap();
+                let rhs = item.remove(0);
+                output.extend(self.paste(lhs, rhs));
+                output.extend(item);
+                last_empty = false;
+            } else {
+                last_empty = item.is_empty() && (last_empty || !paste);
+                output.extend(item);
+            }
+            paste = false;
+        }
+        output
+    }
+
+    /**
+     * The `#` operator: turn the tokens of `arg` into a string literal.
+     */
+    fn stringize(&mut self, hash: Lexeme, arg: &[PPToken]) -> PPToken {
+        let mut text = String::from("\"");
+        for (i, token) in arg.iter().enumerate() {
+            if i > 0 && token.lexeme.leading_space {
+                text.push(' ');
+            }
+
+            let spelling = self.spelling(token.lexeme);
+            match token.lexeme.token {
+                // Quotes and backslashes in string literals and character constants are escaped,
+                // so the string spells them the way they were written.
+                Token::String(_) | Token::Character(_) | Token::Error => for ch in spelling.chars() {
+                    if ch == '"' || ch == '\\' {
+                        text.push('\\');
+                    }
+                    text.push(ch);
+                },
+                _ => text.push_str(&spelling),
+            }
+        }
+        text.push('"');
+
+        let lexemes = self.synthesize(text);
+        let mut lexeme = lexemes[0];
+        lexeme.line_start = hash.line_start;
+        lexeme.leading_space = hash.leading_space;
+        PPToken::new(lexeme)
+    }
+
+    /**
+     * The `##` operator: join two tokens into one. If they don't make a single token together,
+     * that's an error, and they're left as they were.
+     */
+    fn paste(&mut self, lhs: PPToken, rhs: PPToken) -> Vec<PPToken> {
+        let left = self.spelling(lhs.lexeme);
+        let right = self.spelling(rhs.lexeme);
+        let lexemes = self.synthesize(format!("{}{}", left, right));
+        if lexemes.len() != 1 || lexemes[0].token == Token::Error {
+            self.error(lhs.lexeme, format!("pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+                                           left, right));
+            return vec![lhs, rhs];
+        }
+
+        let mut lexeme = lexemes[0];
+        lexeme.line_start = lhs.lexeme.line_start;
+        lexeme.leading_space = lhs.lexeme.leading_space;
+        vec![PPToken{
+            lexeme: lexeme,
+            hide:   lhs.hide,
+        }]
+    }
+
+    /**
+     * Return how `lexeme` was spelled in the source.
+     */
+    pub(super) fn spelling(&self, lexeme: Lexeme) -> String {
+        lexer::translated(self.sources.text(lexeme.span), self.lex_options)
+    }
+
+    /**
+     * Lex text that the preprocessor made up, like the result of `#` or `##`. The text gets a File
+     * of its own, so the tokens have Spans (and spellings) like any others.
+     */
+    pub(super) fn synthesize(&mut self, text: String) -> Vec<Lexeme> {
+        let id = self.sources.add(File{
+            name:   "<scratch space>".to_string(),
+            buf:    text,
+        });
+        let options = lexer::Options{ trigraphs: false, ..self.lex_options };
+        lexer::lex_file(&self.sources.get(id).buf, id, options).0
+    }
+}
+
+/**
+ * Check the rules for the body of a macro: `##` can't come first or last, and in a function-like
+ * macro, `#` must be followed by a parameter.
+ */
+fn check_body(definition: &Macro) -> Result<(), (Lexeme, &'static str)> {
+    let body = &definition.body;
+    for (i, lexeme) in body.iter().enumerate() {
+        match lexeme.token {
+            Token::HashHash if i == 0 || i == body.len() - 1 =>
+                return Err((*lexeme, "'##' cannot appear at either end of a macro expansion")),
+            Token::Hash if definition.params.is_some() &&
+                           body.get(i + 1).and_then(|l| definition.param_index(l.token)).is_none() =>
+                return Err((*lexeme, "'#' is not followed by a macro parameter")),
+            token if !definition.variadic && macro_name(token) == Some(Symbol::intern("__VA_ARGS__")) =>
+                return Err((*lexeme, "__VA_ARGS__ can only appear in the expansion of a variadic macro")),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use preprocessor::test::preprocess;
+
+    fn expand(s: &str) -> (String, Vec<String>) {
+        preprocess(s, &[("a.h", "#define FROM_HEADER 1\n")])
+    }
+
+    fn ok(s: &str) -> String {
+        let (output, errors) = expand(s);
+        assert!(errors.is_empty(), "{:?}", errors);
+        output
+    }
+
+    #[test]
+    fn object_like() {
+        assert_eq!(ok("#define X 1 + 2\nX * X"), "1 + 2 * 1 + 2");
+        assert_eq!(ok("#define EMPTY\na EMPTY b"), "a b");
+        assert_eq!(ok("#define X Y\n#define Y 3\nX"), "3");
+        assert_eq!(ok("#include \"a.h\"\nFROM_HEADER"), "1");
+        assert_eq!(ok("X\n#define X 1\nX"), "X 1");
+    }
+
+    #[test]
+    fn function_like() {
+        assert_eq!(ok("#define add(a, b) a + b\nadd(1, 2) add (3,4)"), "1 + 2 3 + 4");
+        assert_eq!(ok("#define f(x) [x]\nf((a, b)) f(g(c, d)) f()"), "[ ( a , b ) ] [ g ( c , d ) ] [ ]");
+        assert_eq!(ok("#define f() 1\nf()"), "1");
+        assert_eq!(ok("#define f(x) x\nf\n(\n2\n)"), "2");
+    }
+
+    #[test]
+    fn function_like_without_arguments() {
+        assert_eq!(ok("#define f(x) x\nf + f"), "f + f");
+        assert_eq!(ok("#define f (x) x\nf"), "( x ) x");
+        assert_eq!(ok("#define f(x) x\n#define g f\ng(1)"), "1");
+    }
+
+    #[test]
+    fn keywords_can_be_macros() {
+        assert_eq!(ok("#define int long\nint x;"), "long x ;");
+    }
+
+    #[test]
+    fn stringizing() {
+        assert_eq!(ok("#define s(x) #x\ns(a  +  \"b\\n\" 'c')"), r#""a + \"b\\n\" 'c'""#);
+        assert_eq!(ok("#define s(x) # x\ns() s(  a\n b  )"), r#""" "a b""#);
+        assert_eq!(ok("#define s(x) #x\n#define t(x) s(x)\n#define ONE 1\ns(ONE) t(ONE)"), r#""ONE" "1""#);
+        assert_eq!(ok("#define X # a\nX"), "# a");
+    }
+
+    #[test]
+    fn pasting() {
+        assert_eq!(ok("#define cat(a, b) a ## b\ncat(x, y) cat(1, 2) cat(+, =) cat(, y) cat(x, ) cat(,) |"),
+                   "xy 12 += y x |");
+        assert_eq!(ok("#define cat(a, b, c) a ## b ## c\ncat(x, , z) cat(, , z) cat(1, 2, 3)"), "xz z 123");
+        assert_eq!(ok("#define cat(a, b) a ## b\n#define xy 5\ncat(x, y)"), "5");
+        assert_eq!(ok("#define cat(a, b) a ## b\n#define A 1\ncat(A, A)"), "AA");
+        assert_eq!(ok("#define X in ## t\nX"), "int");
+
+        let (output, errors) = expand("#define cat(a, b) a ## b\ncat(+, -)");
+        assert_eq!(output, "+ -");
+        assert_eq!(errors, vec!["main.c:2:5: pasting \"+\" and \"-\" does not give a valid preprocessing token"]);
+    }
+
+    #[test]
+    fn variadic() {
+        assert_eq!(ok("#define f(fmt, ...) g(fmt, __VA_ARGS__)\nf(a, b, (c, d)) f(a) f(a,)"),
+                   "g ( a , b , ( c , d ) ) g ( a , ) g ( a , )");
+        assert_eq!(ok("#define s(...) #__VA_ARGS__\ns(a,b,  c)"), "\"a,b, c\"");
+    }
+
+    /**
+     * Examples of rescanning from C11 6.10.3.4 and 6.10.3.5.
+     */
+    #[test]
+    fn rescanning() {
+        assert_eq!(ok("#define foo foo\nfoo"), "foo");
+        assert_eq!(ok("#define a b\n#define b a\na b"), "a b");
+        assert_eq!(ok("#define f(a) a*g\n#define g(a) f(a)\nf(2)(9)"), "2 * 9 * g");
+        assert_eq!(ok("#define id(x) x\n#define two 2\nid(id(two))"), "2");
+        assert_eq!(ok("#define f(x) g(x\n#define g(x) x\nf(1))"), "1");
+
+        let example = "#define x 3\n#define f(a) f(x * (a))\n#undef x\n#define x 2\n#define g f\n\
+                       #define z z[0]\n#define h g(~\n#define m(a) a(w)\n#define w 0,1\n\
+                       #define t(a) a\n#define p() int\n#define q(x) x\n#define r(x,y) x ## y\n\
+                       f(y+1) + f(f(z)) % t(t(g)(0) + t)(1);\n\
+                       g(x+(3,4)-w) | h 5) & m\n(f)^m(m);";
+        assert_eq!(ok(example),
+                   "f ( 2 * ( y + 1 ) ) + f ( 2 * ( f ( 2 * ( z [ 0 ] ) ) ) ) % f ( 2 * ( 0 ) ) + t ( 1 ) ; \
+                    f ( 2 * ( 2 + ( 3 , 4 ) - 0 , 1 ) ) | f ( 2 * ( ~ 5 ) ) & f ( 2 * ( 0 , 1 ) ) \
+                    ^ m ( 0 , 1 ) ;");
+    }
+
+    #[test]
+    fn undef() {
+        assert_eq!(ok("#define X 1\n#undef X\nX"), "X");
+        assert_eq!(ok("#undef NEVER_DEFINED\n"), "");
+    }
+
+    #[test]
+    fn redefinition() {
+        assert_eq!(expand("#define X 1  +  2\n#define X 1 + 2\n#define f(a) a\n#define f(a) a\n").1,
+                   Vec::<String>::new());
+        assert_eq!(expand("#define X 1\n#define X 2\n#define Y 1+2\n#define Y 1 + 2").1,
+                   vec!["main.c:2:9: 'X' macro redefined", "main.c:4:9: 'Y' macro redefined"]);
+    }
+
+    #[test]
+    fn definition_errors() {
+        let (_, errors) = expand("#define\n#define 3\n#define f(x x\n#define f(x, x)\n#define f(\n\
+                                  #define g(x) #y\n#define h ## a\n#define i a ##\n#define j __VA_ARGS__\n\
+                                  #define k(..., x)\n#undef");
+        assert_eq!(errors, vec![
+            "main.c:1:2: macro name missing",
+            "main.c:2:9: macro name must be an identifier",
+            "main.c:3:13: expected ',' or ')' in macro parameter list",
+            "main.c:4:14: duplicate macro parameter 'x'",
+            "main.c:5:10: missing ')' in macro parameter list",
+            "main.c:6:14: '#' is not followed by a macro parameter",
+            "main.c:7:11: '##' cannot appear at either end of a macro expansion",
+            "main.c:8:13: '##' cannot appear at either end of a macro expansion",
+            "main.c:9:11: __VA_ARGS__ can only appear in the expansion of a variadic macro",
+            "main.c:10:14: missing ')' after '...'",
+            "main.c:11:2: macro name missing",
+        ]);
+    }
+
+    #[test]
+    fn invocation_errors() {
+        let (output, errors) = expand("#define f(a, b) a\nf(1) f(1, 2, 3) x f(1,");
+        assert_eq!(output, "x");
+        assert_eq!(errors, vec![
+            "main.c:2:1: macro 'f' takes 2 arguments, but 1 was given",
+            "main.c:2:6: macro 'f' takes 2 arguments, but 3 were given",
+            "main.c:2:19: unterminated argument list invoking macro 'f'",
+        ]);
+    }
+
+    /**
+     * Tokens from a macro body keep the Spans of the `#define`, and arguments keep their own.
+     */
+    #[test]
+    fn spans_survive_expansion() {
+        let mut preprocessor = Preprocessor::new();
+        let (lexemes, _) = preprocessor.preprocess(File{
+            name:   "main.c".to_string(),
+            buf:    "#define f(x) + x\n\nf(\n  y)".to_string(),
+        });
+        let positions: Vec<(usize, usize)> = lexemes.iter().map(|l| (l.span.line, l.span.column)).collect();
+        assert_eq!(positions, vec![(1, 14), (4, 3)]);
+    }
+}
@@ -0,0 +1,697 @@
+/*!
+ * Preprocessor
+ * ============
+ *
+ * The preprocessor sits between loading source code and parsing it. It lexes each file into
+ * Lexemes, carries out the directives it finds (lines starting with `#`), and hands everything
+ * else on to the parser as one long stream of Lexemes.
+ *
+ * Directives are recognized by their tokens rather than their text, so a directive is a `Hash`
+ * token that is the first token on its line, and it runs until the next token that starts a line.
+ * Every Lexeme keeps the Span it was lexed from, so errors in included files (and in the bodies of
+ * macros) point at the right file and line.
+ *
+ * Macros are handled in `macros.rs` (with the predefined ones in `predefined.rs`), and
+ * conditional compilation in `conditionals.rs`. `#line`, `#error`, `#warning` and `#pragma` are in
+ * `directives.rs`. `output.rs` turns the result back into text, for `-E`, and `headers.rs` has the
+ * headers built into rust-cc.
+ */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use parser::lexer::{self, Lexeme, Operator, Token};
+use source::{Diagnostic, File, FileId, SourceMap, Span};
+use util::intern::Symbol;
+
+use self::conditionals::Conditional;
+use self::macros::{Macro, PPToken};
+
+mod conditionals;
+mod directives;
+mod expression;
+mod headers;
+mod macros;
+mod output;
+mod predefined;
+
+/**
+ * How deeply `#include`s may nest before we assume something has gone wrong. This is the same
+ * limit GCC uses.
+ */
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+/**
+ * Preprocessor holds everything that lives for the whole translation unit: the loaded files, the
+ * places to look for headers, and what we've learned about each header so far.
+ */
+pub struct Preprocessor {
+    /// Every file loaded so far. Spans in the output point into these.
+    pub sources:        SourceMap,
+
+    /// Directories searched for `#include <...>`, and for `#include "..."` when the file isn't
+    /// next to the file including it. These are the `-I` options.
+    pub include_paths:  Vec<PathBuf>,
+
+    pub lex_options:    lexer::Options,
+
+    /// Files that exist only in memory, by path. They are found before anything on disk.
+    virtual_files:      HashMap<PathBuf, String>,
+
+    /// Files that said `#pragma once`.
+    once:               HashSet<PathBuf>,
+
+    /// The include guard macro of every file that has one.
+    guards:             HashMap<PathBuf, Symbol>,
+
+    /// The macros that are currently defined.
+    macros:             HashMap<Symbol, Rc<Macro>>,
+
+    /// The files currently being read. The last one is the innermost `#include`.
+    stack:              Vec<Frame>,
+
+    /// Tokens to read before going back to the files, in reverse order. This is where the results
+    /// of macro expansions go to be rescanned.
+    pending:            Vec<PPToken>,
+
+    /// True while expanding a list of tokens on their own, such as a macro argument. When
+    /// `pending` runs out, so does the input.
+    isolated:           bool,
+
+    /// Where the macro being expanded was used, for placing its expansion in the output.
+    expansion_site:     Span,
+
+    errors:             Vec<Diagnostic>,
+}
+
+/**
+ * Frame is a file that's in the middle of being preprocessed.
+ */
+struct Frame {
+    path:           PathBuf,    // the path the file was found at
+    key:            PathBuf,    // the canonical path, for recognizing the same file by another name
+    lexemes:        Vec<Lexeme>,
+    pos:            usize,
+
+    /// The lexer's Diagnostics for this file, one for each Error token in `lexemes`, in order.
+    /// Each one is reported when its Error token is reached.
+    errors:         VecDeque<Diagnostic>,
+
+    /// The conditional directives we're inside, innermost last.
+    conditionals:   Vec<Conditional>,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        let mut preprocessor = Preprocessor{
+            sources:        SourceMap::new(),
+            include_paths:  vec![],
+            lex_options:    lexer::Options::default(),
+            virtual_files:  HashMap::new(),
+            once:           HashSet::new(),
+            guards:         HashMap::new(),
+            macros:         HashMap::new(),
+            stack:          vec![],
+            pending:        vec![],
+            isolated:       false,
+            expansion_site: Span::default(),
+            errors:         vec![],
+        };
+        for &(name, contents) in &headers::HEADERS {
+            preprocessor.add_virtual_file(Path::new(headers::BUILTIN_INCLUDE_DIR).join(name), contents);
+        }
+        preprocessor.predefine();
+        preprocessor
+    }
+
+    /**
+     * Make `contents` available at `path` without it existing on disk.
+     */
+    pub fn add_virtual_file<P: Into<PathBuf>, S: Into<String>>(&mut self, path: P, contents: S) {
+        self.virtual_files.insert(path.into(), contents.into());
+    }
+
+    /**
+     * Preprocess `file` and everything it includes. Like the lexer, this carries on past errors and
+     * returns all of them along with whatever Lexemes it could produce.
+     */
+    pub fn preprocess(&mut self, file: File) -> (Vec<Lexeme>, Vec<Diagnostic>) {
+        let id = self.sources.add(file);
+        let (output, errors) = self.preprocess_located(id);
+        (output.into_iter().map(|(lexeme, _)| lexeme).collect(), errors)
+    }
+
+    /**
+     * Preprocess the file `id`, pairing each resulting Lexeme with the place it belongs in the
+     * output: where it was in the source or, if it came from a macro, where the macro was used.
+     */
+    fn preprocess_located(&mut self, id: FileId) -> (Vec<(Lexeme, Span)>, Vec<Diagnostic>) {
+        let path = PathBuf::from(&self.sources.get(id).name);
+        let key = self.canonical(&path);
+        self.enter_file(path, key, id);
+
+        let mut output = vec![];
+        while let Some(token) = self.next_expanded() {
+            // Only tokens made by macros have anything in their hide sets.
+            let site = if token.hide.is_empty() { token.lexeme.span } else { self.expansion_site };
+            output.push((token.lexeme, site));
+        }
+
+        (output, mem::replace(&mut self.errors, vec![]))
+    }
+
+    /**
+     * Return the next token, before macro expansion. Tokens waiting to be rescanned come first,
+     * and then tokens from the files, with any directives among them carried out along the way.
+     */
+    fn next_token(&mut self) -> Option<PPToken> {
+        if let Some(token) = self.pending.pop() {
+            return Some(token);
+        }
+        if self.isolated {
+            return None;
+        }
+
+        loop {
+            let lexeme = match self.next_lexeme() {
+                Some(lexeme) => lexeme,
+                None => return None,
+            };
+            if lexeme.token == Token::Hash && lexeme.line_start {
+                // The message of an `#error` or `#warning` doesn't have to be made of valid tokens,
+                // so the lexer's complaints about it are dropped.
+                let reported = self.errors.len();
+                let line = self.rest_of_line();
+                match line.first().and_then(|l| name(l.token)) {
+                    Some("error") | Some("warning") => self.errors.truncate(reported),
+                    _ => (),
+                }
+                self.directive(&line);
+            } else {
+                return Some(PPToken::new(lexeme));
+            }
+        }
+    }
+
+    /**
+     * Return the next Lexeme of the innermost file, moving back out to the file that included it
+     * when it runs out.
+     */
+    fn next_lexeme(&mut self) -> Option<Lexeme> {
+        loop {
+            match self.stack.last_mut() {
+                None => return None,
+                Some(frame) => if frame.pos < frame.lexemes.len() {
+                    let lexeme = frame.lexemes[frame.pos];
+                    frame.pos += 1;
+                    if lexeme.token == Token::Error {
+                        self.errors.extend(frame.errors.pop_front());
+                    }
+                    return Some(lexeme);
+                },
+            }
+            let frame = self.stack.pop().unwrap();
+            self.close_conditionals(frame.conditionals);
+        }
+    }
+
+    /**
+     * Take the rest of the current line. A line never continues past the end of a file.
+     */
+    fn rest_of_line(&mut self) -> Vec<Lexeme> {
+        let mut line = vec![];
+        loop {
+            let more = match self.stack.last() {
+                Some(frame) => frame.lexemes.get(frame.pos).map_or(false, |l| !l.line_start),
+                None => false,
+            };
+            if !more {
+                return line;
+            }
+            line.extend(self.next_lexeme());
+        }
+    }
+
+    /**
+     * Carry out the directive on `line`, which is everything after the `#`.
+     */
+    fn directive(&mut self, line: &[Lexeme]) {
+        // A `#` on its own is the null directive, which does nothing.
+        let first = match line.first() {
+            Some(first) => *first,
+            None => return,
+        };
+
+        // `# 12 "file.h"` is a line marker, like the ones `-E` writes. They work like `#line`, so
+        // that the output of `-E` can be preprocessed again.
+        if let Token::Number(_) = first.token {
+            return self.line(first, line, true);
+        }
+
+        match name(first.token) {
+            Some("define") => self.define(first, &line[1..]),
+            Some("undef") => self.undef(first, &line[1..]),
+            Some("include") => self.include(first, &line[1..]),
+            Some("line") => self.line(first, &line[1..], false),
+            Some("error") | Some("warning") => self.error_directive(first, &line[1..]),
+            Some("pragma") => self.pragma(&line[1..]),
+            Some("if") | Some("ifdef") | Some("ifndef") | Some("elif") | Some("else") | Some("endif") => {
+                self.conditional(first, &line[1..])
+            },
+            Some(name) => self.error(first, format!("invalid preprocessing directive #{}", name)),
+            None => self.error(first, "invalid preprocessing directive"),
+        }
+    }
+
+    /**
+     * `#include "file"` or `#include <file>`.
+     */
+    fn include(&mut self, directive: Lexeme, rest: &[Lexeme]) {
+        let (name, quoted) = match self.header_name(rest) {
+            Some(header) => header,
+            None => {
+                let at = rest.first().cloned().unwrap_or(directive);
+                return self.error(at, "expected \"FILENAME\" or <FILENAME>");
+            },
+        };
+
+        let path = match self.find_include(&name, quoted) {
+            Some(path) => path,
+            None => return self.error(rest[0], format!("'{}' file not found", name)),
+        };
+        let key = self.canonical(&path);
+
+        // Files we've already seen might not need to be read again.
+        if self.once.contains(&key) {
+            return;
+        }
+        if let Some(guard) = self.guards.get(&key) {
+            if self.macros.contains_key(guard) {
+                return;
+            }
+        }
+
+        // A file that's still being read can only be included again if a guard or `#pragma once`
+        // stops it, as above. Anything else would loop until the depth limit, which is only left
+        // to catch long chains of different files.
+        if self.stack.iter().any(|frame| frame.key == key) {
+            return self.error(rest[0], format!("#include cycle: '{}' includes itself", name));
+        }
+        if self.stack.len() >= MAX_INCLUDE_DEPTH {
+            return self.error(rest[0], "#include nested too deeply");
+        }
+
+        let file = match self.load(&path) {
+            Ok(file) => file,
+            Err(e) => return self.error(rest[0], format!("can't read '{}': {}", name, e)),
+        };
+        let id = self.sources.add(file);
+        self.enter_file(path, key, id);
+    }
+
+    /**
+     * Work out which header an `#include` names, and whether it was in quotes (`true`) or angle
+     * brackets (`false`). The name is taken verbatim from the source, so backslashes in it aren't
+     * escapes.
+     */
+    fn header_name(&mut self, rest: &[Lexeme]) -> Option<(String, bool)> {
+        let first = match rest.first() {
+            Some(first) => *first,
+            None => return None,
+        };
+
+        match first.token {
+            Token::String(_) => {
+                let text = self.sources.text(first.span);
+                if text.starts_with('"') {
+                    Some((text[1..text.len() - 1].to_string(), true))
+                } else {
+                    None // a string with an encoding prefix
+                }
+            },
+
+            // `<stdio.h>` is lexed as the tokens `<`, `stdio`, `.`, `h` and `>`, so the name is
+            // whatever was written between the brackets.
+            Token::Operator(Operator::Less) => {
+                let close = rest.iter().find(|l| l.token == Token::Operator(Operator::Greater));
+                match close {
+                    Some(close) if close.span.file == first.span.file => {
+                        let buf = &self.sources.get(first.span.file).buf;
+                        Some((buf[first.span.offset + 1..close.span.offset].to_string(), false))
+                    },
+                    _ => None,
+                }
+            },
+
+            // Otherwise, the line is macro expanded, and the result should be one of the above.
+            // The name in brackets is put back together from the spellings of its tokens.
+            _ => {
+                let tokens = rest.iter().map(|&l| PPToken::new(l)).collect();
+                let expanded: Vec<Lexeme> = self.expand_all(tokens).into_iter().map(|t| t.lexeme).collect();
+                match expanded.first().map(|l| l.token) {
+                    Some(Token::String(_)) => self.header_name(&expanded[..1]),
+                    Some(Token::Operator(Operator::Less)) => {
+                        let close = match expanded.iter().position(|l| l.token == Token::Operator(Operator::Greater)) {
+                            Some(close) => close,
+                            None => return None,
+                        };
+                        let mut name = String::new();
+                        for (i, &lexeme) in expanded[1..close].iter().enumerate() {
+                            if i > 0 && lexeme.leading_space {
+                                name.push(' ');
+                            }
+                            name.push_str(&self.spelling(lexeme));
+                        }
+                        Some((name, false))
+                    },
+                    _ => None,
+                }
+            },
+        }
+    }
+
+    /**
+     * Search for an included file. Quoted names are looked for next to the file including them
+     * first, and then everything is looked for in the include paths, in order, and finally among
+     * the built-in headers.
+     */
+    fn find_include(&self, name: &str, quoted: bool) -> Option<PathBuf> {
+        if Path::new(name).is_absolute() {
+            let path = PathBuf::from(name);
+            return if self.exists(&path) { Some(path) } else { None };
+        }
+
+        let current_dir = if quoted {
+            self.stack.last().and_then(|frame| frame.path.parent()).map(|dir| dir.join(name))
+        } else {
+            None
+        };
+        current_dir.into_iter()
+            .chain(self.include_paths.iter().map(|dir| dir.join(name)))
+            .chain(Some(Path::new(headers::BUILTIN_INCLUDE_DIR).join(name)))
+            .find(|path| self.exists(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.virtual_files.contains_key(path) || path.is_file()
+    }
+
+    /**
+     * Return the path we use to tell whether two paths are the same file.
+     */
+    fn canonical(&self, path: &Path) -> PathBuf {
+        if self.virtual_files.contains_key(path) {
+            return path.to_path_buf();
+        }
+        fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    fn load(&self, path: &Path) -> Result<File, String> {
+        match self.virtual_files.get(path) {
+            Some(contents) => Ok(File{
+                name:   path.display().to_string(),
+                buf:    contents.clone(),
+            }),
+            None => File::from_disk(path).map_err(|e| e.to_string()),
+        }
+    }
+
+    /**
+     * Lex a newly loaded file and start reading from it.
+     */
+    fn enter_file(&mut self, path: PathBuf, key: PathBuf, id: FileId) {
+        let (lexemes, errors) = lexer::lex_file(&self.sources.get(id).buf, id, self.lex_options);
+
+        // The guard macro of a guarded file is remembered, so that once it's defined the next
+        // `#include` of the file can skip it without reading it.
+        if let Some(guard) = include_guard(&lexemes) {
+            self.guards.insert(key.clone(), guard);
+        }
+
+        self.stack.push(Frame{
+            path:           path,
+            key:            key,
+            lexemes:        lexemes,
+            pos:            0,
+            errors:         errors.into_iter().collect(),
+            conditionals:   vec![],
+        });
+    }
+
+    fn error<S: Into<String>>(&mut self, at: Lexeme, message: S) {
+        self.errors.push(Diagnostic::new(at.span, message));
+    }
+
+    fn warning<S: Into<String>>(&mut self, at: Lexeme, message: S) {
+        self.errors.push(Diagnostic::warning(at.span, message));
+    }
+}
+
+/**
+ * Return the spelling of a token that could name a macro or a directive. Keywords count, since
+ * the preprocessor runs before there is any such thing as a keyword, and `#if` and `#else` need
+ * to work.
+ */
+fn name(token: Token) -> Option<&'static str> {
+    match token {
+        Token::Identifier(symbol) => Some(symbol.as_str()),
+        Token::Keyword(keyword) => Some(keyword.as_str()),
+        _ => None,
+    }
+}
+
+/**
+ * Return the name of the macro that `token` would invoke, if it's a name at all.
+ */
+fn macro_name(token: Token) -> Option<Symbol> {
+    match token {
+        Token::Identifier(symbol) => Some(symbol),
+        Token::Keyword(keyword) => Some(Symbol::intern(keyword.as_str())),
+        _ => None,
+    }
+}
+
+/**
+ * Spell `s` as a C string literal.
+ */
+fn string_literal(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            literal.push('\\');
+        }
+        literal.push(c);
+    }
+    literal.push('"');
+    literal
+}
+
+/**
+ * If lexemes `i` and `i + 1` are the start of a directive, return the directive's name.
+ */
+fn directive_at(lexemes: &[Lexeme], i: usize) -> Option<&'static str> {
+    match (lexemes.get(i), lexemes.get(i + 1)) {
+        (Some(hash), Some(directive)) if hash.token == Token::Hash && hash.line_start &&
+                                         !directive.line_start => name(directive.token),
+        _ => None,
+    }
+}
+
+/**
+ * Recognize a file wrapped in an include guard:
+ *
+ * ```c
+ * #ifndef GUARD
+ * #define GUARD
+ * ...
+ * #endif
+ * ```
+ *
+ * where the `#endif` is the last thing in the file and closes the `#ifndef`. Return the guard
+ * macro.
+ */
+fn include_guard(lexemes: &[Lexeme]) -> Option<Symbol> {
+    let len = lexemes.len();
+    if len < 8 || directive_at(lexemes, 0) != Some("ifndef") || directive_at(lexemes, 3) != Some("define") ||
+       directive_at(lexemes, len - 2) != Some("endif") {
+        return None;
+    }
+
+    // The `#define` must have no body, and define the macro the `#ifndef` tests.
+    let guard = match (lexemes[2].token, lexemes[5].token) {
+        (Token::Identifier(a), Token::Identifier(b)) if a == b => a,
+        _ => return None,
+    };
+    if lexemes[2].line_start || lexemes[5].line_start || !lexemes[6].line_start {
+        return None;
+    }
+
+    // Make sure the final `#endif` matches the `#ifndef`, and not some other `#if`.
+    let mut depth = 0;
+    for i in 6..len - 2 {
+        match directive_at(lexemes, i) {
+            Some("if") | Some("ifdef") | Some("ifndef") => depth += 1,
+            Some("endif") if depth == 0 => return None,
+            Some("endif") => depth -= 1,
+            _ => (),
+        }
+    }
+    Some(guard)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /**
+     * Return a Preprocessor with the virtual `files` available, and `sys` as an include path.
+     */
+    pub(super) fn preprocessor(files: &[(&str, &str)]) -> Preprocessor {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.include_paths.push(PathBuf::from("sys"));
+        for &(path, contents) in files {
+            preprocessor.add_virtual_file(path, contents);
+        }
+        preprocessor
+    }
+
+    /**
+     * Preprocess `s` as `main.c` with the virtual `files` available, and return the spellings of
+     * the resulting tokens separated by spaces, and the diagnostics (formatted with file names).
+     */
+    pub(super) fn preprocess(s: &str, files: &[(&str, &str)]) -> (String, Vec<String>) {
+        preprocess_with(s, files, |_| ())
+    }
+
+    /**
+     * Like `preprocess`, but run `setup` on the Preprocessor first, as the command line would.
+     */
+    pub(super) fn preprocess_with<F>(s: &str, files: &[(&str, &str)], setup: F) -> (String, Vec<String>)
+        where F: FnOnce(&mut Preprocessor)
+    {
+        let mut preprocessor = preprocessor(files);
+        setup(&mut preprocessor);
+        let (lexemes, errors) = preprocessor.preprocess(File{
+            name:   "main.c".to_string(),
+            buf:    s.to_string(),
+        });
+        let spellings: Vec<String> = lexemes.iter().map(|&l| preprocessor.spelling(l)).collect();
+        let errors = errors.iter().map(|e| preprocessor.sources.describe(e)).collect();
+        (spellings.join(" "), errors)
+    }
+
+    #[test]
+    fn no_directives() {
+        assert_eq!(preprocess("a 1", &[]), ("a 1".to_string(), vec![]));
+    }
+
+    #[test]
+    fn quoted_include() {
+        let files = [("a.h", "a\n#include \"dir/b.h\"\nc"), ("dir/b.h", "#include \"c.h\"\n"), ("dir/c.h", "b")];
+        assert_eq!(preprocess("#include \"a.h\"\n1", &files),
+                   ("a b c 1".to_string(), vec![]));
+    }
+
+    #[test]
+    fn angle_include_uses_include_paths() {
+        let files = [("sys/stdio.h", "x"), ("stdio.h", "wrong")];
+        assert_eq!(preprocess("#include <stdio.h>", &files), ("x".to_string(), vec![]));
+    }
+
+    #[test]
+    fn quoted_include_falls_back_to_include_paths() {
+        assert_eq!(preprocess("#include \"x.h\"", &[("sys/x.h", "x")]), ("x".to_string(), vec![]));
+    }
+
+    #[test]
+    fn include_errors() {
+        let (_, errors) = preprocess("#include \"missing.h\"\n#include <missing.h>\n#include\n# include 3", &[]);
+        assert_eq!(errors, vec![
+            "main.c:1:10: 'missing.h' file not found",
+            "main.c:2:10: 'missing.h' file not found",
+            "main.c:3:2: expected \"FILENAME\" or <FILENAME>",
+            "main.c:4:11: expected \"FILENAME\" or <FILENAME>",
+        ]);
+    }
+
+    #[test]
+    fn macro_include() {
+        let files = [("a.h", "a"), ("sys/b.h", "b")];
+        assert_eq!(preprocess("#define A \"a.h\"\n#define B <b.h>\n#include A\n#include B", &files),
+                   ("a b".to_string(), vec![]));
+    }
+
+    #[test]
+    fn errors_point_into_included_files() {
+        let (_, errors) = preprocess("#include \"a.h\"\n$", &[("a.h", "\n  @")]);
+        assert_eq!(errors, vec!["a.h:2:3: unexpected character '@'", "main.c:2:1: unexpected character '$'"]);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let files = [("a.h", "a\n#include \"b.h\""), ("b.h", "b\n#include \"a.h\"")];
+        let (output, errors) = preprocess("#include \"a.h\"", &files);
+        assert_eq!(output, "a b");
+        assert_eq!(errors, vec!["b.h:2:10: #include cycle: 'a.h' includes itself"]);
+
+        // A macro that changes each time doesn't make it any less of a cycle.
+        let counter = "#ifndef DEPTH\n#define DEPTH 1\n#else\n#undef DEPTH\n#define DEPTH 2\n#endif\n\
+                       DEPTH\n#if DEPTH < 2\n#include \"a.h\"\n#endif\n";
+        let (output, errors) = preprocess("#include \"a.h\"", &[("a.h", counter)]);
+        assert_eq!(output, "1");
+        assert_eq!(errors, vec!["a.h:9:10: #include cycle: 'a.h' includes itself"]);
+    }
+
+    #[test]
+    fn include_depth_limit() {
+        // Different files can still nest too deeply, where the limit counts main.c too.
+        let names: Vec<String> = (0..MAX_INCLUDE_DEPTH).map(|i| format!("{}.h", i)).collect();
+        let contents: Vec<String> = (0..MAX_INCLUDE_DEPTH).map(|i| format!("#include \"{}.h\"", i + 1)).collect();
+        let files: Vec<(&str, &str)> = names.iter().zip(&contents).map(|(n, c)| (&n[..], &c[..])).collect();
+        let (_, errors) = preprocess("#include \"0.h\"", &files);
+        assert_eq!(errors, vec![format!("{}.h:1:10: #include nested too deeply", MAX_INCLUDE_DEPTH - 2)]);
+    }
+
+    #[test]
+    fn pragma_once() {
+        let files = [("a.h", "#pragma once\na")];
+        assert_eq!(preprocess("#include \"a.h\"\n#include \"a.h\"", &files), ("a".to_string(), vec![]));
+
+        // It stops a file including itself too.
+        let files = [("a.h", "#pragma once\na\n#include \"a.h\"")];
+        assert_eq!(preprocess("#include \"a.h\"", &files), ("a".to_string(), vec![]));
+    }
+
+    #[test]
+    fn include_guards() {
+        let guarded = "#ifndef A_H\n#define A_H\na\n#endif\n";
+        assert_eq!(preprocess("#include \"a.h\"\n#include \"a.h\"", &[("a.h", guarded)]), ("a".to_string(), vec![]));
+
+        // A file guarded this way can include itself without looping.
+        let recursive = "#ifndef A_H\n#define A_H\na\n#include \"a.h\"\n#endif";
+        assert_eq!(preprocess("#include \"a.h\"", &[("a.h", recursive)]), ("a".to_string(), vec![]));
+    }
+
+    #[test]
+    fn recognizing_include_guards() {
+        let guard = |s: &str| include_guard(&lexer::lex(s).unwrap());
+        assert_eq!(guard("#ifndef X\n#define X\na\n#endif"), Some(Symbol::intern("X")));
+        assert_eq!(guard("#ifndef X\n#define X\n#endif"), Some(Symbol::intern("X")));
+        assert_eq!(guard("#ifndef X\n#define X\n#if Y\n#endif\n#endif"), Some(Symbol::intern("X")));
+        assert_eq!(guard("#ifndef X\n#define Y\n#endif"), None);
+        assert_eq!(guard("#ifndef X\n#define X 1\n#endif"), None);
+        assert_eq!(guard("#ifndef X\n#define X\n#endif\na"), None);
+        assert_eq!(guard("a\n#ifndef X\n#define X\n#endif"), None);
+        assert_eq!(guard("#ifndef X\n#define X\n#endif\n#if Y\n#endif"), None);
+    }
+
+    #[test]
+    fn directives_start_lines() {
+        let (output, errors) = preprocess("a # include \"x.h\"\n#\n#bogus", &[]);
+        assert_eq!(output, "a # include \"x.h\"");
+        assert_eq!(errors, vec!["main.c:3:2: invalid preprocessing directive #bogus"]);
+    }
+}
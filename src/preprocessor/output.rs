@@ -0,0 +1,167 @@
+/*!
+ * Preprocessed output
+ * ===================
+ *
+ * `-E` writes out a translation unit after preprocessing, as C source. It's meant for people
+ * debugging macros, so tokens are kept on the lines they came from, and GCC-style line markers
+ * like `# 12 "file.h"` say where the lines came from:
+ *
+ * ```c
+ * # 1 "main.c"
+ * int x;
+ * # 1 "header.h" 1
+ * int y;
+ * # 3 "main.c" 2
+ * ```
+ *
+ * A `1` after the file name means the file was just included, and a `2` means we've returned to
+ * it from an include. Like error messages, the markers give the locations `#line` says the lines
+ * are at.
+ *
+ * The output lexes to the same tokens as the preprocessor produced (with the same lexer options).
+ * Tokens that would run together if written next to each other, like `+` and `+`, get a space
+ * between them even where the source had none.
+ */
+
+use parser::lexer;
+use preprocessor::{string_literal, Preprocessor};
+use source::{Diagnostic, File};
+
+/**
+ * How many blank lines we'll write to get to the next line with tokens on it before it's shorter
+ * to write a line marker.
+ */
+const MAX_BLANK_LINES: usize = 8;
+
+impl Preprocessor {
+    /**
+     * Preprocess `file` and everything it includes, and return the result as text.
+     */
+    pub fn preprocess_to_text(&mut self, file: File) -> (String, Vec<Diagnostic>) {
+        let main = self.sources.add(file);
+        let (output, errors) = self.preprocess_located(main);
+
+        let mut name = self.sources.get(main).name.clone();
+        let mut text = format!("# 1 {}\n", string_literal(&name));
+        let mut files = vec![main]; // the files we're inside, innermost last
+        let mut line = 1;
+        let mut previous: Option<String> = None; // the last token on the current line
+        for (lexeme, site) in output {
+            let (site_name, site_line) = self.sources.presumed(site);
+            let moved = files.last() != Some(&site.file) || site_name != name || site_line < line ||
+                        site_line > line + MAX_BLANK_LINES;
+            if moved {
+                if previous.is_some() {
+                    text.push('\n');
+                }
+                let flag = if files.last() == Some(&site.file) {
+                    ""
+                } else if let Some(i) = files.iter().position(|&f| f == site.file) {
+                    files.truncate(i + 1);
+                    " 2"
+                } else {
+                    files.push(site.file);
+                    " 1"
+                };
+                name = site_name.to_string();
+                text.push_str(&format!("# {} {}{}\n", site_line, string_literal(&name), flag));
+                line = site_line;
+                previous = None;
+            }
+            while line < site_line {
+                text.push('\n');
+                line += 1;
+                previous = None;
+            }
+
+            let spelling = self.spelling(lexeme);
+            match previous {
+                None => for _ in 1..site.column {
+                    text.push(' ');
+                },
+                Some(ref previous) => if lexeme.leading_space || self.would_paste(previous, &spelling) {
+                    text.push(' ');
+                },
+            }
+            text.push_str(&spelling);
+            previous = Some(spelling);
+        }
+        if previous.is_some() {
+            text.push('\n');
+        }
+
+        (text, errors)
+    }
+
+    /**
+     * Whether writing `a` right before `b` would lex as something other than those two tokens.
+     */
+    fn would_paste(&self, a: &str, b: &str) -> bool {
+        let (lexemes, errors) = lexer::lex_file(&format!("{}{}", a, b), 0, self.lex_options);
+        !errors.is_empty() || lexemes.len() != 2 || lexemes[1].span.offset != a.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use preprocessor::test::{preprocess, preprocessor};
+
+    fn preprocess_to_text(s: &str, files: &[(&str, &str)]) -> String {
+        let (text, errors) = preprocessor(files).preprocess_to_text(File{
+            name:   "main.c".to_string(),
+            buf:    s.to_string(),
+        });
+        assert!(errors.is_empty(), "{:?}", errors);
+        text
+    }
+
+    #[test]
+    fn lines_and_whitespace() {
+        assert_eq!(preprocess_to_text("", &[]), "# 1 \"main.c\"\n");
+        assert_eq!(preprocess_to_text("int  main()\n{\n\n    return 0 ;\n}", &[]),
+                   "# 1 \"main.c\"\nint main()\n{\n\n    return 0 ;\n}\n");
+        assert_eq!(preprocess_to_text("#define X 1\n\na = X;", &[]), "# 1 \"main.c\"\n\n\na = 1;\n");
+        assert_eq!(preprocess_to_text("a\n\n\n\n\n\n\n\n\n\n\nb", &[]),
+                   "# 1 \"main.c\"\na\n# 12 \"main.c\"\nb\n");
+    }
+
+    #[test]
+    fn includes() {
+        let files = [("a.h", "x\n#include \"b.h\"\ny"), ("b.h", "#define B\n\nz")];
+        assert_eq!(preprocess_to_text("a\n#include \"a.h\"\nb", &files),
+                   "# 1 \"main.c\"\na\n# 1 \"a.h\" 1\nx\n# 3 \"b.h\" 1\nz\n# 3 \"a.h\" 2\ny\n# 3 \"main.c\" 2\nb\n");
+
+        // The main file comes first even when its first tokens are from a header.
+        assert_eq!(preprocess_to_text("#include \"a.h\"", &[("a.h", "x")]),
+                   "# 1 \"main.c\"\n# 1 \"a.h\" 1\nx\n");
+    }
+
+    #[test]
+    fn line_directives() {
+        assert_eq!(preprocess_to_text("a\n#line 20 \"x.y\"\nb\nc\n#line 30\nd", &[]),
+                   "# 1 \"main.c\"\na\n# 20 \"x.y\"\nb\nc\n# 30 \"x.y\"\nd\n");
+    }
+
+    #[test]
+    fn macro_expansions_stay_where_they_were_used() {
+        // Whatever follows a macro invocation that spans lines goes back to the line it was on.
+        let source = "#define ADD(a, b) a+b\nx = ADD(1,\n        2); y\n  ADD(3, 4)";
+        assert_eq!(preprocess_to_text(source, &[]), "# 1 \"main.c\"\n\nx = 1+2\n          ; y\n  3+4\n");
+    }
+
+    #[test]
+    fn tokens_dont_paste() {
+        let source = "#define E\n#define P +\n#define CAT(a, b) a ## b\n-E- +P a/**/b x E y CAT(<, <)=";
+        assert_eq!(preprocess_to_text(source, &[]), "# 1 \"main.c\"\n\n\n\n- - + + a b x y << =\n");
+    }
+
+    #[test]
+    fn output_preprocesses_to_the_same_tokens() {
+        let files = [("a.h", "#ifndef A_H\n#define A_H\n#define SQUARE(x) ((x) * (x))\nint a;\n#endif\n")];
+        let source = "#include \"a.h\"\n#define S(x) #x\nint main() {\n    return SQUARE(2)--1 + sizeof S(a \"b\\n\");\n}\n\
+                      #include \"a.h\"\n\n\n\n\n\n\n\n\n\n\nchar *file = __FILE__; int line = __LINE__;\n";
+        let text = preprocess_to_text(source, &files);
+        assert_eq!(preprocess(&text, &[]).0, preprocess(source, &files).0);
+    }
+}
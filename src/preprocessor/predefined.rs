@@ -0,0 +1,210 @@
+/*!
+ * Predefined macros
+ * =================
+ *
+ * The macros every translation unit starts with (C11 6.10.8), and the ones given on the command
+ * line with `-D` and `-U`.
+ *
+ * Most of them are ordinary object-like macros, made by running `#define` lines through the
+ * preprocessor before the main file is read, as if they were in a file of their own. `__FILE__`
+ * and `__LINE__` change as we go, so they are built in and worked out each time they're used.
+ */
+
+use std::env;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parser::lexer;
+use preprocessor::{string_literal, Preprocessor};
+use preprocessor::macros::{Macro, PPToken};
+use source::File;
+use util::intern::Symbol;
+
+/**
+ * Builtin is a macro whose expansion depends on where it's used.
+ */
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Builtin {
+    File,
+    Line,
+}
+
+impl Preprocessor {
+    /**
+     * Define the predefined macros.
+     */
+    pub(super) fn predefine(&mut self) {
+        let (date, time) = date_and_time(build_time());
+        let definitions = format!("#define __STDC__ 1\n\
+                                   #define __STDC_VERSION__ 201112L\n\
+                                   #define __STDC_HOSTED__ 1\n\
+                                   #define __RUST_CC__ 1\n\
+                                   #define __DATE__ \"{}\"\n\
+                                   #define __TIME__ \"{}\"\n", date, time);
+        self.run_directives("<built-in>", definitions);
+
+        for &(name, builtin) in &[("__FILE__", Builtin::File), ("__LINE__", Builtin::Line)] {
+            self.macros.insert(Symbol::intern(name), Rc::new(Macro{
+                params:     None,
+                variadic:   false,
+                body:       vec![],
+                builtin:    Some(builtin),
+            }));
+        }
+    }
+
+    /**
+     * Define `name` as if by `#define name value`. This is `-D name=value`.
+     */
+    pub fn define_macro(&mut self, name: &str, value: &str) {
+        // The value ends at the end of the line, so it can't sneak in another directive.
+        let value = value.lines().next().unwrap_or("");
+        self.run_directives("<command line>", format!("#define {} {}\n", name, value));
+    }
+
+    /**
+     * Undefine `name` as if by `#undef name`. This is `-U name`.
+     */
+    pub fn undefine_macro(&mut self, name: &str) {
+        self.run_directives("<command line>", format!("#undef {}\n", name));
+    }
+
+    /**
+     * Carry out the directives in `text`, which has nothing but directives in it.
+     */
+    fn run_directives(&mut self, name: &str, text: String) {
+        let id = self.sources.add(File{
+            name:   name.to_string(),
+            buf:    text,
+        });
+        let (lexemes, errors) = lexer::lex_file(&self.sources.get(id).buf, id, self.lex_options);
+        self.errors.extend(errors);
+
+        let starts: Vec<usize> = (0..lexemes.len()).filter(|&i| lexemes[i].line_start).collect();
+        for (n, &start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).cloned().unwrap_or(lexemes.len());
+            self.directive(&lexemes[start + 1..end]);
+        }
+    }
+
+    /**
+     * Expand `__FILE__` or `__LINE__`. They describe the line currently being read from the
+     * innermost file, which is where `token` came from unless it was in the body of a macro.
+     */
+    pub(super) fn expand_builtin(&mut self, name: Symbol, builtin: Builtin, token: &PPToken) -> PPToken {
+        let here = match self.stack.last() {
+            Some(frame) if frame.pos > 0 => frame.lexemes[frame.pos - 1],
+            _ => token.lexeme,
+        };
+        let text = match (builtin, self.sources.presumed(here.span)) {
+            (Builtin::File, (name, _)) => string_literal(name),
+            (Builtin::Line, (_, line)) => line.to_string(),
+        };
+
+        let mut lexeme = self.synthesize(text)[0];
+        lexeme.line_start = token.lexeme.line_start;
+        lexeme.leading_space = token.lexeme.leading_space;
+        let mut hide = token.hide.clone();
+        hide.push(name);
+        PPToken{
+            lexeme: lexeme,
+            hide:   hide,
+        }
+    }
+}
+
+/**
+ * Return the time to give `__DATE__` and `__TIME__`. Like GCC, we use `SOURCE_DATE_EPOCH` instead
+ * of the current time if it's set, so that builds can be reproduced exactly.
+ */
+fn build_time() -> SystemTime {
+    match env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse().ok()) {
+        Some(seconds) => UNIX_EPOCH + Duration::from_secs(seconds),
+        None => SystemTime::now(),
+    }
+}
+
+/**
+ * Format `time` as `__DATE__` and `__TIME__` want it: `"Mmm dd yyyy"` (with the day padded with a
+ * space) and `"hh:mm:ss"`. Times are in UTC.
+ */
+fn date_and_time(time: SystemTime) -> (String, String) {
+    const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Count in 400 year eras starting on March 1st, so the leap day comes at the end of a year.
+    // This is Howard Hinnant's `civil_from_days`.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // 0 is March
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    (format!("{} {:2} {}", MONTHS[month as usize - 1], day, year),
+     format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use preprocessor::test::{preprocess, preprocess_with};
+
+    fn ok(s: &str) -> String {
+        let (output, errors) = preprocess(s, &[("dir/a.h", "__FILE__ __LINE__\n__LINE__")]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        output
+    }
+
+    #[test]
+    fn standard_macros() {
+        assert_eq!(ok("__STDC__ __STDC_VERSION__ __STDC_HOSTED__ __RUST_CC__"), "1 201112L 1 1");
+        assert_eq!(ok("#if defined(__RUST_CC__) && __STDC_VERSION__ >= 199901L\nyes\n#endif"), "yes");
+        assert_eq!(ok("#if defined __FILE__ && defined __DATE__\nyes\n#endif"), "yes");
+    }
+
+    #[test]
+    fn file_and_line() {
+        assert_eq!(ok("__FILE__ __LINE__\n\n __LINE__"), "\"main.c\" 1 3");
+        assert_eq!(ok("#include \"dir/a.h\"\n__LINE__ __FILE__"), "\"dir/a.h\" 1 2 2 \"main.c\"");
+        assert_eq!(ok("#define HERE __FILE__:__LINE__\n\nHERE"), "\"main.c\" : 3");
+        assert_eq!(ok("#define S(x) #x\n#define STR(x) S(x)\nSTR(__LINE__)"), "\"3\"");
+        assert_eq!(ok("#if __LINE__ == 1\nyes\n#endif"), "yes");
+    }
+
+    #[test]
+    fn date_and_time_formats() {
+        let at = |seconds| date_and_time(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), ("Jan  1 1970".to_string(), "00:00:00".to_string()));
+        assert_eq!(at(1000000000), ("Sep  9 2001".to_string(), "01:46:40".to_string()));
+        assert_eq!(at(951868799), ("Feb 29 2000".to_string(), "23:59:59".to_string()));
+        assert_eq!(at(1792316096), ("Oct 18 2026".to_string(), "09:34:56".to_string()));
+
+        let output = ok("__DATE__ __TIME__");
+        assert_eq!((output.len(), &output[..1], &output[14..15]), (24, "\"", "\""));
+    }
+
+    #[test]
+    fn command_line_definitions() {
+        let (output, errors) = preprocess_with("A B C __STDC_HOSTED__", &[], |p| {
+            p.define_macro("A", "1");
+            p.define_macro("B", "A + 2");
+            p.define_macro("C", "x");
+            p.undefine_macro("C");
+            p.undefine_macro("__STDC_HOSTED__");
+        });
+        assert_eq!((output, errors), ("1 1 + 2 C __STDC_HOSTED__".to_string(), vec![]));
+
+        let (output, errors) = preprocess_with("F(2)", &[], |p| p.define_macro("F(x)", "x * x\n#define G"));
+        assert_eq!((output, errors), ("2 * 2".to_string(), vec![]));
+
+        let (_, errors) = preprocess_with("", &[], |p| p.define_macro("3", "x"));
+        assert_eq!(errors, vec!["<command line>:1:9: macro name must be an identifier"]);
+    }
+}
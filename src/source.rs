@@ -1,14 +1,94 @@
 
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::io::prelude::*;
 use std::path::Path;
 use std::error::Error;
 
+/**
+ * FileId identifies the File a Span points into. It's an index into the SourceMap the File was
+ * added to.
+ */
+pub type FileId = usize;
+
+/**
+ * Span describes where a piece of source code (usually a single token) came from.
+ *
+ * `offset` and `len` are measured in bytes so the original text can be sliced back out of the
+ * File. `line` and `column` are 1-based and exist purely for humans reading error messages.
+ */
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Span {
+    pub file:   FileId,
+    pub offset: usize,  // byte offset of the first character
+    pub len:    usize,  // length in bytes
+    pub line:   usize,  // 1-based line number
+    pub column: usize,  // 1-based column, counted in characters
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/**
+ * Level says how serious a Diagnostic is. Only errors stop a program from being compiled.
+ */
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+/**
+ * Diagnostic is an error message attached to the place in the source that caused it. The lexer
+ * and the parser both report their errors this way.
+ */
+#[derive(Clone,Debug,PartialEq)]
+pub struct Diagnostic {
+    pub level:      Level,
+    pub span:       Span,
+    pub message:    String,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(span: Span, message: S) -> Self {
+        Diagnostic{
+            level:      Level::Error,
+            span:       span,
+            message:    message.into(),
+        }
+    }
+
+    pub fn warning<S: Into<String>>(span: Span, message: S) -> Self {
+        Diagnostic{
+            level:      Level::Warning,
+            ..Diagnostic::new(span, message)
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.level {
+            Level::Error => write!(f, "{}: {}", self.span, self.message),
+            Level::Warning => write!(f, "{}: warning: {}", self.span, self.message),
+        }
+    }
+}
+
 /**
  * File is our initial datastructure. We load the source code into this structure and then do
  * operations on it.
  */
 pub struct File {
+    pub name: String,
     pub buf:  String,
 }
 
@@ -21,85 +101,106 @@ impl File {
         let mut s = String::new();
         try!(f.read_to_string(&mut s));
 
-        // Handle block comments.
-        //
-        // TODO: Move block comment support into the lexer. It doesn't make sense for a File to
-        // handle block comments (a File should be agnostic about its contents), and pretrimming
-        // the block comments will throw off line numbers.
-        //
-        s = File::strip_block_comments(s);
-
         Ok(File{
+            name: location.display().to_string(),
             buf: s,
         })
     }
+}
+
+/**
+ * SourceMap owns every File that goes into a translation unit: the main file and everything it
+ * `#include`s. Spans point into it by FileId.
+ *
+ * It also knows where each line of a file is *presumed* to be, which `#line` can change. Generated
+ * code uses `#line` to make errors point at the file it was generated from.
+ */
+#[derive(Default)]
+pub struct SourceMap {
+    files:      Vec<File>,
+
+    /// The `#line` directives in each file that has any, in order.
+    line_maps:  HashMap<FileId, Vec<LineMap>>,
+}
+
+/**
+ * LineMap says that from physical line `from` of a file on, the lines are presumed to be numbered
+ * from `line`, in the file called `name`.
+ */
+struct LineMap {
+    from:   usize,
+    line:   usize,
+    name:   String,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
 
     /**
-     * Remove all comments from the string that are of the form `/* some comment */`. These may
-     * span multiple newlines. Nested block comments are not supported (in the traditional C
-     * style).
+     * Take ownership of `file` and return the FileId that Spans into it should use.
      */
-    fn strip_block_comments(s: String) -> String {
-        let mut in_block_comment = false;
-        let mut processed = String::with_capacity(s.len());
-        let mut iter = s.chars().peekable();
-
-        loop {
-            // this is really a manual version of `for c in iter` but we have to be able to peek
-            // and skip.
-            let c = match iter.next() {
-                Some(c) => c,
-                None => break,
-            };
-
-            if in_block_comment {
-                if c == '*' {
-                    match iter.peek() {
-                        Some(&'/') => {
-                            iter.next(); // skip over the `/` character
-                            in_block_comment = false;
-                        },
-                        _ => (),
-                    }
-                }
-                continue;
-            }
-
-            // possibly starting new comment
-            if c == '/' {
-                match iter.peek() {
-                    Some(&'*') => {
-                        iter.next(); // skip over the `*` character
-                        in_block_comment = true;
-                        continue;
-                    },
-                    _ => (),
-                }
-            }
-
-            processed.push(c);
-        }
+    pub fn add(&mut self, file: File) -> FileId {
+        self.files.push(file);
+        self.files.len() - 1
+    }
 
-        processed
+    pub fn get(&self, id: FileId) -> &File {
+        &self.files[id]
     }
-}
 
-#[test]
-fn strip_block_comments() {
-    assert_eq!(File::strip_block_comments(
-            "/**/".to_string()),
-            "".to_string());
+    /**
+     * Return the source text that `span` covers.
+     */
+    pub fn text(&self, span: Span) -> &str {
+        &self.files[span.file].buf[span.offset..span.offset + span.len]
+    }
 
-    assert_eq!(File::strip_block_comments(
-            "hello /*hello*/goodbye".to_string()),
-            "hello goodbye".to_string());
+    /**
+     * Record a `#line`: physical line `from` of `file` (the line after the directive) is presumed to
+     * be line `line`, and to be in the file called `name`, or in the same file as before if `name`
+     * is None.
+     */
+    pub fn add_line_map(&mut self, file: FileId, from: usize, line: usize, name: Option<String>) {
+        let name = match name {
+            Some(name) => name,
+            None => self.presumed_name(file, from).to_string(),
+        };
+        self.line_maps.entry(file).or_insert_with(Vec::new).push(LineMap{
+            from:   from,
+            line:   line,
+            name:   name,
+        });
+    }
 
-    assert_eq!(File::strip_block_comments(
-            "this is my file /* this is a comment */ */".to_string()),
-            "this is my file  */".to_string());
+    /**
+     * Return the file name and line number the start of `span` is presumed to be at.
+     */
+    pub fn presumed(&self, span: Span) -> (&str, usize) {
+        match self.line_map(span.file, span.line) {
+            Some(map) => (&map.name, map.line + span.line - map.from),
+            None => (&self.files[span.file].name, span.line),
+        }
+    }
 
-    assert_eq!(File::strip_block_comments(
-            "this /*is my */file /* this is a\n multiline comment */".to_string()),
-            "this file ".to_string());
-}
+    fn presumed_name(&self, file: FileId, line: usize) -> &str {
+        match self.line_map(file, line) {
+            Some(map) => &map.name,
+            None => &self.files[file].name,
+        }
+    }
+
+    fn line_map(&self, file: FileId, line: usize) -> Option<&LineMap> {
+        self.line_maps.get(&file).and_then(|maps| maps.iter().rev().find(|map| map.from <= line))
+    }
 
+    /**
+     * Format `diagnostic` for humans, like `hello.c:3:14: unexpected character '$'`.
+     */
+    pub fn describe(&self, diagnostic: &Diagnostic) -> String {
+        let (name, line) = self.presumed(diagnostic.span);
+        let span = Span{ line: line, ..diagnostic.span };
+        format!("{}:{}", name, Diagnostic{ span: span, ..diagnostic.clone() })
+    }
+}
@@ -0,0 +1,170 @@
+/*!
+ * Interning
+ * =========
+ *
+ * The lexer produces a lot of identifiers and string literals, most of which are repeats. Instead
+ * of allocating a fresh copy of each one, we store every distinct value once in a table and hand
+ * out a small integer id for it. Those ids are `Copy`, compare in constant time, and keep `Token`
+ * cheap to copy around.
+ *
+ * The tables are thread-local and live for as long as their thread does. An id is only
+ * meaningful on the thread that created it.
+ */
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+use ast::StringLiteral;
+
+/**
+ * Symbol is an interned identifier (or any other str). `Symbol::intern("main")` returns the same
+ * Symbol every time it's called.
+ */
+#[derive(Clone,Copy,PartialEq,Eq,Hash,PartialOrd,Ord)]
+pub struct Symbol(u32);
+
+/**
+ * StringId is an interned string literal.
+ */
+#[derive(Clone,Copy,PartialEq,Eq,Hash)]
+pub struct StringId(u32);
+
+thread_local! {
+    static SYMBOLS: RefCell<Interner<&'static str>> = RefCell::new(Interner::new());
+    static STRINGS: RefCell<Interner<StringLiteral>> = RefCell::new(Interner::new());
+}
+
+impl Symbol {
+    pub fn intern(s: &str) -> Symbol {
+        SYMBOLS.with(|symbols| {
+            let mut symbols = symbols.borrow_mut();
+            if let Some(&id) = symbols.ids.get(s) {
+                return Symbol(id);
+            }
+
+            // Symbols live as long as the thread, so it's fine to leak the string and hand out
+            // 'static references to it.
+            let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+            Symbol(symbols.insert(s))
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        SYMBOLS.with(|symbols| symbols.borrow().items[self.0 as usize])
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl StringId {
+    pub fn intern(literal: StringLiteral) -> StringId {
+        STRINGS.with(|strings| {
+            let mut strings = strings.borrow_mut();
+            if let Some(&id) = strings.ids.get(&literal) {
+                return StringId(id);
+            }
+            StringId(strings.insert(literal))
+        })
+    }
+
+    /**
+     * Return a copy of the interned literal.
+     */
+    pub fn get(&self) -> StringLiteral {
+        STRINGS.with(|strings| strings.borrow().items[self.0 as usize].clone())
+    }
+}
+
+impl fmt::Debug for StringId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.get())
+    }
+}
+
+/**
+ * Interner is the table behind both kinds of id: a vector of the distinct values, plus a map from
+ * each value back to its index.
+ */
+struct Interner<T: Eq + Hash> {
+    ids:    HashMap<T, u32, BuildHasherDefault<FxHasher>>,
+    items:  Vec<T>,
+}
+
+impl<T: Eq + Hash + Clone> Interner<T> {
+    fn new() -> Self {
+        Interner{
+            ids:    HashMap::default(),
+            items:  vec![],
+        }
+    }
+
+    fn insert(&mut self, item: T) -> u32 {
+        let id = self.items.len() as u32;
+        self.ids.insert(item.clone(), id);
+        self.items.push(item);
+        id
+    }
+}
+
+/**
+ * FxHasher is the simple multiply-and-rotate hash used by rustc. It is much faster than the
+ * default SipHash for the short keys we intern, and we don't need SipHash's protection against
+ * malicious input.
+ */
+#[derive(Default)]
+struct FxHasher {
+    hash: u64,
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+        for chunk in bytes.chunks(8) {
+            let mut word = 0u64;
+            for (i, &b) in chunk.iter().enumerate() {
+                word |= (b as u64) << (i * 8);
+            }
+            self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn symbols() {
+        let a = Symbol::intern("alpha");
+        let b = Symbol::intern("beta");
+        assert!(a != b);
+        assert_eq!(Symbol::intern("alpha"), a);
+        assert_eq!(a.as_str(), "alpha");
+        assert_eq!(b.as_str(), "beta");
+        assert_eq!(format!("{:?}", a), "\"alpha\"");
+    }
+
+    #[test]
+    fn strings() {
+        let a = StringId::intern(StringLiteral::from_str("a"));
+        assert_eq!(StringId::intern(StringLiteral::from_str("a")), a);
+        assert!(StringId::intern(StringLiteral::from_str("b")) != a);
+        assert_eq!(a.get(), StringLiteral::from_str("a"));
+    }
+}
@@ -1,3 +1,5 @@
+pub mod intern;
+
 
 /**
  * StepbackIterator is an Iterator that provides a `step_back()` in addition to the standard
@@ -79,6 +81,26 @@ impl<T: Clone> StepbackIterator<T> {
         }
     }
 
+    /**
+     * Return the index of the item that the next call to `next()` will return. Like the index used
+     * by `step_back()`, this may point past the end of the items.
+     */
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /**
+     * Return a reference to the item most recently returned by `next()`, or None if `next()` has
+     * not returned anything yet (or has run off the end).
+     */
+    pub fn current(&self) -> Option<&T> {
+        if self.pos == 0 {
+            None
+        } else {
+            self.items.get(self.pos - 1)
+        }
+    }
+
     /**
      * Returns true if the end of the item stream has been reached.
      */